@@ -0,0 +1,221 @@
+//! Files inside zip and tar archives, read in place without extracting the archive
+
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+use zip::{CompressionMethod, ZipArchive};
+
+use crate::backend::BackendError;
+use crate::thumbnail::ReadSeek;
+
+/// Separates the archive from the member in virtual paths like `photos.zip!/2024/a.jpg`
+pub(crate) const SEPARATOR: &str = "!/";
+/// Compressed members are decompressed into memory up to this length
+const MAX_MEMBER_LENGTH: u64 = 64 * 1024 * 1024;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const XZ_MAGIC: &[u8] = b"\xfd7zXZ\0";
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const BZIP2_MAGIC: &[u8] = b"BZh";
+
+/// A file or directory inside an archive
+pub(crate) enum Member {
+    Directory,
+    /// The content of the file, starting at position 0
+    File(Box<dyn ReadSeek>),
+}
+
+/// Opens `member` of the zip or tar archive at `archive`. Stored members are read in
+/// place. Compressed ones, and members of compressed tars, are decompressed into memory
+pub(crate) fn open_member(archive: &Path, member: &str) -> Result<Member, BackendError> {
+    if member.is_empty() {
+        return Ok(Member::Directory);
+    }
+    let mut file = BufReader::new(File::open(archive)?);
+    let magic = file.fill_buf()?;
+    let found = match magic {
+        [b'P', b'K', 3, 4, ..] | [b'P', b'K', 5, 6, ..] => zip_member(file, member)?,
+        _ if is_compressed(magic) => tar_member(decompressed(file)?, member)?,
+        _ => tar_member_in_place(file, member)?,
+    };
+    found.ok_or_else(|| format!("No {} in {}", member, archive.display()).into())
+}
+
+/// The part of a virtual path inside the archive, without leading `./` or `/` and
+/// without the trailing `/` of directories
+pub(crate) fn member_path(name: &str) -> &str {
+    name.trim_start_matches("./")
+        .trim_start_matches('/')
+        .trim_end_matches('/')
+}
+
+fn zip_member(file: BufReader<File>, member: &str) -> Result<Option<Member>, BackendError> {
+    let mut archive = ZipArchive::new(file)?;
+    let Some(index) = archive.index_for_name(member) else {
+        // Not every archive has entries for its directories
+        let prefix = format!("{member}/");
+        let is_directory = archive.file_names().any(|name| name.starts_with(&prefix));
+        return Ok(is_directory.then_some(Member::Directory));
+    };
+    let entry = archive.by_index(index)?;
+    if entry.is_dir() {
+        return Ok(Some(Member::Directory));
+    }
+    if entry.compression() == CompressionMethod::Stored {
+        let (start, length) = (entry.data_start(), entry.size());
+        drop(entry);
+        let section = Section::new(archive.into_inner(), start, length)?;
+        return Ok(Some(Member::File(Box::new(section))));
+    }
+    let length = entry.size();
+    let data = read_limited(entry, length)?;
+    Ok(Some(Member::File(Box::new(Cursor::new(data)))))
+}
+
+/// Finds `member` in an uncompressed tar, skipping over the other entries
+fn tar_member_in_place(
+    file: BufReader<File>,
+    member: &str,
+) -> Result<Option<Member>, BackendError> {
+    let mut archive = tar::Archive::new(file);
+    let mut section = None;
+    for entry in archive.entries_with_seek()? {
+        let entry = entry?;
+        match matches(&entry, member)? {
+            Some(true) => return Ok(Some(Member::Directory)),
+            Some(false) => {
+                section = Some((entry.raw_file_position(), entry.size()));
+                break;
+            }
+            None => {}
+        }
+    }
+    let Some((start, length)) = section else {
+        return Ok(None);
+    };
+    let section = Section::new(archive.into_inner(), start, length)?;
+    Ok(Some(Member::File(Box::new(section))))
+}
+
+/// Finds `member` in a tar read from a decompressor, which can't seek
+fn tar_member(reader: impl Read, member: &str) -> Result<Option<Member>, BackendError> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let entry = entry?;
+        match matches(&entry, member)? {
+            Some(true) => return Ok(Some(Member::Directory)),
+            Some(false) => {
+                let length = entry.size();
+                let data = read_limited(entry, length)?;
+                return Ok(Some(Member::File(Box::new(Cursor::new(data)))));
+            }
+            None => {}
+        }
+    }
+    Ok(None)
+}
+
+/// Whether `entry` is `member`: `Some(true)` for a directory, which is also what any
+/// entry inside it tells, and `Some(false)` for a file
+fn matches(entry: &tar::Entry<impl Read>, member: &str) -> Result<Option<bool>, BackendError> {
+    let name = entry.path()?;
+    let name = name.to_string_lossy();
+    let name = member_path(&name);
+    if name == member {
+        let entry_type = entry.header().entry_type();
+        return match entry_type {
+            tar::EntryType::Directory => Ok(Some(true)),
+            _ if entry_type.is_file() => Ok(Some(false)),
+            _ => Err(format!("{} is not a file or directory", member).into()),
+        };
+    }
+    let is_inside = name
+        .strip_prefix(member)
+        .is_some_and(|rest| rest.starts_with('/'));
+    Ok(is_inside.then_some(true))
+}
+
+/// Reads `length` bytes of a member, refusing members too large to hold in memory
+fn read_limited(reader: impl Read, length: u64) -> Result<Vec<u8>, BackendError> {
+    if length > MAX_MEMBER_LENGTH {
+        return Err(format!("Member is {} bytes, too large to decompress", length).into());
+    }
+    let mut data = Vec::with_capacity(length as usize);
+    reader.take(MAX_MEMBER_LENGTH).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn is_compressed(magic: &[u8]) -> bool {
+    [GZIP_MAGIC, XZ_MAGIC, ZSTD_MAGIC, BZIP2_MAGIC]
+        .iter()
+        .any(|compressed| magic.starts_with(compressed))
+}
+
+/// Wraps `reader` in the decompressor its first bytes call for. Data that isn't
+/// compressed is read as is
+pub(crate) fn decompressed<'a>(reader: impl Read + 'a) -> Result<Box<dyn Read + 'a>, BackendError> {
+    let mut reader = BufReader::new(reader);
+    let magic = reader.fill_buf()?;
+    if magic.starts_with(GZIP_MAGIC) {
+        Ok(Box::new(GzDecoder::new(reader)))
+    } else if magic.starts_with(XZ_MAGIC) {
+        Ok(Box::new(lzma_rust2::XzReader::new(reader, true)))
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        let decoder =
+            ruzstd::decoding::StreamingDecoder::new(reader).map_err(|error| error.to_string())?;
+        Ok(Box::new(decoder))
+    } else if magic.starts_with(BZIP2_MAGIC) {
+        Err("bzip2 compression is not supported".into())
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// `length` bytes of `inner` from `start`, read and sought in as if they were all of it
+struct Section<R> {
+    inner: R,
+    start: u64,
+    length: u64,
+    position: u64,
+}
+
+impl<R: Seek> Section<R> {
+    fn new(mut inner: R, start: u64, length: u64) -> io::Result<Self> {
+        inner.seek(SeekFrom::Start(start))?;
+        Ok(Self {
+            inner,
+            start,
+            length,
+            position: 0,
+        })
+    }
+}
+
+impl<R: Read> Read for Section<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.length.saturating_sub(self.position);
+        let length = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+        let read = self.inner.read(&mut buf[..length])?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Seek for Section<R> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.length.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Seek before the start"))?;
+        let absolute = self
+            .start
+            .checked_add(position)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Seek past the end"))?;
+        self.inner.seek(SeekFrom::Start(absolute))?;
+        self.position = position;
+        Ok(position)
+    }
+}
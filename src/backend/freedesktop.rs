@@ -0,0 +1,472 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::archive::{self, Member};
+use crate::custom_icon::{self, CustomIcon};
+use crate::decode;
+use crate::freedesktop;
+use crate::freedesktop::app_stream::AppStreamCatalog;
+use crate::freedesktop::desktop_entry::DesktopEntry;
+use crate::freedesktop::icon_theme::{current_theme_name, IconLookup};
+use crate::freedesktop::mime_db::MimeDatabase;
+use crate::freedesktop::mounts::MountTable;
+use crate::freedesktop::special_folders::SpecialFolders;
+use crate::freedesktop::thumbnail_cache::{ThumbnailCache, ThumbnailSize};
+use crate::image::Image;
+use crate::mime::{self, MimeBasis, MimeGuess};
+use crate::request::{FileKind, IconRequest, IconSource, IconState, BROKEN_LINK_EMBLEM};
+use crate::thumbnail::{self, ReadSeek, Thumbnailer};
+
+use super::{Backend, BackendError};
+
+/// Shown when nothing better matches, as file managers do
+const UNKNOWN_ICON: &str = "unknown";
+/// Shown for applications without a usable icon
+const APPLICATION_ICON: &str = "application-x-executable";
+/// Size given by `recommended_size`, the usual file manager icon size
+const RECOMMENDED_SIZE: u32 = 48;
+
+/// Loads icons from freedesktop icon themes, the way Linux desktops do
+pub struct FreedesktopBackend {
+    lookup: IconLookup,
+    mime_database: MimeDatabase,
+    theme: String,
+    application_dirs: Vec<PathBuf>,
+    app_stream_roots: Vec<PathBuf>,
+    /// Catalogs can be several megabytes, so they are only read once an application is asked for
+    app_stream: OnceLock<AppStreamCatalog>,
+    thumbnail_cache: Option<ThumbnailCache>,
+    thumbnailers: Vec<Box<dyn Thumbnailer>>,
+    special_folders: SpecialFolders,
+    mounts: Option<MountTable>,
+}
+
+impl FreedesktopBackend {
+    /// Uses the icon theme the desktop is set to and the system MIME database
+    pub fn new() -> Self {
+        Self {
+            lookup: IconLookup::from_env(),
+            mime_database: MimeDatabase::from_env(),
+            theme: current_theme_name(),
+            application_dirs: freedesktop::application_dirs(),
+            app_stream_roots: AppStreamCatalog::default_roots(),
+            app_stream: OnceLock::new(),
+            thumbnail_cache: ThumbnailCache::from_env(),
+            thumbnailers: thumbnail::default_thumbnailers(),
+            special_folders: SpecialFolders::from_env(),
+            mounts: MountTable::from_env(),
+        }
+    }
+
+    /// Looks icons up in `icon_dirs` and MIME types in `mime_dirs` only, using `theme`.
+    /// No applications, special folders or mounts are known until `with_application_dirs`,
+    /// `with_special_folders` and `with_mounts` are called
+    pub fn with_dirs(icon_dirs: Vec<PathBuf>, mime_dirs: &[PathBuf], theme: &str) -> Self {
+        Self {
+            lookup: IconLookup::new(icon_dirs),
+            mime_database: MimeDatabase::load(mime_dirs),
+            theme: theme.to_string(),
+            application_dirs: Vec::new(),
+            app_stream_roots: Vec::new(),
+            app_stream: OnceLock::new(),
+            thumbnail_cache: None,
+            thumbnailers: thumbnail::default_thumbnailers(),
+            special_folders: SpecialFolders::default(),
+            mounts: None,
+        }
+    }
+
+    /// Serves thumbnails from `thumbnail_cache`, or from none at all
+    pub fn with_thumbnail_cache(mut self, thumbnail_cache: Option<ThumbnailCache>) -> Self {
+        self.thumbnail_cache = thumbnail_cache;
+        self
+    }
+
+    /// Tries `thumbnailer` before the others for the types it supports
+    pub fn with_thumbnailer(mut self, thumbnailer: Box<dyn Thumbnailer>) -> Self {
+        self.thumbnailers.insert(0, thumbnailer);
+        self
+    }
+
+    /// Looks `.desktop` files up in `application_dirs` and AppStream catalogs
+    /// in `app_stream_roots`, which contain `xml`, `yaml` and `icons` directories
+    pub fn with_application_dirs(
+        mut self,
+        application_dirs: Vec<PathBuf>,
+        app_stream_roots: Vec<PathBuf>,
+    ) -> Self {
+        self.application_dirs = application_dirs;
+        self.app_stream_roots = app_stream_roots;
+        self.app_stream = OnceLock::new();
+        self
+    }
+
+    /// Gives the folders in `special_folders`, like Downloads or the trash, their own icons
+    pub fn with_special_folders(mut self, special_folders: SpecialFolders) -> Self {
+        self.special_folders = special_folders;
+        self
+    }
+
+    /// Gives mount points the icons of their drives from `mounts`, or no special icons
+    pub fn with_mounts(mut self, mounts: Option<MountTable>) -> Self {
+        self.mounts = mounts;
+        self
+    }
+
+    /// A preview of the file's content fitted to the request. Taken from the thumbnail cache
+    /// if the desktop already made one, otherwise made by a thumbnailer and cached
+    fn thumbnail(&self, request: &IconRequest) -> Option<Image> {
+        if request.type_icon_only {
+            return None;
+        }
+        if let IconSource::ArchiveMember {
+            archive, member, ..
+        } = &request.source
+        {
+            return self.member_thumbnail(request, archive, member);
+        }
+        let path = request.source.as_path()?;
+        if !path.is_file() {
+            return None;
+        }
+        let (width, height) = (request.pixel_width(), request.pixel_height());
+        let size = ThumbnailSize::for_pixels(width.max(height));
+        let cache = self.thumbnail_cache.as_ref();
+        if let Some(thumbnail) = cache.and_then(|cache| cache.lookup(path, size)) {
+            return Some(thumbnail.fit(width, height));
+        }
+
+        let mime_type = self.file_mime_type(path)?;
+        let thumbnailer = self.thumbnailer_for(&mime_type)?;
+        if cache.is_some_and(|cache| cache.has_failed(path)) {
+            return None;
+        }
+        match thumbnailer.thumbnail(path, size.pixels()) {
+            Ok(thumbnail) => {
+                let thumbnail = thumbnail.with_thumbnail(true);
+                if let Some(Err(error)) =
+                    cache.map(|cache| cache.store(path, &thumbnail, size, Some(&mime_type)))
+                {
+                    tracing::debug!("Cannot cache thumbnail of {}: {}", path.display(), error);
+                }
+                Some(thumbnail.fit(width, height))
+            }
+            Err(error) => {
+                tracing::debug!("Cannot thumbnail {}: {}", path.display(), error);
+                if let Some(cache) = cache {
+                    _ = cache.store_failure(path);
+                }
+                None
+            }
+        }
+    }
+
+    /// A preview of a file inside an archive, read in place. The thumbnail cache only
+    /// holds files on disk, so it isn't used. The type is told by the name of the member,
+    /// or by its content when the name doesn't tell
+    fn member_thumbnail(
+        &self,
+        request: &IconRequest,
+        archive: &Path,
+        member: &str,
+    ) -> Option<Image> {
+        let by_name = self.mime_type_by_name(&request.source);
+        if by_name
+            .as_ref()
+            .is_some_and(|mime_type| self.thumbnailer_for(mime_type).is_none())
+        {
+            return None;
+        }
+        let mut reader = match archive::open_member(archive, member) {
+            Ok(Member::File(reader)) => reader,
+            Ok(Member::Directory) => return None,
+            Err(error) => {
+                tracing::debug!("Cannot open {}: {}", request.source, error);
+                return None;
+            }
+        };
+        let mime_type = by_name.or_else(|| sniff(reader.as_mut()))?;
+        let thumbnailer = self.thumbnailer_for(&mime_type)?;
+        let (width, height) = (request.pixel_width(), request.pixel_height());
+        let size = ThumbnailSize::for_pixels(width.max(height));
+        let name = PathBuf::from(request.source.to_string());
+        match thumbnailer.thumbnail_reader(reader.as_mut(), &name, size.pixels()) {
+            Ok(thumbnail) => Some(thumbnail.with_thumbnail(true).fit(width, height)),
+            Err(error) => {
+                tracing::debug!("Cannot thumbnail {}: {}", request.source, error);
+                None
+            }
+        }
+    }
+
+    /// The icon the user set for a folder, unless the request ignores those
+    fn custom_folder_icon(&self, request: &IconRequest, theme: &str, size: u32) -> Option<Image> {
+        if request.ignore_custom_icon {
+            return None;
+        }
+        let path = request.source.as_path().filter(|path| path.is_dir())?;
+        let (width, height) = (request.pixel_width(), request.pixel_height());
+        let loaded = match custom_icon::find(path)? {
+            CustomIcon::Named(name) => {
+                let file = self
+                    .lookup
+                    .find(theme, &name, size, request.theme_scale())?;
+                decode::decode_icon_file(&file, width, height)
+            }
+            CustomIcon::File { path, index } => custom_icon::load_file(&path, index, width, height),
+        };
+        loaded
+            .map_err(|error| {
+                tracing::debug!("Cannot load the icon of {}: {}", path.display(), error)
+            })
+            .ok()
+    }
+
+    fn thumbnailer_for(&self, mime_type: &str) -> Option<&dyn Thumbnailer> {
+        self.thumbnailers
+            .iter()
+            .find(|thumbnailer| thumbnailer.supports(mime_type))
+            .map(Box::as_ref)
+    }
+
+    /// The type of the file at `path` as this backend sees it: the type of its extension in
+    /// the MIME database, unless its first bytes clearly are of another type. Aliases are
+    /// resolved to their canonical type on both sides.
+    ///
+    /// The first `mime::SNIFF_LENGTH` bytes of a regular file are read on every call, even
+    /// when its extension is known, as a misleading extension can only be caught that way
+    pub fn mime_guess(&self, path: &Path) -> MimeGuess {
+        let by_extension = self.mime_type_by_name(&IconSource::Path(path.to_path_buf()));
+        // Reading a FIFO or device could block or have side effects
+        let head = Some(path)
+            .filter(|path| path.is_file())
+            .and_then(|path| File::open(path).ok())
+            .and_then(|mut file| mime::read_head(&mut file).ok())
+            .unwrap_or_default();
+        MimeGuess::decide_with_aliases(by_extension, &head, |mime_type| {
+            self.mime_database.canonical(mime_type).to_string()
+        })
+    }
+
+    fn file_mime_type(&self, path: &Path) -> Option<String> {
+        let guess = self.mime_guess(path);
+        if guess.basis == MimeBasis::ContentOverExtension {
+            tracing::debug!(
+                "{} is named like {} but holds {}",
+                path.display(),
+                guess.by_extension.as_deref().unwrap_or_default(),
+                guess.mime_type
+            );
+        }
+        guess.known().map(str::to_string)
+    }
+
+    fn mime_type_by_name(&self, source: &IconSource) -> Option<String> {
+        source
+            .extension()
+            .and_then(|extension| self.mime_database.mime_type_for_extension(&extension))
+    }
+
+    /// Finds the icon file for `request`
+    fn resolve(&self, request: &IconRequest, theme: &str, size: u32) -> Option<PathBuf> {
+        if let IconSource::Application(id) = &request.source {
+            return self.application_icon(id, theme, size, request.theme_scale());
+        }
+
+        let mut names = self.icon_names(&request.source);
+        if let (IconState::Open, Some(name)) = (request.state, names.first()) {
+            // `folder-open`, `user-home-open` and so on
+            names.insert(0, format!("{}-open", name));
+        }
+        self.find(theme, &names, size, request.theme_scale())
+            .or_else(|| match request.source {
+                IconSource::IconName(_) => None,
+                // Only once every shortened type name has been tried
+                _ => self
+                    .lookup
+                    .find(theme, UNKNOWN_ICON, size, request.theme_scale()),
+            })
+    }
+
+    /// The `Icon` of the application's `.desktop` file, then its AppStream icons. Deleted
+    /// entries and those whose `TryExec` program is missing are skipped
+    fn application_icon(&self, id: &str, theme: &str, size: u32, scale: u32) -> Option<PathBuf> {
+        let entry = DesktopEntry::find_in(&self.application_dirs, id)
+            .filter(|entry| !entry.is_hidden() && entry.is_installed());
+        if let Some(icon) = entry.as_ref().and_then(DesktopEntry::icon) {
+            let path = Path::new(&icon);
+            let file = if path.is_absolute() {
+                path.is_file().then(|| path.to_path_buf())
+            } else {
+                // Old entries name theme icons with their extension
+                let name = icon
+                    .strip_suffix(".png")
+                    .or_else(|| icon.strip_suffix(".svg"))
+                    .or_else(|| icon.strip_suffix(".xpm"))
+                    .unwrap_or(&icon);
+                self.lookup.find(theme, name, size, scale)
+            };
+            if file.is_some() {
+                return file;
+            }
+        }
+
+        let catalog = self
+            .app_stream
+            .get_or_init(|| AppStreamCatalog::load(&self.app_stream_roots));
+        catalog
+            .stock_icon(id)
+            .and_then(|name| self.lookup.find(theme, name, size, scale))
+            .or_else(|| catalog.icon_file(id, size, scale))
+            .or_else(|| self.lookup.find(theme, APPLICATION_ICON, size, scale))
+    }
+
+    /// Icon names to try for `source`, most specific first
+    fn icon_names(&self, source: &IconSource) -> Vec<String> {
+        let mime_type = match source {
+            IconSource::IconName(name) => return vec![name.clone()],
+            IconSource::Path(path) => match FileKind::of(path) {
+                Some(FileKind::File) | None => self.file_mime_type(path),
+                Some(FileKind::Directory) => return self.directory_icon_names(path),
+                Some(kind) => return self.kind_icon_names(kind),
+            },
+            IconSource::FileKind(kind) => return self.kind_icon_names(*kind),
+            IconSource::MimeType(mime_type) => Some(mime_type.clone()),
+            IconSource::ArchiveMember {
+                archive, member, ..
+            } => match self.mime_type_by_name(source) {
+                Some(mime_type) => Some(mime_type),
+                None => match archive::open_member(archive, member) {
+                    Ok(Member::Directory) => return vec!["folder".to_string()],
+                    Ok(Member::File(mut reader)) => sniff(reader.as_mut()),
+                    Err(error) => {
+                        tracing::debug!("Cannot open {}: {}", source, error);
+                        None
+                    }
+                },
+            },
+            _ => self.mime_type_by_name(source),
+        };
+        mime_type
+            .map(|mime_type| self.mime_database.icon_names(&mime_type))
+            .unwrap_or_default()
+    }
+
+    /// The icon of a special folder, then the drive mounted there, then any folder's
+    fn directory_icon_names(&self, path: &Path) -> Vec<String> {
+        let special = self.special_folders.icon_name(path);
+        let mounted = self
+            .mounts
+            .iter()
+            .flat_map(|mounts| mounts.icon_names(path));
+        let mut names: Vec<String> = special
+            .into_iter()
+            .chain(mounted)
+            .map(str::to_string)
+            .collect();
+        names.extend(self.kind_icon_names(FileKind::Directory));
+        names
+    }
+
+    /// Directories are `folder` in current themes. The other kinds go by their MIME type
+    fn kind_icon_names(&self, kind: FileKind) -> Vec<String> {
+        let mut names = self.mime_database.icon_names(kind.mime_type());
+        if kind == FileKind::Directory {
+            names.insert(0, "folder".to_string());
+        }
+        names
+    }
+
+    fn find(&self, theme: &str, names: &[String], size: u32, scale: u32) -> Option<PathBuf> {
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        self.lookup.find_any(theme, &names, size, scale)
+    }
+}
+
+/// The MIME type of content by its first bytes, leaving `reader` at its start
+fn sniff(reader: &mut dyn ReadSeek) -> Option<String> {
+    mime::sniff_reader(reader).ok()?.map(str::to_string)
+}
+
+impl Default for FreedesktopBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backend for FreedesktopBackend {
+    fn load(&self, request: &IconRequest) -> Result<Image, BackendError> {
+        let theme = request.theme.as_deref().unwrap_or(&self.theme);
+        let theme = self.lookup.variant_for(theme, request.color_scheme);
+        let size = request.width.max(request.height);
+
+        let custom = || self.custom_folder_icon(request, &theme, size);
+        let mut image = match self.thumbnail(request).or_else(custom) {
+            Some(thumbnail) => thumbnail,
+            None => {
+                let file = self
+                    .resolve(request, &theme, size)
+                    .ok_or_else(|| format!("No icon in theme {} for {}", theme, request.source))?;
+                decode::decode_icon_file(&file, request.pixel_width(), request.pixel_height())?
+            }
+        };
+
+        // Emblems go in the bottom right corner at half the icon size
+        let emblem_width = image.width / 2;
+        let emblem_height = image.height / 2;
+        let broken_link = request
+            .source
+            .as_path()
+            .is_some_and(|path| FileKind::of(path) == Some(FileKind::Symlink));
+        let overlays = request.overlays.iter().map(String::as_str);
+        let overlays = overlays.chain(broken_link.then_some(BROKEN_LINK_EMBLEM));
+        // Icons of a pixel or two have no room for emblems
+        let overlays = overlays.filter(|_| emblem_width > 0 && emblem_height > 0);
+        for overlay in overlays {
+            let Some(file) = self
+                .lookup
+                .find(&theme, overlay, size / 2, request.theme_scale())
+            else {
+                tracing::debug!("No emblem {} in theme {}", overlay, theme);
+                continue;
+            };
+            let emblem = match decode::decode_icon_file(&file, emblem_width, emblem_height) {
+                Ok(emblem) => emblem,
+                Err(error) => {
+                    tracing::debug!("Cannot load emblem {}: {}", file.display(), error);
+                    continue;
+                }
+            };
+            image.overlay(
+                &emblem,
+                image.width - emblem_width,
+                image.height - emblem_height,
+            );
+        }
+        if request.state == IconState::Disabled {
+            image.dim();
+        }
+        Ok(image)
+    }
+
+    fn recommended_size(&self, _path: &Path) -> Result<(u32, u32), BackendError> {
+        Ok((RECOMMENDED_SIZE, RECOMMENDED_SIZE))
+    }
+
+    /// The trash gets another icon as it fills or empties while its path stays the same, so
+    /// it is keyed by the icon it has now
+    fn cache_key(&self, request: &IconRequest) -> IconRequest {
+        let trash = request
+            .source
+            .as_path()
+            .and_then(|path| self.special_folders.trash_icon(path));
+        match trash {
+            Some(icon) => IconRequest {
+                source: IconSource::IconName(icon.to_string()),
+                ..request.clone()
+            },
+            None => request.clone(),
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::image::Image;
+use crate::request::IconRequest;
+
+pub mod freedesktop;
+pub mod sandbox;
+#[cfg(windows)]
+mod win32;
+
+/// Error returned by a backend. Must be sendable so loads can run on a watchdog thread
+pub type BackendError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A source of file icons. Implementations must be safe to call from any thread
+pub trait Backend: Send + Sync {
+    /// Returns the icon described by `request`, rendered at its pixel size
+    fn load(&self, request: &IconRequest) -> Result<Image, BackendError>;
+
+    /// Returns the icon size the platform recommends for `path`
+    fn recommended_size(&self, path: &Path) -> Result<(u32, u32), BackendError>;
+
+    /// The part of `request` that decides what this backend returns. Caches are keyed on it,
+    /// so requests that differ only in fields the backend ignores share an entry
+    fn cache_key(&self, request: &IconRequest) -> IconRequest {
+        request.clone()
+    }
+}
+
+/// Returns the backend for the current platform
+pub fn default_backend() -> Arc<dyn Backend> {
+    #[cfg(windows)]
+    {
+        Arc::new(win32::ShellBackend)
+    }
+    #[cfg(not(windows))]
+    {
+        Arc::new(freedesktop::FreedesktopBackend::new())
+    }
+}
@@ -0,0 +1,698 @@
+use parking_lot::Mutex;
+use std::ffi::OsString;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use crate::image::Image;
+use crate::request::{
+    ArchiveFingerprint, ColorScheme, FileKind, IconRequest, IconSource, IconState, OutputFormat,
+};
+
+use super::{Backend, BackendError};
+
+/// Set in the environment of helper processes so the host binary knows to serve requests
+pub const HELPER_ENV: &str = "GETFILEICON_SANDBOX_HELPER";
+/// Memory cap of a helper process in bytes, applied by the helper to itself on startup
+const MEMORY_LIMIT_ENV: &str = "GETFILEICON_SANDBOX_MEMORY_LIMIT";
+
+/// Written by a helper before it serves requests. Anything the host binary printed to stdout
+/// before calling into the helper is skipped up to this marker
+const HANDSHAKE: &[u8; 8] = b"GFIHLP01";
+
+const DEFAULT_MEMORY_LIMIT: u64 = 512 * 1024 * 1024;
+/// How long a helper may take to start or to answer before it is killed
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Largest message either side accepts, so a corrupt length can't make us allocate gigabytes
+const MAX_MESSAGE_LEN: u32 = 256 * 1024 * 1024;
+
+const REQUEST_LOAD: u8 = 1;
+const REQUEST_RECOMMENDED_SIZE: u8 = 2;
+const RESPONSE_IMAGE: u8 = 1;
+const RESPONSE_SIZE: u8 = 2;
+const RESPONSE_ERROR: u8 = 3;
+
+/// Runs extraction in a pool of helper processes, so a crashing or runaway decoder
+/// can't take down the host process.
+///
+/// Helpers are started from the current executable by default, which must call
+/// `run_helper_if_requested` at the start of `main`. A helper that crashes is restarted
+/// on the next request it is given, and so is one that takes too long to answer
+pub struct SandboxBackend {
+    helpers: Vec<Mutex<Option<Helper>>>,
+    next_helper: AtomicUsize,
+    restarts: AtomicUsize,
+    program: PathBuf,
+    args: Vec<OsString>,
+    memory_limit: Option<u64>,
+    timeout: Option<Duration>,
+}
+
+impl SandboxBackend {
+    /// Creates a pool of `helpers` processes. Helpers are started lazily on first use
+    pub fn new(helpers: usize) -> Result<Self, BackendError> {
+        let program = std::env::current_exe()?;
+        Ok(Self {
+            helpers: (0..helpers.max(1)).map(|_| Mutex::new(None)).collect(),
+            next_helper: AtomicUsize::new(0),
+            restarts: AtomicUsize::new(0),
+            program,
+            args: Vec::new(),
+            memory_limit: Some(DEFAULT_MEMORY_LIMIT),
+            timeout: Some(DEFAULT_TIMEOUT),
+        })
+    }
+
+    /// Starts helpers from `program` with `args` instead of the current executable
+    pub fn with_program<I, S>(mut self, program: impl Into<PathBuf>, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.program = program.into();
+        self.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Caps the memory of each helper. `None` removes the cap
+    pub fn with_memory_limit(mut self, bytes: Option<u64>) -> Self {
+        self.memory_limit = bytes;
+        self
+    }
+
+    /// Kills a helper that takes longer than `timeout` to start or to answer, freeing its
+    /// slot in the pool. `None` waits forever
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns how many times a helper had to be restarted after crashing or hanging
+    pub fn restarts(&self) -> usize {
+        self.restarts.load(Ordering::Relaxed)
+    }
+
+    fn request(&self, request: &Request) -> Result<Response, BackendError> {
+        // Prefer an idle helper, otherwise wait on the next one in turn
+        let mut slot = self
+            .helpers
+            .iter()
+            .find_map(|helper| helper.try_lock())
+            .unwrap_or_else(|| {
+                let index = self.next_helper.fetch_add(1, Ordering::Relaxed) % self.helpers.len();
+                self.helpers[index].lock()
+            });
+
+        if slot.is_none() {
+            let helper = Helper::spawn(&self.program, &self.args, self.memory_limit, self.timeout)?;
+            *slot = Some(helper);
+        }
+        let helper = slot.as_mut().expect("helper was just spawned");
+
+        match helper.round_trip(request, self.timeout) {
+            Ok(response) => Ok(response),
+            Err(err) => {
+                // The helper crashed or broke the protocol. Kill it so the next request starts fresh
+                tracing::warn!("Sandbox helper failed on {}: {}", request.icon.source, err);
+                *slot = None;
+                self.restarts.fetch_add(1, Ordering::Relaxed);
+                Err(format!(
+                    "Sandbox helper crashed while loading {}: {}",
+                    request.icon.source, err
+                )
+                .into())
+            }
+        }
+    }
+}
+
+impl Backend for SandboxBackend {
+    fn load(&self, request: &IconRequest) -> Result<Image, BackendError> {
+        let request = Request {
+            kind: REQUEST_LOAD,
+            icon: request.clone(),
+        };
+        match self.request(&request)? {
+            Response::Image(image) => Ok(image),
+            Response::Error(message) => Err(message.into()),
+            Response::Size(..) => Err("Sandbox helper answered with the wrong response".into()),
+        }
+    }
+
+    fn recommended_size(&self, path: &Path) -> Result<(u32, u32), BackendError> {
+        let request = Request {
+            kind: REQUEST_RECOMMENDED_SIZE,
+            icon: IconRequest::new(path, 0, 0),
+        };
+        match self.request(&request)? {
+            Response::Size(width, height) => Ok((width, height)),
+            Response::Error(message) => Err(message.into()),
+            Response::Image(..) => Err("Sandbox helper answered with the wrong response".into()),
+        }
+    }
+}
+
+struct Helper {
+    /// Shared with the watchdog, which kills the child to unblock a read that takes too long
+    child: Arc<Mutex<Child>>,
+    stdin: BufWriter<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Helper {
+    fn spawn(
+        program: &Path,
+        args: &[OsString],
+        memory_limit: Option<u64>,
+        timeout: Option<Duration>,
+    ) -> Result<Self, BackendError> {
+        tracing::debug!("Starting sandbox helper {}", program.display());
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .env(HELPER_ENV, "1")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit());
+        match memory_limit {
+            Some(bytes) => command.env(MEMORY_LIMIT_ENV, bytes.to_string()),
+            None => command.env_remove(MEMORY_LIMIT_ENV),
+        };
+
+        let mut child = command.spawn()?;
+        let stdin = BufWriter::new(child.stdin.take().ok_or("Helper has no stdin")?);
+        let stdout = BufReader::new(child.stdout.take().ok_or("Helper has no stdout")?);
+        let mut helper = Self {
+            child: Arc::new(Mutex::new(child)),
+            stdin,
+            stdout,
+        };
+
+        // Dropping the helper on failure kills the child
+        let watchdog = helper.watchdog(timeout);
+        helper
+            .wait_for_handshake()
+            .map_err(|err| watchdog.explain(err))?;
+        Ok(helper)
+    }
+
+    fn wait_for_handshake(&mut self) -> Result<(), BackendError> {
+        let mut matched = 0;
+        let mut byte = [0u8; 1];
+        while matched < HANDSHAKE.len() {
+            self.stdout.read_exact(&mut byte)?;
+            if byte[0] == HANDSHAKE[matched] {
+                matched += 1;
+            } else {
+                matched = usize::from(byte[0] == HANDSHAKE[0]);
+            }
+        }
+        Ok(())
+    }
+
+    fn round_trip(
+        &mut self,
+        request: &Request,
+        timeout: Option<Duration>,
+    ) -> Result<Response, BackendError> {
+        let watchdog = self.watchdog(timeout);
+        let mut exchange = || {
+            request.write(&mut self.stdin)?;
+            self.stdin.flush()?;
+            Response::read(&mut self.stdout)
+        };
+        exchange().map_err(|err| watchdog.explain(err))
+    }
+
+    /// Kills the child unless the returned guard is dropped within `timeout`
+    fn watchdog(&self, timeout: Option<Duration>) -> Watchdog {
+        let (done, finished) = mpsc::channel();
+        let fired = Arc::new(AtomicBool::new(false));
+        if let Some(timeout) = timeout {
+            let child = Arc::clone(&self.child);
+            let thread_fired = Arc::clone(&fired);
+            let spawned = std::thread::Builder::new()
+                .name("getfileicon-sandbox-watchdog".to_string())
+                .spawn(move || {
+                    if let Err(mpsc::RecvTimeoutError::Timeout) = finished.recv_timeout(timeout) {
+                        thread_fired.store(true, Ordering::Release);
+                        _ = child.lock().kill();
+                    }
+                });
+            if let Err(err) = spawned {
+                tracing::warn!("Failed to start the sandbox watchdog: {}", err);
+            }
+        }
+        Watchdog { _done: done, fired }
+    }
+
+    fn kill(&mut self) {
+        let mut child = self.child.lock();
+        _ = child.kill();
+        _ = child.wait();
+    }
+}
+
+/// Stops the watchdog of a helper when dropped
+struct Watchdog {
+    _done: mpsc::Sender<()>,
+    fired: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+    /// Replaces the broken pipe error of a helper the watchdog killed
+    fn explain(&self, err: BackendError) -> BackendError {
+        if self.fired.load(Ordering::Acquire) {
+            "Sandbox helper did not answer in time".into()
+        } else {
+            err
+        }
+    }
+}
+
+impl Drop for Helper {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+/// Serves sandbox requests if this process was started as a helper, then exits.
+/// Call this at the very start of `main` when using `SandboxBackend`
+pub fn run_helper_if_requested() {
+    if std::env::var_os(HELPER_ENV).is_some() {
+        run_helper(super::default_backend());
+        std::process::exit(0);
+    }
+}
+
+/// Serves sandbox requests on stdin/stdout with `backend` until the parent closes the pipe
+pub fn run_helper(backend: Arc<dyn Backend>) {
+    if let Some(bytes) = std::env::var(MEMORY_LIMIT_ENV)
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+    {
+        if let Err(err) = apply_memory_limit(bytes) {
+            tracing::warn!("Failed to cap sandbox helper memory: {}", err);
+        }
+    }
+
+    let mut stdin = BufReader::new(std::io::stdin().lock());
+    let mut stdout = BufWriter::new(std::io::stdout().lock());
+    if stdout
+        .write_all(HANDSHAKE)
+        .and_then(|_| stdout.flush())
+        .is_err()
+    {
+        return;
+    }
+
+    // Any read error means the parent went away
+    while let Ok(request) = Request::read(&mut stdin) {
+        let response = match request.kind {
+            REQUEST_LOAD => match backend.load(&request.icon) {
+                Ok(image) => Response::Image(image),
+                Err(err) => Response::Error(err.to_string()),
+            },
+            REQUEST_RECOMMENDED_SIZE => match request.icon.source.as_path() {
+                Some(path) => match backend.recommended_size(path) {
+                    Ok((width, height)) => Response::Size(width, height),
+                    Err(err) => Response::Error(err.to_string()),
+                },
+                None => Response::Error("Recommended sizes need a path".to_string()),
+            },
+            kind => Response::Error(format!("Unknown sandbox request kind {}", kind)),
+        };
+        if response
+            .write(&mut stdout)
+            .and_then(|_| stdout.flush())
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+#[cfg(unix)]
+fn apply_memory_limit(bytes: u64) -> Result<(), BackendError> {
+    let limit = libc::rlimit {
+        rlim_cur: bytes as libc::rlim_t,
+        rlim_max: bytes as libc::rlim_t,
+    };
+    if unsafe { libc::setrlimit(libc::RLIMIT_AS, &limit) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn apply_memory_limit(bytes: u64) -> Result<(), BackendError> {
+    use windows::core::PCWSTR;
+    use windows::Win32::System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, JobObjectExtendedLimitInformation,
+        SetInformationJobObject, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+        JOB_OBJECT_LIMIT_PROCESS_MEMORY,
+    };
+    use windows::Win32::System::Threading::GetCurrentProcess;
+
+    unsafe {
+        // The job handle is intentionally leaked; the limit must last as long as the process
+        let job = CreateJobObjectW(None, PCWSTR::null())?;
+        let mut info = JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
+        info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_PROCESS_MEMORY;
+        info.ProcessMemoryLimit = bytes as usize;
+        SetInformationJobObject(
+            job,
+            JobObjectExtendedLimitInformation,
+            &info as *const _ as *const _,
+            std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+        )?;
+        AssignProcessToJobObject(job, GetCurrentProcess())?;
+    }
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn apply_memory_limit(_bytes: u64) -> Result<(), BackendError> {
+    Err("Memory limits are not supported on this platform".into())
+}
+
+/// Request frame, all integers little endian:
+/// kind (u8), width (u32), height (u32), scale percent (u32), color scheme (u8), state (u8),
+/// type icon only (u8), ignore custom icon (u8), format (u8), has theme (u8), theme,
+/// overlay count (u32), overlays, source kind (u8), source. Strings are a length (u32)
+/// followed by UTF-8 bytes.
+/// Paths are sent losslessly, see `path_to_bytes`. Archive members are the archive path,
+/// the member, then the fingerprint length (u64) and modification time (u64). File kinds
+/// are a u8
+struct Request {
+    kind: u8,
+    icon: IconRequest,
+}
+
+impl Request {
+    fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        let icon = &self.icon;
+        writer.write_all(&[self.kind])?;
+        writer.write_all(&icon.width.to_le_bytes())?;
+        writer.write_all(&icon.height.to_le_bytes())?;
+        writer.write_all(&icon.scale_percent.to_le_bytes())?;
+        writer.write_all(&[
+            icon.color_scheme as u8,
+            icon.state as u8,
+            u8::from(icon.type_icon_only),
+            u8::from(icon.ignore_custom_icon),
+            icon.format as u8,
+            u8::from(icon.theme.is_some()),
+        ])?;
+        write_bytes(writer, icon.theme.as_deref().unwrap_or_default().as_bytes())?;
+        writer.write_all(&(icon.overlays.len() as u32).to_le_bytes())?;
+        for overlay in &icon.overlays {
+            write_bytes(writer, overlay.as_bytes())?;
+        }
+        write_source(writer, &icon.source)
+    }
+
+    fn read(reader: &mut impl Read) -> Result<Self, BackendError> {
+        let kind = read_u8(reader)?;
+        let width = read_u32(reader)?;
+        let height = read_u32(reader)?;
+        let scale_percent = read_u32(reader)?;
+        let color_scheme = match read_u8(reader)? {
+            1 => ColorScheme::Light,
+            2 => ColorScheme::Dark,
+            _ => ColorScheme::System,
+        };
+        let state = match read_u8(reader)? {
+            1 => IconState::Open,
+            2 => IconState::Selected,
+            3 => IconState::Disabled,
+            _ => IconState::Normal,
+        };
+        let type_icon_only = read_u8(reader)? != 0;
+        let ignore_custom_icon = read_u8(reader)? != 0;
+        let format = match read_u8(reader)? {
+            1 => OutputFormat::Png,
+            2 => OutputFormat::Base64Png,
+            _ => OutputFormat::Rgba,
+        };
+        let has_theme = read_u8(reader)? != 0;
+        let theme = String::from_utf8(read_bytes(reader)?)?;
+        let overlay_count = read_u32(reader)?;
+        let mut overlays = Vec::with_capacity(overlay_count.min(64) as usize);
+        for _ in 0..overlay_count {
+            overlays.push(String::from_utf8(read_bytes(reader)?)?);
+        }
+        let mut icon = IconRequest::from_source(read_source(reader)?, width, height)
+            .with_color_scheme(color_scheme)
+            .with_state(state)
+            .type_icon_only(type_icon_only)
+            .ignore_custom_icon(ignore_custom_icon)
+            .with_format(format);
+        icon.scale_percent = scale_percent.max(100);
+        if has_theme {
+            icon = icon.with_theme(&theme);
+        }
+        for overlay in &overlays {
+            icon = icon.with_overlay(overlay);
+        }
+        Ok(Self { kind, icon })
+    }
+}
+
+const SOURCE_PATH: u8 = 0;
+const SOURCE_EXTENSION: u8 = 1;
+const SOURCE_MIME_TYPE: u8 = 2;
+const SOURCE_ICON_NAME: u8 = 3;
+const SOURCE_APPLICATION: u8 = 4;
+const SOURCE_ARCHIVE_MEMBER: u8 = 5;
+const SOURCE_FILE_KIND: u8 = 6;
+
+fn write_source(writer: &mut impl Write, source: &IconSource) -> std::io::Result<()> {
+    match source {
+        IconSource::Path(path) => {
+            writer.write_all(&[SOURCE_PATH])?;
+            write_bytes(writer, &path_to_bytes(path))
+        }
+        IconSource::Extension(extension) => {
+            writer.write_all(&[SOURCE_EXTENSION])?;
+            write_bytes(writer, extension.as_bytes())
+        }
+        IconSource::MimeType(mime_type) => {
+            writer.write_all(&[SOURCE_MIME_TYPE])?;
+            write_bytes(writer, mime_type.as_bytes())
+        }
+        IconSource::IconName(name) => {
+            writer.write_all(&[SOURCE_ICON_NAME])?;
+            write_bytes(writer, name.as_bytes())
+        }
+        IconSource::Application(id) => {
+            writer.write_all(&[SOURCE_APPLICATION])?;
+            write_bytes(writer, id.as_bytes())
+        }
+        IconSource::ArchiveMember {
+            archive,
+            member,
+            fingerprint,
+        } => {
+            writer.write_all(&[SOURCE_ARCHIVE_MEMBER])?;
+            write_bytes(writer, &path_to_bytes(archive))?;
+            write_bytes(writer, member.as_bytes())?;
+            writer.write_all(&fingerprint.length.to_le_bytes())?;
+            writer.write_all(&fingerprint.modified.to_le_bytes())
+        }
+        IconSource::FileKind(kind) => writer.write_all(&[SOURCE_FILE_KIND, *kind as u8]),
+    }
+}
+
+fn read_source(reader: &mut impl Read) -> Result<IconSource, BackendError> {
+    match read_u8(reader)? {
+        SOURCE_PATH => Ok(IconSource::Path(path_from_bytes(read_bytes(reader)?)?)),
+        SOURCE_EXTENSION => Ok(IconSource::Extension(String::from_utf8(read_bytes(
+            reader,
+        )?)?)),
+        SOURCE_MIME_TYPE => Ok(IconSource::MimeType(String::from_utf8(read_bytes(
+            reader,
+        )?)?)),
+        SOURCE_ICON_NAME => Ok(IconSource::IconName(String::from_utf8(read_bytes(
+            reader,
+        )?)?)),
+        SOURCE_APPLICATION => Ok(IconSource::Application(String::from_utf8(read_bytes(
+            reader,
+        )?)?)),
+        SOURCE_ARCHIVE_MEMBER => Ok(IconSource::ArchiveMember {
+            archive: path_from_bytes(read_bytes(reader)?)?,
+            member: String::from_utf8(read_bytes(reader)?)?,
+            fingerprint: ArchiveFingerprint {
+                length: read_u64(reader)?,
+                modified: read_u64(reader)?,
+            },
+        }),
+        SOURCE_FILE_KIND => Ok(IconSource::FileKind(match read_u8(reader)? {
+            0 => FileKind::File,
+            1 => FileKind::Directory,
+            2 => FileKind::Symlink,
+            3 => FileKind::Socket,
+            4 => FileKind::Fifo,
+            5 => FileKind::BlockDevice,
+            6 => FileKind::CharDevice,
+            kind => return Err(format!("Unknown sandbox file kind {}", kind).into()),
+        })),
+        kind => Err(format!("Unknown sandbox source kind {}", kind).into()),
+    }
+}
+
+/// Response frame: kind (u8) followed by
+/// - image: width (u32), height (u32), pixel length (u32), RGBA pixels, is thumbnail (u8),
+///   has origin (u8), then the origin path length (u32) and path if set
+/// - size: width (u32), height (u32)
+/// - error: message length (u32), UTF-8 message
+enum Response {
+    Image(Image),
+    Size(u32, u32),
+    Error(String),
+}
+
+impl Response {
+    fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        match self {
+            Self::Image(image) => {
+                writer.write_all(&[RESPONSE_IMAGE])?;
+                writer.write_all(&image.width.to_le_bytes())?;
+                writer.write_all(&image.height.to_le_bytes())?;
+                write_bytes(writer, image.as_rgba())?;
+                writer.write_all(&[image.is_thumbnail() as u8])?;
+                match image.origin() {
+                    Some(origin) => {
+                        writer.write_all(&[1])?;
+                        write_bytes(writer, &path_to_bytes(origin))
+                    }
+                    None => writer.write_all(&[0]),
+                }
+            }
+            Self::Size(width, height) => {
+                writer.write_all(&[RESPONSE_SIZE])?;
+                writer.write_all(&width.to_le_bytes())?;
+                writer.write_all(&height.to_le_bytes())
+            }
+            Self::Error(message) => {
+                writer.write_all(&[RESPONSE_ERROR])?;
+                write_bytes(writer, message.as_bytes())
+            }
+        }
+    }
+
+    fn read(reader: &mut impl Read) -> Result<Self, BackendError> {
+        match read_u8(reader)? {
+            RESPONSE_IMAGE => {
+                let width = read_u32(reader)?;
+                let height = read_u32(reader)?;
+                let pixels = read_bytes(reader)?;
+                // Images index their pixels by size, so a lying helper must not get one built
+                let expected = (width as u64)
+                    .checked_mul(height as u64)
+                    .and_then(|area| area.checked_mul(4));
+                if expected != Some(pixels.len() as u64) {
+                    return Err(format!(
+                        "Sandbox image of {}x{} has {} bytes of pixels",
+                        width,
+                        height,
+                        pixels.len()
+                    )
+                    .into());
+                }
+                let is_thumbnail = read_u8(reader)? != 0;
+                let mut image =
+                    Image::from_rgba(pixels, width, height).with_thumbnail(is_thumbnail);
+                if read_u8(reader)? != 0 {
+                    image = image.with_origin(path_from_bytes(read_bytes(reader)?)?);
+                }
+                Ok(Self::Image(image))
+            }
+            RESPONSE_SIZE => Ok(Self::Size(read_u32(reader)?, read_u32(reader)?)),
+            RESPONSE_ERROR => Ok(Self::Error(String::from_utf8(read_bytes(reader)?)?)),
+            kind => Err(format!("Unknown sandbox response kind {}", kind).into()),
+        }
+    }
+}
+
+/// Raw bytes of the path on Unix and UTF-16 little endian code units on Windows,
+/// so names that aren't valid Unicode make it to the helper unchanged
+#[cfg(unix)]
+fn path_to_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> Result<PathBuf, BackendError> {
+    use std::os::unix::ffi::OsStringExt;
+    Ok(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+#[cfg(windows)]
+fn path_to_bytes(path: &Path) -> Vec<u8> {
+    use std::os::windows::ffi::OsStrExt;
+    path.as_os_str()
+        .encode_wide()
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect()
+}
+
+#[cfg(windows)]
+fn path_from_bytes(bytes: Vec<u8>) -> Result<PathBuf, BackendError> {
+    use std::os::windows::ffi::OsStringExt;
+    if bytes.len() % 2 != 0 {
+        return Err("Sandbox path has an odd number of bytes".into());
+    }
+    let wide: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+    Ok(PathBuf::from(OsString::from_wide(&wide)))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn path_to_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(not(any(unix, windows)))]
+fn path_from_bytes(bytes: Vec<u8>) -> Result<PathBuf, BackendError> {
+    Ok(PathBuf::from(String::from_utf8(bytes)?))
+}
+
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> std::io::Result<()> {
+    writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(bytes)
+}
+
+fn read_u8(reader: &mut impl Read) -> std::io::Result<u8> {
+    let mut buffer = [0u8; 1];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer[0])
+}
+
+fn read_u32(reader: &mut impl Read) -> std::io::Result<u32> {
+    let mut buffer = [0u8; 4];
+    reader.read_exact(&mut buffer)?;
+    Ok(u32::from_le_bytes(buffer))
+}
+
+fn read_u64(reader: &mut impl Read) -> std::io::Result<u64> {
+    let mut buffer = [0u8; 8];
+    reader.read_exact(&mut buffer)?;
+    Ok(u64::from_le_bytes(buffer))
+}
+
+fn read_bytes(reader: &mut impl Read) -> Result<Vec<u8>, BackendError> {
+    let len = read_u32(reader)?;
+    if len > MAX_MESSAGE_LEN {
+        return Err(format!("Sandbox message of {} bytes is too large", len).into());
+    }
+    let mut buffer = vec![0u8; len as usize];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
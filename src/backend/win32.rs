@@ -0,0 +1,243 @@
+use std::path::Path;
+use std::sync::OnceLock;
+use windows::Win32::Graphics::Gdi::{DeleteObject, HBITMAP};
+use windows::Win32::UI::Shell::{self as win_shell, SHSTOCKICONID};
+
+use crate::custom_icon::{self, CustomIcon};
+use crate::image::Image;
+use crate::mime::{self, MimeBasis, MimeGuess};
+use crate::request::{ColorScheme, FileKind, IconRequest, IconSource, IconState};
+use crate::{renderer, shell};
+
+use super::freedesktop::FreedesktopBackend;
+use super::{Backend, BackendError};
+
+/// Freedesktop icon names of the stock icons of the shell. Named icons are looked up here
+/// when no freedesktop icon theme is installed, or it lacks them
+const STOCK_ICONS: &[(&str, SHSTOCKICONID)] = &[
+    ("folder", win_shell::SIID_FOLDER),
+    ("folder-open", win_shell::SIID_FOLDEROPEN),
+    ("folder-remote", win_shell::SIID_DRIVENET),
+    ("user-trash", win_shell::SIID_RECYCLER),
+    ("user-trash-full", win_shell::SIID_RECYCLERFULL),
+    ("user-desktop", win_shell::SIID_DESKTOPPC),
+    ("computer", win_shell::SIID_DESKTOPPC),
+    ("network-server", win_shell::SIID_SERVER),
+    ("network-workgroup", win_shell::SIID_MYNETWORK),
+    ("drive-harddisk", win_shell::SIID_DRIVEFIXED),
+    ("drive-removable-media", win_shell::SIID_DRIVEREMOVE),
+    ("drive-optical", win_shell::SIID_DRIVECD),
+    ("media-optical", win_shell::SIID_MEDIACDROM),
+    ("media-optical-dvd", win_shell::SIID_MEDIADVD),
+    ("media-flash", win_shell::SIID_MEDIACOMPACTFLASH),
+    ("printer", win_shell::SIID_PRINTER),
+    ("text-x-generic", win_shell::SIID_DOCNOASSOC),
+    ("application-x-executable", win_shell::SIID_APPLICATION),
+    ("audio-x-generic", win_shell::SIID_AUDIOFILES),
+    ("image-x-generic", win_shell::SIID_IMAGEFILES),
+    ("video-x-generic", win_shell::SIID_VIDEOFILES),
+    ("emblem-symbolic-link", win_shell::SIID_LINK),
+    ("emblem-shared", win_shell::SIID_SHARE),
+    ("emblem-readonly", win_shell::SIID_LOCK),
+    ("system-search", win_shell::SIID_FIND),
+    ("help-browser", win_shell::SIID_HELP),
+    ("dialog-information", win_shell::SIID_INFO),
+    ("dialog-warning", win_shell::SIID_WARNING),
+    ("dialog-error", win_shell::SIID_ERROR),
+];
+
+/// Loads icons and thumbnails through the Windows shell.
+///
+/// Named icons come from a freedesktop icon theme when one is installed, and otherwise from
+/// the stock icons of the shell, which cover common names like `folder`, `user-trash-full`
+/// and `drive-optical`. Kinds of entries Windows doesn't have, like FIFOs, get the closest
+/// stock icon. Applications are only found through installed `.desktop` files, which
+/// Windows programs don't have
+pub struct ShellBackend;
+
+impl Backend for ShellBackend {
+    fn load(&self, request: &IconRequest) -> Result<Image, BackendError> {
+        match &request.source {
+            IconSource::IconName(name) => {
+                return themes().load(request).or_else(|error| {
+                    tracing::debug!("No themed icon {}: {}", name, error);
+                    named_stock_icon(name, request.pixel_width(), request.pixel_height())
+                });
+            }
+            // The shell has no desktop entries. Use them if they are installed
+            IconSource::Application(_) => return themes().load(request),
+            _ => {}
+        }
+        if request.theme.is_some()
+            || request.color_scheme != ColorScheme::System
+            || request.state != IconState::Normal
+            || !request.overlays.is_empty()
+        {
+            tracing::debug!("The shell ignores theme, color scheme, state and overlays");
+        }
+
+        let width = request.pixel_width();
+        let height = request.pixel_height();
+        match &request.source {
+            IconSource::Path(path) if path.is_dir() && request.ignore_custom_icon => {
+                // The shell would show the icon of `desktop.ini`
+                let bitmap = shell::get_folder_icon(width.max(height))?;
+                list_icon(bitmap, width, height)
+            }
+            IconSource::Path(path) => {
+                // The shell only has icons for files and folders, not for links that lead
+                // nowhere or the special files of other systems
+                if let Some(kind) = FileKind::of(path)
+                    .filter(|kind| !matches!(kind, FileKind::File | FileKind::Directory))
+                {
+                    return stock_icon(kind_stock_icon(kind), width, height);
+                }
+                if let Some(image) = custom_folder_icon(path, request) {
+                    return Ok(image);
+                }
+                if let Some(extension) = content_extension(path) {
+                    return extension_icon(extension, width, height);
+                }
+                let bitmap =
+                    shell::get_custom_sized_icon(path, width, height, request.type_icon_only)?;
+                let (pixels, _, _) = take_bitmap_pixels(bitmap)?;
+                Ok(Image::from_rgba(bgra_to_rgba(&pixels), width, height))
+            }
+            // The shell can't look inside archives, so members get the icon of their type
+            IconSource::Extension(_)
+            | IconSource::MimeType(_)
+            | IconSource::ArchiveMember { .. } => {
+                let extension = request
+                    .source
+                    .extension()
+                    .ok_or_else(|| format!("No known extension for {}", request.source))?;
+                extension_icon(&extension, width, height)
+            }
+            IconSource::FileKind(kind) => stock_icon(kind_stock_icon(*kind), width, height),
+            IconSource::IconName(_) | IconSource::Application(_) => {
+                Err(format!("The shell has no icon for {}", request.source).into())
+            }
+        }
+    }
+
+    fn recommended_size(&self, path: &Path) -> Result<(u32, u32), BackendError> {
+        Ok(shell::get_recommended_icon_size(path)?)
+    }
+
+    /// Types, kinds and archive members always come from the shell, which ignores the theme,
+    /// color scheme, state and overlays
+    fn cache_key(&self, request: &IconRequest) -> IconRequest {
+        match request.source {
+            IconSource::Extension(_)
+            | IconSource::MimeType(_)
+            | IconSource::FileKind(_)
+            | IconSource::ArchiveMember { .. } => IconRequest {
+                theme: None,
+                color_scheme: ColorScheme::System,
+                state: IconState::Normal,
+                overlays: Vec::new(),
+                ..request.clone()
+            },
+            _ => request.clone(),
+        }
+    }
+}
+
+/// The icon the user set for a folder with `.directory` or an image inside it, which the
+/// shell doesn't know about, or with `desktop.ini`. When that can't be loaded, the shell
+/// may still manage, for example with icons of system libraries kept in `.mun` files
+fn custom_folder_icon(path: &Path, request: &IconRequest) -> Option<Image> {
+    if !path.is_dir() {
+        return None;
+    }
+    let loaded = match custom_icon::find(path)? {
+        CustomIcon::Named(name) => themes().load(&IconRequest {
+            source: IconSource::IconName(name),
+            ..request.clone()
+        }),
+        CustomIcon::File { path, index } => {
+            custom_icon::load_file(&path, index, request.pixel_width(), request.pixel_height())
+        }
+    };
+    loaded
+        .map_err(|error| tracing::debug!("Cannot load the icon of {}: {}", path.display(), error))
+        .ok()
+}
+
+/// The shell goes by extension. For files without one, or with one of a type their content
+/// clearly isn't, this is the usual extension of the type of their content
+fn content_extension(path: &Path) -> Option<&'static str> {
+    let guess = MimeGuess::of_file(path).ok()?;
+    match guess.basis {
+        MimeBasis::Content if path.extension().is_none() => {}
+        MimeBasis::ContentOverExtension => {}
+        _ => return None,
+    }
+    mime::extension_for_mime_type(&guess.mime_type)
+}
+
+/// Icon themes, for what the shell has no icons for
+fn themes() -> &'static FreedesktopBackend {
+    static THEMES: OnceLock<FreedesktopBackend> = OnceLock::new();
+    THEMES.get_or_init(FreedesktopBackend::new)
+}
+
+/// The stock icon closest to `kind`. Windows has no FIFOs, sockets or device files, so those
+/// are shown as files of no known type, or as a drive for block devices
+fn kind_stock_icon(kind: FileKind) -> SHSTOCKICONID {
+    match kind {
+        FileKind::Directory => win_shell::SIID_FOLDER,
+        FileKind::Symlink => win_shell::SIID_LINK,
+        FileKind::BlockDevice => win_shell::SIID_DRIVEFIXED,
+        FileKind::File | FileKind::Socket | FileKind::Fifo | FileKind::CharDevice => {
+            win_shell::SIID_DOCNOASSOC
+        }
+    }
+}
+
+/// The stock icon for `name`, or for the names it shortens to, like `user-trash` for
+/// `user-trash-full-symbolic`
+fn named_stock_icon(name: &str, width: u32, height: u32) -> Result<Image, BackendError> {
+    let id = std::iter::successors(Some(name), |name| name.rsplit_once('-').map(|(n, _)| n))
+        .find_map(|name| {
+            STOCK_ICONS
+                .iter()
+                .find(|(stock, _)| *stock == name)
+                .map(|(_, id)| *id)
+        })
+        .ok_or_else(|| format!("No icon named {} in the icon themes or the shell", name))?;
+    stock_icon(id, width, height)
+}
+
+fn stock_icon(id: SHSTOCKICONID, width: u32, height: u32) -> Result<Image, BackendError> {
+    let (bitmap, origin) = shell::get_stock_icon(id, width.max(height))?;
+    Ok(list_icon(bitmap, width, height)?.with_origin(origin))
+}
+
+fn extension_icon(extension: &str, width: u32, height: u32) -> Result<Image, BackendError> {
+    let bitmap = shell::get_extension_icon(extension, width.max(height))?;
+    list_icon(bitmap, width, height)
+}
+
+fn list_icon(bitmap: HBITMAP, width: u32, height: u32) -> Result<Image, BackendError> {
+    let (pixels, list_width, list_height) = take_bitmap_pixels(bitmap)?;
+    // The image lists only come in a few sizes
+    Ok(Image::from_rgba(bgra_to_rgba(&pixels), list_width, list_height).resize(width, height))
+}
+
+/// Reads the pixels of `bitmap` and frees it
+fn take_bitmap_pixels(bitmap: HBITMAP) -> Result<(Vec<u8>, u32, u32), BackendError> {
+    let result = renderer::extract_bitmap_pixels(bitmap);
+    unsafe {
+        _ = DeleteObject(bitmap);
+    }
+    Ok(result?)
+}
+
+fn bgra_to_rgba(pixels: &[u8]) -> Vec<u8> {
+    let mut rgba_pixels = pixels.to_vec();
+    for chunk in rgba_pixels.chunks_exact_mut(4) {
+        chunk.swap(0, 2); // Swap Red (chunk[2]) and Blue (chunk[0])
+    }
+    rgba_pixels
+}
@@ -1,13 +1,15 @@
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::RwLock as TokioRwLock;
 
 use crate::image::Image;
+use crate::loader::Loader;
 
 use super::utils::EvictionQueue;
 
-type CacheKey = String;
+type CacheKey = PathBuf;
 
 struct CacheEntry {
     image: Image,
@@ -22,10 +24,16 @@ pub struct EasyPngCache {
     cache: Arc<TokioRwLock<HashMap<CacheKey, CacheEntry>>>,
     eviction_queue: Arc<TokioRwLock<EvictionQueue<CacheKey>>>,
     max_size: usize,
+    loader: Loader,
 }
 
 impl EasyPngCache {
     pub fn new(max_size: usize) -> Self {
+        Self::with_loader(max_size, Loader::default())
+    }
+
+    /// Creates a cache that loads missing images through `loader`, e.g. one with a timeout
+    pub fn with_loader(max_size: usize, loader: Loader) -> Self {
         let cache = Arc::new(TokioRwLock::new(HashMap::new()));
         let eviction_queue = Arc::new(TokioRwLock::new(EvictionQueue::new(max_size)));
 
@@ -43,11 +51,13 @@ impl EasyPngCache {
             cache,
             eviction_queue,
             max_size,
+            loader,
         }
     }
 
-    pub async fn get(&self, path: &str) -> Option<Arc<Image>> {
-        let key = path.to_string();
+    pub async fn get(&self, path: impl AsRef<Path>) -> Option<Arc<Image>> {
+        let path = path.as_ref();
+        let key = path.to_path_buf();
 
         // First try a read lock
         let image = {
@@ -93,7 +103,7 @@ impl EasyPngCache {
 
         // Create new image
         tracing::debug!("Loading new image from file");
-        match Image::try_new_from_file_recommended(path) {
+        match self.loader.load_recommended(path) {
             Ok(image) => {
                 if cache.len() >= self.max_size {
                     tracing::debug!(
@@ -103,7 +113,7 @@ impl EasyPngCache {
                     // Use the eviction queue to determine what to remove
                     if let Some(old_key) = queue.get_oldest() {
                         cache.remove(old_key);
-                        tracing::debug!("Evicted entry for path: {}", old_key);
+                        tracing::debug!("Evicted entry for path: {}", old_key.display());
                     }
                 }
 
@@ -120,6 +130,12 @@ impl EasyPngCache {
                 tracing::debug!("Successfully added new image to cache");
                 Some(image)
             }
+            Err(e) if e.is_timeout() => {
+                // Hand out the generic icon without caching it, so a later request can retry
+                tracing::warn!("Using fallback icon: {}", e);
+                // The shell's own fallback size when it can't recommend one
+                Some(Arc::new(Image::fallback(16, 16)))
+            }
             Err(e) => {
                 tracing::error!("Failed to create image: {}", e);
                 None
@@ -0,0 +1,194 @@
+use parking_lot::Mutex;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::sync::{oneshot, Notify};
+
+use crate::image::Image;
+use crate::request::IconRequest;
+
+use super::png_cache::PngCache;
+
+/// Priority of a queued load. Higher values are loaded first
+pub type Priority = i32;
+
+struct QueuedLoad {
+    sequence: u64,
+    request: IconRequest,
+    priority: Arc<AtomicI32>,
+    sender: oneshot::Sender<Option<Arc<Image>>>,
+}
+
+impl QueuedLoad {
+    fn is_cancelled(&self) -> bool {
+        self.sender.is_closed()
+    }
+}
+
+struct QueueState {
+    pending: Mutex<Vec<QueuedLoad>>,
+    next_sequence: Mutex<u64>,
+    notify: Notify,
+    shutdown: AtomicBool,
+}
+
+impl QueueState {
+    /// Removes and returns the highest priority request that has not been cancelled.
+    /// Requests with equal priority are served in submission order
+    fn pop_next(&self) -> Option<QueuedLoad> {
+        let mut pending = self.pending.lock();
+        pending.retain(|load| !load.is_cancelled());
+
+        let index = pending
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| {
+                let a_priority = a.priority.load(Ordering::Relaxed);
+                let b_priority = b.priority.load(Ordering::Relaxed);
+                a_priority
+                    .cmp(&b_priority)
+                    .then_with(|| b.sequence.cmp(&a.sequence))
+            })
+            .map(|(index, _)| index)?;
+        Some(pending.swap_remove(index))
+    }
+}
+
+/// A prioritized queue of icon loads in front of a `PngCache`.
+///
+/// Each submitted load returns a `LoadHandle`. The priority of a load can be changed
+/// until a worker picks it up, and dropping the handle cancels the load if it has not started yet
+pub struct LoadQueue {
+    state: Arc<QueueState>,
+}
+
+impl LoadQueue {
+    /// Creates a queue that loads into `cache` using `workers` concurrent tasks
+    pub fn new(cache: Arc<PngCache>, workers: usize) -> Self {
+        let state = Arc::new(QueueState {
+            pending: Mutex::new(Vec::new()),
+            next_sequence: Mutex::new(0),
+            notify: Notify::new(),
+            shutdown: AtomicBool::new(false),
+        });
+
+        for _ in 0..workers {
+            let state = Arc::clone(&state);
+            let cache = Arc::clone(&cache);
+            tokio::spawn(async move {
+                Self::run_worker(state, cache).await;
+            });
+        }
+
+        Self { state }
+    }
+
+    /// Queues a load and returns a handle that resolves to the loaded image
+    pub fn submit(
+        &self,
+        path: impl AsRef<Path>,
+        width: u32,
+        height: u32,
+        priority: Priority,
+    ) -> LoadHandle {
+        self.submit_request(IconRequest::new(path, width, height), priority)
+    }
+
+    /// Queues a load for `request` and returns a handle that resolves to the loaded image
+    pub fn submit_request(&self, request: IconRequest, priority: Priority) -> LoadHandle {
+        let (sender, receiver) = oneshot::channel();
+        let priority = Arc::new(AtomicI32::new(priority));
+
+        let sequence = {
+            let mut next_sequence = self.state.next_sequence.lock();
+            let sequence = *next_sequence;
+            *next_sequence += 1;
+            sequence
+        };
+
+        self.state.pending.lock().push(QueuedLoad {
+            sequence,
+            request,
+            priority: Arc::clone(&priority),
+            sender,
+        });
+        self.state.notify.notify_one();
+
+        LoadHandle { priority, receiver }
+    }
+
+    /// Returns the number of loads that are waiting for a worker and have not been cancelled
+    pub fn pending(&self) -> usize {
+        self.state
+            .pending
+            .lock()
+            .iter()
+            .filter(|load| !load.is_cancelled())
+            .count()
+    }
+
+    async fn run_worker(state: Arc<QueueState>, cache: Arc<PngCache>) {
+        loop {
+            // Register interest before checking the queue so a submit in between is not missed
+            let notified = state.notify.notified();
+            if state.shutdown.load(Ordering::Acquire) {
+                return;
+            }
+
+            let Some(load) = state.pop_next() else {
+                notified.await;
+                continue;
+            };
+
+            tracing::debug!(
+                "Load queue starting {} at {}x{} with priority {}",
+                load.request.source,
+                load.request.width,
+                load.request.height,
+                load.priority.load(Ordering::Relaxed)
+            );
+            let image = cache.get_request(&load.request).await;
+            // The receiver may have been dropped while loading; the image is still cached
+            _ = load.sender.send(image);
+        }
+    }
+}
+
+impl Drop for LoadQueue {
+    fn drop(&mut self) {
+        self.state.shutdown.store(true, Ordering::Release);
+        self.state.notify.notify_waiters();
+    }
+}
+
+/// A pending load in a `LoadQueue`. Await it to get the image.
+///
+/// Dropping the handle cancels the load if a worker has not picked it up yet
+pub struct LoadHandle {
+    priority: Arc<AtomicI32>,
+    receiver: oneshot::Receiver<Option<Arc<Image>>>,
+}
+
+impl LoadHandle {
+    pub fn priority(&self) -> Priority {
+        self.priority.load(Ordering::Relaxed)
+    }
+
+    /// Raises or lowers the priority of the load. Has no effect once the load has started
+    pub fn set_priority(&self, priority: Priority) {
+        self.priority.store(priority, Ordering::Relaxed);
+    }
+}
+
+impl Future for LoadHandle {
+    type Output = Option<Arc<Image>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.receiver)
+            .poll(cx)
+            .map(|result| result.ok().flatten())
+    }
+}
@@ -1,3 +1,4 @@
 pub mod png_cache;
 mod utils;
-pub mod easy_png_cache;
\ No newline at end of file
+pub mod easy_png_cache;
+pub mod load_queue;
\ No newline at end of file
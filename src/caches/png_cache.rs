@@ -1,18 +1,16 @@
 use std::collections::HashMap;
+use std::path::Path;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::RwLock as TokioRwLock;
 
 use crate::image::Image;
+use crate::loader::Loader;
+use crate::request::IconRequest;
 
 use super::utils::EvictionQueue;
 
-#[derive(Hash, Eq, PartialEq, Clone)]
-struct CacheKey {
-    path: String,
-    width: u32,
-    height: u32,
-}
+type CacheKey = IconRequest;
 
 struct CacheEntry {
     image: Image,
@@ -25,10 +23,16 @@ pub struct PngCache {
     cache: Arc<TokioRwLock<HashMap<CacheKey, CacheEntry>>>,
     eviction_queue: Arc<TokioRwLock<EvictionQueue<CacheKey>>>,
     max_size: usize,
+    loader: Loader,
 }
 
 impl PngCache {
     pub fn new(max_size: usize) -> Self {
+        Self::with_loader(max_size, Loader::default())
+    }
+
+    /// Creates a cache that loads missing images through `loader`, e.g. one with a timeout
+    pub fn with_loader(max_size: usize, loader: Loader) -> Self {
         let cache = Arc::new(TokioRwLock::new(HashMap::new()));
         let eviction_queue = Arc::new(TokioRwLock::new(EvictionQueue::new(max_size)));
 
@@ -46,21 +50,29 @@ impl PngCache {
             cache,
             eviction_queue,
             max_size,
+            loader,
         }
     }
 
-    pub async fn get(&self, path: &str, width: u32, height: u32) -> Option<Arc<Image>> {
+    pub async fn get(
+        &self,
+        path: impl AsRef<Path>,
+        width: u32,
+        height: u32,
+    ) -> Option<Arc<Image>> {
+        self.get_request(&IconRequest::new(path, width, height)).await
+    }
+
+    /// Gets the icon for `request`. Requests that differ in any field the backend looks at
+    /// are cached separately
+    pub async fn get_request(&self, request: &IconRequest) -> Option<Arc<Image>> {
         tracing::debug!(
             "Cache get request for path: {}, size: {}x{}",
-            path,
-            width,
-            height
+            request.source,
+            request.width,
+            request.height
         );
-        let key = CacheKey {
-            path: path.to_string(),
-            width,
-            height,
-        };
+        let key = self.loader.cache_key(request);
 
         // First try a read lock
         let image = {
@@ -106,7 +118,7 @@ impl PngCache {
 
         // Create new image
         tracing::debug!("Loading new image from file");
-        match Image::try_new_from_file(path, width, height) {
+        match self.loader.load_request(request) {
             Ok(image) => {
                 if cache.len() >= self.max_size {
                     tracing::debug!(
@@ -116,7 +128,7 @@ impl PngCache {
                     // Use the eviction queue to determine what to remove
                     if let Some(old_key) = queue.get_oldest() {
                         cache.remove(old_key);
-                        tracing::debug!("Evicted entry for path: {}", old_key.path);
+                        tracing::debug!("Evicted entry for: {}", old_key.source);
                     }
                 }
 
@@ -133,6 +145,14 @@ impl PngCache {
                 tracing::debug!("Successfully added new image to cache");
                 Some(image)
             }
+            Err(e) if e.is_timeout() => {
+                // Hand out the generic icon without caching it, so a later request can retry
+                tracing::warn!("Using fallback icon: {}", e);
+                Some(Arc::new(Image::fallback(
+                    request.pixel_width(),
+                    request.pixel_height(),
+                )))
+            }
             Err(e) => {
                 tracing::error!("Failed to create image: {}", e);
                 None
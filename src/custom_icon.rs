@@ -0,0 +1,213 @@
+//! Icons users set for their folders: `Icon=` in KDE's `.directory`, `IconResource=` in
+//! Windows' `desktop.ini`, and hidden `.folder.png` style images inside the folder
+
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Component, Path, PathBuf, Prefix};
+
+use crate::backend::BackendError;
+use crate::freedesktop::keyfile::KeyFile;
+use crate::image::Image;
+use crate::mime;
+use crate::thumbnail::{self, executable, ico};
+
+/// Images that stand for the folder they are in, in order of preference
+const SIDECARS: &[&str] = &[".folder.png", ".folder.svg", ".folder.ico"];
+/// Settings and icon files larger than this are ignored
+const MAX_FILE_LENGTH: u64 = 16 * 1024 * 1024;
+/// Extensions of PE files whose resources hold icons
+const EXECUTABLE_EXTENSIONS: &[&str] = &["exe", "dll", "cpl", "ocx", "scr", "mun"];
+
+/// An icon set for a folder
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub(crate) enum CustomIcon {
+    /// A name from the icon theme, like `folder-red`
+    Named(String),
+    /// An image, icon or executable file. `index` picks the icon of an executable like the
+    /// `file,index` references of Windows: counted from 0, or a negated resource ID
+    File { path: PathBuf, index: i32 },
+}
+
+/// The icon set for `directory`, if any: from `.directory`, then `desktop.ini`, then an
+/// image inside it
+pub(crate) fn find(directory: &Path) -> Option<CustomIcon> {
+    kde_icon(directory)
+        .or_else(|| windows_icon(directory))
+        .or_else(|| {
+            SIDECARS
+                .iter()
+                .map(|name| directory.join(name))
+                .find(|path| path.is_file())
+                .map(|path| CustomIcon::File { path, index: 0 })
+        })
+}
+
+/// Decodes the icon file `path` at `width` x `height`. Of icon files and executables, the
+/// image that best fits is used. Settings can name any path, so only regular files on local
+/// drives are read: a FIFO would block, and a network share would be connected to
+pub(crate) fn load_file(
+    path: &Path,
+    index: i32,
+    width: u32,
+    height: u32,
+) -> Result<Image, BackendError> {
+    check_regular_file(path)?;
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default();
+    let size = width.max(height);
+    let image = if extension == "ico" {
+        ico::decode_best(&read_limited(path)?, size)?
+    } else if EXECUTABLE_EXTENSIONS.contains(&extension.as_str()) {
+        let icon = executable::read_icon(BufReader::new(File::open(path)?), index)?;
+        ico::decode_best(&icon, size)?
+    } else {
+        // Images are as untrusted as the settings naming them, so they get the limits of
+        // icons inside files rather than those of theme icons
+        thumbnail::decode_embedded_icon(read_limited(path)?, size, path)?
+    };
+    Ok(image.fit(width, height).with_origin(path))
+}
+
+/// `Icon=` in the `[Desktop Entry]` of `.directory`: a theme icon, or a file when it is
+/// a path, which may be relative to the folder. Theme icons have no extension, so a name
+/// like `cover.png` is a file in the folder when there is one
+fn kde_icon(directory: &Path) -> Option<CustomIcon> {
+    let data = read_limited(&directory.join(".directory")).ok()?;
+    let settings = KeyFile::parse(&String::from_utf8_lossy(&data));
+    let icon = settings.get("Desktop Entry", "Icon")?.trim();
+    let path = directory.join(icon);
+    if icon.is_empty() {
+        None
+    } else if icon.contains('/') || (is_image_name(icon) && path.is_file()) {
+        Some(CustomIcon::File { path, index: 0 })
+    } else {
+        Some(CustomIcon::Named(icon.to_string()))
+    }
+}
+
+/// Whether `name` has the extension of an image type
+fn is_image_name(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(|extension| mime::mime_type_for_extension(&extension.to_ascii_lowercase()))
+        .is_some_and(|mime_type| mime_type.starts_with("image/"))
+}
+
+/// `IconResource=file,index` in the `[.ShellClassInfo]` of `desktop.ini`, or the older
+/// `IconFile=` and `IconIndex=`. Keys are matched ignoring case, as Windows does
+fn windows_icon(directory: &Path) -> Option<CustomIcon> {
+    let data = ["desktop.ini", "Desktop.ini"]
+        .iter()
+        .find_map(|name| read_limited(&directory.join(name)).ok())?;
+    let text = decode_text(&data);
+    let (mut resource, mut file, mut index) = (None, None, 0);
+    let mut in_class_info = false;
+    for line in text.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_class_info = line.eq_ignore_ascii_case("[.ShellClassInfo]");
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=').filter(|_| in_class_info) else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim().to_ascii_lowercase().as_str() {
+            "iconresource" => resource = Some(value),
+            "iconfile" => file = Some(value),
+            "iconindex" => index = value.parse().unwrap_or(0),
+            _ => {}
+        }
+    }
+
+    let (file, index) = match resource {
+        // The index follows the last comma, as file names may contain commas too
+        Some(resource) => resource
+            .rsplit_once(',')
+            .and_then(|(file, index)| Some((file, index.trim().parse().ok()?)))
+            .unwrap_or((resource, 0)),
+        None => (file?, index),
+    };
+    let file = expand_variables(file.trim_matches('"'));
+    if file.is_empty() {
+        return None;
+    }
+    // Folders copied from Windows keep their backslashes
+    let file = if cfg!(windows) {
+        file
+    } else {
+        file.replace('\\', "/")
+    };
+    let path = directory.join(file);
+    Some(CustomIcon::File { path, index })
+}
+
+/// Expands variables like `%SystemRoot%`. Unknown ones are left as they are
+fn expand_variables(text: &str) -> String {
+    let mut expanded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('%') {
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('%') else {
+            break;
+        };
+        expanded.push_str(&rest[..start]);
+        match std::env::var(&after[..end]) {
+            Ok(value) if end > 0 => {
+                expanded.push_str(&value);
+                rest = &after[end + 1..];
+            }
+            _ => {
+                expanded.push('%');
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// `desktop.ini` is UTF-16 with a byte order mark when Windows writes non-ASCII names
+fn decode_text(data: &[u8]) -> String {
+    match data {
+        [0xff, 0xfe, rest @ ..] => {
+            let units: Vec<u16> = rest
+                .chunks_exact(2)
+                .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        _ => {
+            let data = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(data);
+            String::from_utf8_lossy(data).into_owned()
+        }
+    }
+}
+
+/// Fails unless `path` is a regular file on a local drive. Network shares and devices,
+/// like `\\server\share` or `\\.\pipe\name`, are refused before anything touches them
+fn check_regular_file(path: &Path) -> Result<(), BackendError> {
+    if let Some(Component::Prefix(prefix)) = path.components().next() {
+        if !matches!(prefix.kind(), Prefix::Disk(_) | Prefix::VerbatimDisk(_)) {
+            return Err(format!("{} is not on a local drive", path.display()).into());
+        }
+    }
+    if !path.metadata()?.is_file() {
+        return Err(format!("{} is not a regular file", path.display()).into());
+    }
+    Ok(())
+}
+
+fn read_limited(path: &Path) -> Result<Vec<u8>, BackendError> {
+    check_regular_file(path)?;
+    let file = File::open(path)?;
+    if file.metadata()?.len() > MAX_FILE_LENGTH {
+        return Err(format!("{} is too large", path.display()).into());
+    }
+    let mut data = Vec::new();
+    file.take(MAX_FILE_LENGTH).read_to_end(&mut data)?;
+    Ok(data)
+}
@@ -0,0 +1,58 @@
+use resvg::{tiny_skia, usvg};
+use std::path::Path;
+
+use crate::backend::BackendError;
+use crate::image::Image;
+
+/// Decodes an icon file into an image of exactly `width` x `height`.
+/// SVG files are rendered at that size, everything else is decoded and scaled
+pub fn decode_icon_file(path: &Path, width: u32, height: u32) -> Result<Image, BackendError> {
+    let data = std::fs::read(path)?;
+    let is_svg = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("svg"));
+    let image = if is_svg {
+        render_svg(&data, width, height)?
+    } else {
+        decode_raster(&data)?.resize(width, height)
+    };
+    Ok(image.with_origin(path))
+}
+
+/// Decodes PNG, JPEG and the other formats the `image` crate reads at their own size
+pub fn decode_raster(data: &[u8]) -> Result<Image, BackendError> {
+    let decoded = image::load_from_memory(data)?.to_rgba8();
+    let (width, height) = decoded.dimensions();
+    Ok(Image::from_rgba(decoded.into_raw(), width, height))
+}
+
+/// Renders an SVG centered in a `width` x `height` canvas, keeping its aspect ratio
+pub fn render_svg(data: &[u8], width: u32, height: u32) -> Result<Image, BackendError> {
+    let tree = usvg::Tree::from_data(data, &usvg::Options::default())?;
+    render_tree(&tree, width, height)
+}
+
+/// Renders a parsed SVG centered in a `width` x `height` canvas, keeping its aspect ratio
+pub fn render_tree(tree: &usvg::Tree, width: u32, height: u32) -> Result<Image, BackendError> {
+    let mut pixmap =
+        tiny_skia::Pixmap::new(width, height).ok_or("Cannot render an SVG at zero size")?;
+
+    let size = tree.size();
+    let scale = (width as f32 / size.width()).min(height as f32 / size.height());
+    let offset_x = (width as f32 - size.width() * scale) / 2.0;
+    let offset_y = (height as f32 - size.height() * scale) / 2.0;
+    let transform =
+        tiny_skia::Transform::from_scale(scale, scale).post_translate(offset_x, offset_y);
+    resvg::render(tree, transform, &mut pixmap.as_mut());
+
+    // tiny-skia works with premultiplied alpha
+    let pixels = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect();
+    Ok(Image::from_rgba(pixels, width, height))
+}
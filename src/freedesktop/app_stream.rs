@@ -0,0 +1,283 @@
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use super::desktop_entry::normalize_id;
+
+/// Size AppStream assumes for cached icons that don't state one
+const DEFAULT_CACHED_SIZE: u32 = 64;
+
+#[derive(Debug, Clone)]
+enum CatalogIcon {
+    /// An icon theme name
+    Stock(String),
+    /// A file shipped with the catalog or installed by the package
+    File {
+        path: PathBuf,
+        size: u32,
+        scale: u32,
+    },
+}
+
+/// Icons from the AppStream catalogs distributions ship for software centers.
+/// Covers applications whose `.desktop` file names an icon that isn't installed
+#[derive(Debug, Default)]
+pub struct AppStreamCatalog {
+    /// Component and desktop file IDs, without `.desktop`, to their icons
+    icons: HashMap<String, Vec<CatalogIcon>>,
+}
+
+impl AppStreamCatalog {
+    /// The `swcatalog` and legacy `app-info` directories catalogs are installed to
+    pub fn default_roots() -> Vec<PathBuf> {
+        let mut roots = Vec::new();
+        for name in ["swcatalog", "app-info"] {
+            roots.extend(super::data_dirs().into_iter().map(|dir| dir.join(name)));
+            roots.push(Path::new("/var/lib").join(name));
+            roots.push(Path::new("/var/cache").join(name));
+        }
+        roots
+    }
+
+    /// Loads the XML and YAML catalogs under each root. Earlier roots take precedence
+    pub fn load(roots: &[PathBuf]) -> Self {
+        let mut catalog = Self::default();
+        for root in roots {
+            for directory in ["xml", "xmls"] {
+                for file in catalog_files(&root.join(directory)) {
+                    match read_catalog(&file) {
+                        Ok(text) => catalog.read_xml(root, &text),
+                        Err(error) => tracing::debug!("Skipping {}: {}", file.display(), error),
+                    }
+                }
+            }
+            for file in catalog_files(&root.join("yaml")) {
+                match read_catalog(&file) {
+                    Ok(text) => catalog.read_yaml(root, &text),
+                    Err(error) => tracing::debug!("Skipping {}: {}", file.display(), error),
+                }
+            }
+        }
+        catalog
+    }
+
+    /// The icon theme name the catalog gives for `id`
+    pub fn stock_icon(&self, id: &str) -> Option<&str> {
+        self.icons
+            .get(&normalize_id(id))?
+            .iter()
+            .find_map(|icon| match icon {
+                CatalogIcon::Stock(name) => Some(name.as_str()),
+                CatalogIcon::File { .. } => None,
+            })
+    }
+
+    /// The icon file for `id` closest to `size` x `scale` pixels, preferring larger ones
+    pub fn icon_file(&self, id: &str, size: u32, scale: u32) -> Option<PathBuf> {
+        let wanted = size.saturating_mul(scale);
+        let files = self
+            .icons
+            .get(&normalize_id(id))?
+            .iter()
+            .filter_map(|icon| match icon {
+                CatalogIcon::File { path, size, scale } if path.is_file() => {
+                    Some((size.saturating_mul(*scale), path))
+                }
+                _ => None,
+            });
+        let (larger, smaller): (Vec<_>, Vec<_>) = files.partition(|(pixels, _)| *pixels >= wanted);
+        larger
+            .into_iter()
+            .min_by_key(|(pixels, _)| *pixels)
+            .or_else(|| smaller.into_iter().max_by_key(|(pixels, _)| *pixels))
+            .map(|(_, path)| path.clone())
+    }
+
+    fn add(&mut self, ids: &[String], icons: Vec<CatalogIcon>) {
+        if icons.is_empty() {
+            return;
+        }
+        for id in ids {
+            self.icons
+                .entry(normalize_id(id))
+                .or_insert_with(|| icons.clone());
+        }
+    }
+
+    /// `<components origin="..."><component><id/><launchable/><icon/></component></components>`
+    fn read_xml(&mut self, root: &Path, text: &str) {
+        let document = match roxmltree::Document::parse(text) {
+            Ok(document) => document,
+            Err(error) => {
+                tracing::debug!("Invalid AppStream XML in {}: {}", root.display(), error);
+                return;
+            }
+        };
+        let origin = document
+            .root_element()
+            .attribute("origin")
+            .unwrap_or_default();
+        for component in document
+            .root_element()
+            .children()
+            .filter(|node| node.has_tag_name("component"))
+        {
+            let mut ids = Vec::new();
+            let mut icons = Vec::new();
+            for child in component.children().filter(|node| node.is_element()) {
+                let text = child.text().unwrap_or_default().trim();
+                match child.tag_name().name() {
+                    "id" => ids.push(text.to_string()),
+                    "launchable" if child.attribute("type") == Some("desktop-id") => {
+                        ids.push(text.to_string())
+                    }
+                    "icon" => {
+                        let number =
+                            |name| child.attribute(name).and_then(|value| value.parse().ok());
+                        let icon = icon_entry(
+                            root,
+                            origin,
+                            child.attribute("type").unwrap_or_default(),
+                            text,
+                            number("width"),
+                            number("scale"),
+                        );
+                        icons.extend(icon);
+                    }
+                    _ => {}
+                }
+            }
+            self.add(&ids, icons);
+        }
+    }
+
+    /// DEP-11: a header document with `Origin`, then one document per component
+    fn read_yaml(&mut self, root: &Path, text: &str) {
+        let mut origin = String::new();
+        for document in serde_yaml::Deserializer::from_str(text) {
+            let Ok(document) = Value::deserialize(document) else {
+                continue;
+            };
+            if let Some(header_origin) = document.get("Origin").and_then(Value::as_str) {
+                origin = header_origin.to_string();
+                continue;
+            }
+            let mut ids: Vec<String> = document
+                .get("ID")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .into_iter()
+                .collect();
+            if let Some(desktop_ids) = document
+                .get("Launchable")
+                .and_then(|launchable| launchable.get("desktop-id"))
+                .and_then(Value::as_sequence)
+            {
+                ids.extend(
+                    desktop_ids
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(str::to_string),
+                );
+            }
+
+            let mut icons = Vec::new();
+            if let Some(icon) = document.get("Icon").and_then(Value::as_mapping) {
+                for (kind, entries) in icon {
+                    let kind = kind.as_str().unwrap_or_default();
+                    // Entries are a name, or a list of `{name, width, height, scale}` maps
+                    let entries = match entries.as_sequence() {
+                        Some(entries) => entries.iter().collect(),
+                        None => vec![entries],
+                    };
+                    for entry in entries {
+                        let number = |name| {
+                            entry
+                                .get(name)
+                                .and_then(Value::as_u64)
+                                .map(|value| value as u32)
+                        };
+                        let name = entry
+                            .as_str()
+                            .or_else(|| entry.get("name").and_then(Value::as_str))
+                            .unwrap_or_default();
+                        icons.extend(icon_entry(
+                            root,
+                            &origin,
+                            kind,
+                            name,
+                            number("width"),
+                            number("scale"),
+                        ));
+                    }
+                }
+            }
+            self.add(&ids, icons);
+        }
+    }
+}
+
+/// Builds an icon from its AppStream type. `remote` icons would need a download and are skipped
+fn icon_entry(
+    root: &Path,
+    origin: &str,
+    kind: &str,
+    name: &str,
+    width: Option<u32>,
+    scale: Option<u32>,
+) -> Option<CatalogIcon> {
+    if name.is_empty() {
+        return None;
+    }
+    let size = width.unwrap_or(DEFAULT_CACHED_SIZE);
+    let scale = scale.unwrap_or(1).max(1);
+    match kind {
+        "stock" => Some(CatalogIcon::Stock(name.to_string())),
+        "cached" => {
+            // `icons/<origin>/64x64/name.png`, or `64x64@2` for scaled icons
+            let size_dir = match scale {
+                1 => format!("{}x{}", size, size),
+                scale => format!("{}x{}@{}", size, size, scale),
+            };
+            Some(CatalogIcon::File {
+                path: root.join("icons").join(origin).join(size_dir).join(name),
+                size,
+                scale,
+            })
+        }
+        "local" => Some(CatalogIcon::File {
+            path: PathBuf::from(name),
+            size,
+            scale,
+        }),
+        _ => None,
+    }
+}
+
+/// Catalog files in `dir`, sorted so the result doesn't depend on the file system
+fn catalog_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    files.sort();
+    files
+}
+
+/// Reads a catalog, decompressing `.gz` files
+fn read_catalog(path: &Path) -> std::io::Result<String> {
+    let file = std::fs::File::open(path)?;
+    let mut text = String::new();
+    if path.extension().is_some_and(|extension| extension == "gz") {
+        GzDecoder::new(file).read_to_string(&mut text)?;
+    } else {
+        std::io::BufReader::new(file).read_to_string(&mut text)?;
+    }
+    Ok(text)
+}
@@ -0,0 +1,161 @@
+use std::path::{Path, PathBuf};
+
+use super::keyfile::KeyFile;
+
+const GROUP: &str = "Desktop Entry";
+
+/// An installed application, read from its `.desktop` file
+#[derive(Debug)]
+pub struct DesktopEntry {
+    id: String,
+    path: PathBuf,
+    file: KeyFile,
+}
+
+impl DesktopEntry {
+    /// Reads a `.desktop` file. The ID is the file name without `.desktop`
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let id = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Self::load_with_id(path, id)
+    }
+
+    fn load_with_id(path: &Path, id: String) -> std::io::Result<Self> {
+        Ok(Self {
+            id,
+            path: path.to_path_buf(),
+            file: KeyFile::load(path)?,
+        })
+    }
+
+    /// Finds the entry for a desktop file ID such as `org.gnome.Nautilus`
+    /// in the `applications` directory of every XDG data directory
+    pub fn find(id: &str) -> Option<Self> {
+        Self::find_in(&super::application_dirs(), id)
+    }
+
+    /// Like `find`, but only looks in `dirs`. Earlier directories take precedence
+    pub fn find_in(dirs: &[PathBuf], id: &str) -> Option<Self> {
+        let id = normalize_id(id);
+        dirs.iter().find_map(|dir| {
+            candidate_paths(&id)
+                .into_iter()
+                .map(|relative| dir.join(relative))
+                .find(|path| path.is_file())
+                .and_then(|path| Self::load_with_id(&path, id.clone()).ok())
+        })
+    }
+
+    /// The desktop file ID, without `.desktop`
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The application name in the current locale
+    pub fn name(&self) -> Option<String> {
+        match super::message_locale() {
+            Some(locale) => self.name_for_locale(&locale),
+            None => self.get("Name"),
+        }
+    }
+
+    /// The application name for a POSIX locale such as `de_DE.UTF-8`
+    pub fn name_for_locale(&self, locale: &str) -> Option<String> {
+        self.file.get_localized(GROUP, "Name", locale).map(unescape)
+    }
+
+    /// The `Icon` key: an absolute path or an icon theme name
+    pub fn icon(&self) -> Option<String> {
+        self.get("Icon")
+    }
+
+    /// The `TryExec` key: a program that must exist for the entry to be usable
+    pub fn try_exec(&self) -> Option<String> {
+        self.get("TryExec")
+    }
+
+    /// Whether the program from `TryExec` exists, as an absolute path or on `PATH`.
+    /// Entries without `TryExec` are always installed
+    pub fn is_installed(&self) -> bool {
+        let Some(program) = self.try_exec() else {
+            return true;
+        };
+        let program = Path::new(&program);
+        if program.is_absolute() {
+            return is_executable(program);
+        }
+        std::env::var_os("PATH").is_some_and(|path| {
+            std::env::split_paths(&path).any(|dir| is_executable(&dir.join(program)))
+        })
+    }
+
+    /// Whether the entry has `Hidden=true`, which means it was deleted. It hides entries of
+    /// the same ID in directories of lower precedence
+    pub fn is_hidden(&self) -> bool {
+        self.get("Hidden")
+            .is_some_and(|hidden| hidden.trim() == "true")
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        self.file.get(GROUP, key).map(unescape)
+    }
+}
+
+/// Strips `.desktop`, so IDs can be given either way
+pub(crate) fn normalize_id(id: &str) -> String {
+    id.strip_suffix(".desktop").unwrap_or(id).to_string()
+}
+
+/// Relative paths a desktop file ID may live at. Entries in subdirectories get the
+/// directory names joined with `-` as a prefix, so `kde4-dolphin` may be `kde4/dolphin.desktop`
+fn candidate_paths(id: &str) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(format!("{}.desktop", id))];
+    let mut prefix = PathBuf::new();
+    let mut rest = id;
+    while let Some((directory, remainder)) = rest.split_once('-') {
+        prefix.push(directory);
+        rest = remainder;
+        paths.push(prefix.join(format!("{}.desktop", rest)));
+    }
+    paths
+}
+
+/// Undoes the `\s`, `\n`, `\t`, `\r` and `\\` escapes of string values
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(char) = chars.next() {
+        if char != '\\' {
+            result.push(char);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => result.push(' '),
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
@@ -0,0 +1,357 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::keyfile::KeyFile;
+use crate::request::ColorScheme;
+
+/// Every theme falls back to this one, as required by the icon theme spec
+const FALLBACK_THEME: &str = "hicolor";
+/// Icon file formats we can decode, in order of preference
+const ICON_EXTENSIONS: [&str; 2] = ["png", "svg"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DirectoryKind {
+    Fixed,
+    Scalable,
+    Threshold,
+}
+
+#[derive(Debug)]
+struct ThemeDirectory {
+    path: String,
+    size: u32,
+    scale: u32,
+    min_size: u32,
+    max_size: u32,
+    threshold: u32,
+    kind: DirectoryKind,
+}
+
+impl ThemeDirectory {
+    fn parse(index: &KeyFile, path: &str) -> Option<Self> {
+        let size = index.get_u32(path, "Size")?;
+        let kind = match index.get(path, "Type") {
+            Some("Fixed") => DirectoryKind::Fixed,
+            Some("Scalable") => DirectoryKind::Scalable,
+            _ => DirectoryKind::Threshold,
+        };
+        Some(Self {
+            path: path.to_string(),
+            size,
+            scale: index.get_u32(path, "Scale").unwrap_or(1),
+            min_size: index.get_u32(path, "MinSize").unwrap_or(size),
+            max_size: index.get_u32(path, "MaxSize").unwrap_or(size),
+            threshold: index.get_u32(path, "Threshold").unwrap_or(2),
+            kind,
+        })
+    }
+
+    /// Infers a directory from its name the way `hicolor` names them (`48x48`,
+    /// `48x48@2`, `scalable`), for themes shipped without their `index.theme`
+    fn from_name(name: &str) -> Option<Self> {
+        if name == "scalable" {
+            return Some(Self {
+                path: name.to_string(),
+                size: 128,
+                scale: 1,
+                min_size: 1,
+                max_size: 256,
+                threshold: 2,
+                kind: DirectoryKind::Scalable,
+            });
+        }
+        let (dimensions, scale) = match name.split_once('@') {
+            Some((dimensions, scale)) => (dimensions, scale.parse().ok()?),
+            None => (name, 1),
+        };
+        let (width, height) = dimensions.split_once('x')?;
+        let size: u32 = width.parse().ok()?;
+        (height.parse() == Ok(size)).then(|| Self {
+            path: name.to_string(),
+            size,
+            scale,
+            min_size: size,
+            max_size: size,
+            threshold: 2,
+            kind: DirectoryKind::Threshold,
+        })
+    }
+
+    /// `DirectoryMatchesSize` from the icon theme spec
+    fn matches_size(&self, size: u32, scale: u32) -> bool {
+        if self.scale != scale {
+            return false;
+        }
+        match self.kind {
+            DirectoryKind::Fixed => self.size == size,
+            DirectoryKind::Scalable => self.min_size <= size && size <= self.max_size,
+            DirectoryKind::Threshold => {
+                self.size.saturating_sub(self.threshold) <= size
+                    && size <= self.size.saturating_add(self.threshold)
+            }
+        }
+    }
+
+    /// `DirectorySizeDistance` from the icon theme spec, in physical pixels
+    fn size_distance(&self, size: u32, scale: u32) -> u32 {
+        let wanted = size.saturating_mul(scale);
+        let (min, max) = match self.kind {
+            DirectoryKind::Fixed => (self.size, self.size),
+            DirectoryKind::Scalable => (self.min_size, self.max_size),
+            DirectoryKind::Threshold => (
+                self.size.saturating_sub(self.threshold),
+                self.size.saturating_add(self.threshold),
+            ),
+        };
+        let (min, max) = (
+            min.saturating_mul(self.scale),
+            max.saturating_mul(self.scale),
+        );
+        if wanted < min {
+            min - wanted
+        } else {
+            wanted.saturating_sub(max)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Theme {
+    /// Every `<base dir>/<theme name>` that exists, in base dir order
+    roots: Vec<PathBuf>,
+    inherits: Vec<String>,
+    directories: Vec<ThemeDirectory>,
+}
+
+impl Theme {
+    fn load(base_dirs: &[PathBuf], name: &str) -> Option<Self> {
+        let roots: Vec<PathBuf> = base_dirs
+            .iter()
+            .map(|base| base.join(name))
+            .filter(|root| root.is_dir())
+            .collect();
+        // The first index.theme found describes the whole theme
+        let index = roots
+            .iter()
+            .find_map(|root| KeyFile::load(&root.join("index.theme")).ok())?;
+
+        let mut paths = index.get_list("Icon Theme", "Directories");
+        paths.extend(index.get_list("Icon Theme", "ScaledDirectories"));
+        let directories = paths
+            .iter()
+            .filter_map(|path| ThemeDirectory::parse(&index, path))
+            .collect();
+
+        Some(Self {
+            roots,
+            inherits: index.get_list("Icon Theme", "Inherits"),
+            directories,
+        })
+    }
+
+    /// `LookupIcon` from the icon theme spec
+    fn lookup(&self, name: &str, size: u32, scale: u32) -> Option<PathBuf> {
+        for directory in &self.directories {
+            if !directory.matches_size(size, scale) {
+                continue;
+            }
+            if let Some(file) = self.find_file(directory, name) {
+                return Some(file);
+            }
+        }
+
+        self.directories
+            .iter()
+            .filter_map(|directory| {
+                let file = self.find_file(directory, name)?;
+                Some((directory.size_distance(size, scale), file))
+            })
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, file)| file)
+    }
+
+    fn find_file(&self, directory: &ThemeDirectory, name: &str) -> Option<PathBuf> {
+        self.roots.iter().find_map(|root| {
+            ICON_EXTENSIONS.iter().find_map(|extension| {
+                let file = root
+                    .join(&directory.path)
+                    .join(format!("{}.{}", name, extension));
+                file.is_file().then_some(file)
+            })
+        })
+    }
+}
+
+/// Resolves icon names to files following the freedesktop icon theme spec
+pub struct IconLookup {
+    base_dirs: Vec<PathBuf>,
+    themes: Mutex<HashMap<String, Option<Arc<Theme>>>>,
+}
+
+impl IconLookup {
+    pub fn new(base_dirs: Vec<PathBuf>) -> Self {
+        Self {
+            base_dirs,
+            themes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Uses the base directories from the spec: `~/.icons`, `$XDG_DATA_HOME/icons`,
+    /// `$XDG_DATA_DIRS/icons` and `/usr/share/pixmaps`
+    pub fn from_env() -> Self {
+        let mut base_dirs = Vec::new();
+        if let Some(home) = super::home_dir() {
+            base_dirs.push(home.join(".icons"));
+        }
+        base_dirs.extend(super::data_dirs().into_iter().map(|dir| dir.join("icons")));
+        base_dirs.push(PathBuf::from("/usr/share/pixmaps"));
+        Self::new(base_dirs)
+    }
+
+    /// Finds the file for `name`, trying the whole theme chain first and then
+    /// the same name with dash-separated parts stripped from the end
+    /// (`text-x-rust`, `text-x`, `text`)
+    pub fn find(&self, theme: &str, name: &str, size: u32, scale: u32) -> Option<PathBuf> {
+        self.find_any(theme, &[name], size, scale)
+    }
+
+    /// Like `find`, but tries several names. All exact names are tried before any is stripped
+    pub fn find_any(&self, theme: &str, names: &[&str], size: u32, scale: u32) -> Option<PathBuf> {
+        let mut candidates: Vec<&str> = names.to_vec();
+        for name in names {
+            let mut stripped = *name;
+            while let Some((rest, _)) = stripped.rsplit_once('-') {
+                stripped = rest;
+                if !candidates.contains(&stripped) {
+                    candidates.push(stripped);
+                }
+            }
+        }
+
+        let chain = self.theme_chain(theme);
+        for candidate in candidates {
+            if let Some(file) = chain
+                .iter()
+                .find_map(|theme| theme.lookup(candidate, size, scale))
+            {
+                tracing::debug!("Resolved icon {} to {}", candidate, file.display());
+                return Some(file);
+            }
+            if let Some(file) = self.find_unthemed(candidate) {
+                return Some(file);
+            }
+        }
+        None
+    }
+
+    /// Picks the theme to use for `color_scheme`, preferring `<theme>-Dark` style variants
+    pub fn variant_for(&self, theme: &str, color_scheme: ColorScheme) -> String {
+        let suffixes: &[&str] = match color_scheme {
+            ColorScheme::System => return theme.to_string(),
+            ColorScheme::Dark => &["-Dark", "-dark"],
+            ColorScheme::Light => &["-Light", "-light"],
+        };
+        suffixes
+            .iter()
+            .map(|suffix| format!("{}{}", theme, suffix))
+            .find(|variant| self.theme(variant).is_some())
+            .unwrap_or_else(|| theme.to_string())
+    }
+
+    /// The theme, then everything it inherits from depth first, then `hicolor`
+    fn theme_chain(&self, theme: &str) -> Vec<Arc<Theme>> {
+        let mut names: Vec<String> = Vec::new();
+        let mut pending = vec![theme.to_string()];
+        let mut chain = Vec::new();
+        while let Some(name) = pending.pop() {
+            if names.contains(&name) {
+                continue;
+            }
+            names.push(name.clone());
+            if let Some(theme) = self.theme(&name) {
+                pending.extend(theme.inherits.iter().rev().cloned());
+                chain.push(theme);
+            }
+        }
+        if !names.iter().any(|name| name == FALLBACK_THEME) {
+            if let Some(theme) = self.theme(FALLBACK_THEME) {
+                chain.push(theme);
+            }
+        }
+        chain
+    }
+
+    fn theme(&self, name: &str) -> Option<Arc<Theme>> {
+        let mut themes = self.themes.lock();
+        themes
+            .entry(name.to_string())
+            .or_insert_with(|| Theme::load(&self.base_dirs, name).map(Arc::new))
+            .clone()
+    }
+
+    /// Icons placed directly in a base directory, like `/usr/share/pixmaps/foo.png`
+    fn find_unthemed(&self, name: &str) -> Option<PathBuf> {
+        self.base_dirs.iter().find_map(|base| {
+            ICON_EXTENSIONS.iter().find_map(|extension| {
+                let file = base.join(format!("{}.{}", name, extension));
+                file.is_file().then_some(file)
+            })
+        })
+    }
+}
+
+/// Picks the file to show at `size` among `paths` of one icon, relative to a theme
+/// without an `index.theme` like the ones packages ship: `48x48/apps/name.png`,
+/// `scalable/apps/name.svg`. Sizes are matched as `LookupIcon` does
+pub fn best_unindexed<'a>(paths: &[&'a str], size: u32, scale: u32) -> Option<&'a str> {
+    let directories: Vec<(ThemeDirectory, &str)> = paths
+        .iter()
+        .filter_map(|path| {
+            let directory = ThemeDirectory::from_name(path.split('/').next()?)?;
+            Some((directory, *path))
+        })
+        .collect();
+    directories
+        .iter()
+        .find(|(directory, _)| directory.matches_size(size, scale))
+        .or_else(|| {
+            directories
+                .iter()
+                .min_by_key(|(directory, _)| directory.size_distance(size, scale))
+        })
+        .map(|(_, path)| *path)
+}
+
+/// Returns the icon theme the desktop is using, or `hicolor` if it can't be told
+pub fn current_theme_name() -> String {
+    let config = super::config_home();
+    let candidates = config.iter().flat_map(|config| {
+        [
+            (
+                config.join("gtk-4.0/settings.ini"),
+                "Settings",
+                "gtk-icon-theme-name",
+            ),
+            (
+                config.join("gtk-3.0/settings.ini"),
+                "Settings",
+                "gtk-icon-theme-name",
+            ),
+            (config.join("kdeglobals"), "Icons", "Theme"),
+        ]
+    });
+    for (path, group, key) in candidates {
+        if let Some(theme) = read_setting(&path, group, key) {
+            return theme;
+        }
+    }
+    FALLBACK_THEME.to_string()
+}
+
+fn read_setting(path: &Path, group: &str, key: &str) -> Option<String> {
+    let file = KeyFile::load(path).ok()?;
+    let value = file.get(group, key)?.trim_matches('"');
+    (!value.is_empty()).then(|| value.to_string())
+}
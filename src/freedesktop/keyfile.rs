@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+/// A parsed freedesktop key file, the INI-like format of `index.theme` and `.desktop` files
+#[derive(Debug, Default)]
+pub struct KeyFile {
+    groups: HashMap<String, HashMap<String, String>>,
+}
+
+impl KeyFile {
+    pub fn parse(text: &str) -> Self {
+        let mut groups: HashMap<String, HashMap<String, String>> = HashMap::new();
+        let mut current: Option<String> = None;
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line
+                .strip_prefix('[')
+                .and_then(|rest| rest.strip_suffix(']'))
+            {
+                groups.entry(name.to_string()).or_default();
+                current = Some(name.to_string());
+                continue;
+            }
+            let (Some(group), Some((key, value))) = (&current, line.split_once('=')) else {
+                continue;
+            };
+            // The first occurrence of a key wins, as in GLib
+            groups
+                .entry(group.clone())
+                .or_default()
+                .entry(key.trim().to_string())
+                .or_insert_with(|| value.trim().to_string());
+        }
+
+        Self { groups }
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        Ok(Self::parse(&std::fs::read_to_string(path)?))
+    }
+
+    pub fn get(&self, group: &str, key: &str) -> Option<&str> {
+        self.groups.get(group)?.get(key).map(String::as_str)
+    }
+
+    /// Returns the best `key[locale]` value for a POSIX locale like `de_DE.UTF-8@euro`,
+    /// following the matching order of the desktop entry spec, or the plain `key`
+    pub fn get_localized(&self, group: &str, key: &str, locale: &str) -> Option<&str> {
+        let locale = locale.split('.').next().unwrap_or_default();
+        let (locale, modifier) = match locale.split_once('@') {
+            Some((locale, modifier)) => (locale, Some(modifier)),
+            None => (locale, None),
+        };
+        let language = locale.split('_').next().unwrap_or_default();
+        let mut candidates = Vec::new();
+        if let Some(modifier) = modifier {
+            candidates.push(format!("{}@{}", locale, modifier));
+        }
+        candidates.push(locale.to_string());
+        if let Some(modifier) = modifier {
+            candidates.push(format!("{}@{}", language, modifier));
+        }
+        candidates.push(language.to_string());
+
+        candidates
+            .iter()
+            .filter(|candidate| !candidate.is_empty())
+            .find_map(|candidate| self.get(group, &format!("{}[{}]", key, candidate)))
+            .or_else(|| self.get(group, key))
+    }
+
+    /// Returns a list value. Accepts both `;` and `,` separators, since themes use either
+    pub fn get_list(&self, group: &str, key: &str) -> Vec<String> {
+        self.get(group, key)
+            .map(|value| {
+                value
+                    .split([';', ','])
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn get_u32(&self, group: &str, key: &str) -> Option<u32> {
+        self.get(group, key)?.parse().ok()
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::mime;
+
+/// The parts of the shared-mime-info database needed to pick icons
+#[derive(Debug, Default)]
+pub struct MimeDatabase {
+    /// Lowercase extension to MIME type, from `*.ext` globs
+    extensions: HashMap<String, (u32, String)>,
+    /// Icon names from the `icons` files
+    icons: HashMap<String, String>,
+    /// Icon names from the `generic-icons` files
+    generic_icons: HashMap<String, String>,
+    /// Canonical types of aliases, from the `aliases` files
+    aliases: HashMap<String, String>,
+}
+
+impl MimeDatabase {
+    /// Loads `mime/` from every XDG data directory
+    pub fn from_env() -> Self {
+        let dirs: Vec<PathBuf> = super::data_dirs()
+            .into_iter()
+            .map(|dir| dir.join("mime"))
+            .collect();
+        Self::load(&dirs)
+    }
+
+    /// Loads the database from `mime` directories in lookup order.
+    /// Earlier directories take precedence
+    pub fn load(dirs: &[PathBuf]) -> Self {
+        let mut database = Self::default();
+        for dir in dirs.iter().rev() {
+            database.read_globs(&dir.join("globs2"));
+            read_map(&dir.join("icons"), ':', &mut database.icons);
+            read_map(&dir.join("generic-icons"), ':', &mut database.generic_icons);
+            read_map(&dir.join("aliases"), ' ', &mut database.aliases);
+        }
+        database
+    }
+
+    /// `weight:mime/type:glob` lines. Only simple `*.ext` globs are used
+    fn read_globs(&mut self, path: &Path) {
+        let Ok(text) = std::fs::read_to_string(path) else {
+            return;
+        };
+        for line in text.lines().filter(|line| !line.starts_with('#')) {
+            let mut fields = line.split(':');
+            let (Some(weight), Some(mime_type), Some(glob)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            let Some(extension) = glob.strip_prefix("*.") else {
+                continue;
+            };
+            if extension.contains(['*', '?', '[']) {
+                continue;
+            }
+            let weight = weight.parse().unwrap_or(50);
+            let extension = extension.to_lowercase();
+            // Higher weights win. Later directories override earlier ones at equal weight
+            match self.extensions.get(&extension) {
+                Some((existing, _)) if *existing > weight => {}
+                _ => {
+                    self.extensions
+                        .insert(extension, (weight, mime_type.to_string()));
+                }
+            }
+        }
+    }
+
+    /// Returns the MIME type for a lowercase extension, falling back to the built-in table
+    pub fn mime_type_for_extension(&self, extension: &str) -> Option<String> {
+        self.extensions
+            .get(extension)
+            .map(|(_, mime_type)| mime_type.clone())
+            .or_else(|| mime::mime_type_for_extension(extension).map(str::to_string))
+    }
+
+    /// The type `mime_type` is an alias of, or `mime_type` itself
+    pub fn canonical<'a>(&'a self, mime_type: &'a str) -> &'a str {
+        self.aliases
+            .get(mime_type)
+            .map_or(mime_type, String::as_str)
+    }
+
+    /// Icon names to try for `mime_type`, most specific first
+    pub fn icon_names(&self, mime_type: &str) -> Vec<String> {
+        let mime_type = self.canonical(mime_type);
+        let mut names = Vec::new();
+        if let Some(icon) = self.icons.get(mime_type) {
+            names.push(icon.clone());
+        }
+        names.push(mime_type.replace('/', "-"));
+        match self.generic_icons.get(mime_type) {
+            Some(icon) => names.push(icon.clone()),
+            None => {
+                let media = mime_type.split('/').next().unwrap_or_default();
+                names.push(format!("{}-x-generic", media));
+            }
+        }
+        names
+    }
+}
+
+/// `mime/type:icon-name` or `alias/type canonical/type` lines
+fn read_map(path: &Path, separator: char, map: &mut HashMap<String, String>) {
+    let Ok(text) = std::fs::read_to_string(path) else {
+        return;
+    };
+    for (key, value) in text.lines().filter_map(|line| line.split_once(separator)) {
+        map.insert(key.to_string(), value.to_string());
+    }
+}
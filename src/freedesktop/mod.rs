@@ -0,0 +1,70 @@
+use std::path::PathBuf;
+
+pub mod app_stream;
+pub mod desktop_entry;
+pub mod icon_theme;
+pub mod keyfile;
+pub mod mime_db;
+pub mod mounts;
+pub mod special_folders;
+pub mod thumbnail_cache;
+
+pub fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+/// `$XDG_CONFIG_HOME`, defaulting to `~/.config`
+pub fn config_home() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME").or_else(|| Some(home_dir()?.join(".config")))
+}
+
+/// `$XDG_CACHE_HOME`, defaulting to `~/.cache`
+pub fn cache_home() -> Option<PathBuf> {
+    xdg_dir("XDG_CACHE_HOME").or_else(|| Some(home_dir()?.join(".cache")))
+}
+
+/// `$XDG_DATA_HOME`, defaulting to `~/.local/share`
+pub fn data_home() -> Option<PathBuf> {
+    xdg_dir("XDG_DATA_HOME").or_else(|| Some(home_dir()?.join(".local/share")))
+}
+
+/// `$XDG_DATA_HOME` followed by `$XDG_DATA_DIRS`, in lookup order
+pub fn data_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(data_home) = data_home() {
+        dirs.push(data_home);
+    }
+    match std::env::var_os("XDG_DATA_DIRS").filter(|value| !value.is_empty()) {
+        Some(value) => dirs.extend(std::env::split_paths(&value).filter(|dir| dir.is_absolute())),
+        None => dirs.extend([
+            PathBuf::from("/usr/local/share"),
+            PathBuf::from("/usr/share"),
+        ]),
+    }
+    dirs
+}
+
+/// `applications` in every XDG data directory, where `.desktop` files are installed
+pub fn application_dirs() -> Vec<PathBuf> {
+    data_dirs()
+        .into_iter()
+        .map(|dir| dir.join("applications"))
+        .collect()
+}
+
+/// The locale used for messages, from `LC_ALL`, `LC_MESSAGES` or `LANG`
+pub fn message_locale() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|variable| std::env::var(variable).ok())
+        .find(|value| !value.is_empty())
+}
+
+/// Reads an XDG directory variable. Relative paths are invalid per the spec and ignored
+fn xdg_dir(variable: &str) -> Option<PathBuf> {
+    std::env::var_os(variable)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+}
@@ -0,0 +1,222 @@
+//! Drive icons for mount points, by the kind of media mounted there
+
+use std::path::{Path, PathBuf};
+
+/// File systems reached over the network
+const NETWORK_FILE_SYSTEMS: &[&str] = &[
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb3",
+    "smbfs",
+    "ncpfs",
+    "afs",
+    "ceph",
+    "glusterfs",
+    "9p",
+    "davfs",
+    "fuse.sshfs",
+    "fuse.rclone",
+];
+/// File systems of CDs, DVDs and Blu-ray discs
+const OPTICAL_FILE_SYSTEMS: &[&str] = &["iso9660", "udf"];
+/// The SCSI peripheral type of CD and DVD drives in `device/type`
+const SCSI_TYPE_ROM: &str = "5";
+
+/// The mounts of the system, read from `mountinfo` and `sysfs` on each lookup so that
+/// media plugged in later are found
+#[derive(Debug, Clone)]
+pub struct MountTable {
+    mountinfo: PathBuf,
+    sys: PathBuf,
+    dev: PathBuf,
+    /// Whether only paths on another device than their parent are looked up, which spares
+    /// reading the table for the other directories
+    mount_points_only: bool,
+}
+
+/// A line of `mountinfo`
+struct Mount {
+    mount_point: PathBuf,
+    file_system: String,
+    source: String,
+}
+
+impl MountTable {
+    /// Reads the mount table from `mountinfo`, in the format of `/proc/self/mountinfo`, block
+    /// devices from `sys`, laid out like `/sys`, and resolves the links that name devices,
+    /// like `/dev/mapper/home`, in `dev`. Every directory is looked up in the table
+    pub fn new(
+        mountinfo: impl Into<PathBuf>,
+        sys: impl Into<PathBuf>,
+        dev: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            mountinfo: mountinfo.into(),
+            sys: sys.into(),
+            dev: dev.into(),
+            mount_points_only: false,
+        }
+    }
+
+    /// The mounts of this process. `None` where there is no `/proc/self/mountinfo`. Only
+    /// directories on another device than their parent are looked up, so bind mounts of a
+    /// directory don't get drive icons
+    pub fn from_env() -> Option<Self> {
+        let mountinfo = Path::new("/proc/self/mountinfo");
+        mountinfo.is_file().then(|| Self {
+            mount_points_only: true,
+            ..Self::new(mountinfo, "/sys", "/dev")
+        })
+    }
+
+    /// Icon names for `path` if something is mounted there, most specific first. Empty for
+    /// other directories and for virtual file systems like `tmpfs`
+    pub fn icon_names(&self, path: &Path) -> Vec<&'static str> {
+        if self.mount_points_only && !is_on_own_device(path) {
+            return Vec::new();
+        }
+        let Ok(text) = std::fs::read_to_string(&self.mountinfo) else {
+            return Vec::new();
+        };
+        // Later mounts hide earlier ones on the same mount point
+        let Some(mount) = text
+            .lines()
+            .rev()
+            .filter_map(Mount::parse)
+            .find(|mount| mount.mount_point == path)
+        else {
+            return Vec::new();
+        };
+        self.classify(&mount)
+    }
+
+    fn classify(&self, mount: &Mount) -> Vec<&'static str> {
+        if NETWORK_FILE_SYSTEMS.contains(&mount.file_system.as_str()) {
+            return vec!["folder-remote"];
+        }
+        let is_optical = OPTICAL_FILE_SYSTEMS.contains(&mount.file_system.as_str());
+        let Some(device) = mount.source.strip_prefix("/dev/") else {
+            return Vec::new();
+        };
+        // `/dev/mapper` and `/dev/disk/by-*` names link to the kernel's name
+        let device = self
+            .dev
+            .join(device)
+            .canonicalize()
+            .ok()
+            .and_then(|device| Some(device.file_name()?.to_str()?.to_string()))
+            .unwrap_or_else(|| device.rsplit('/').next().unwrap_or(device).to_string());
+        if device.starts_with("loop") {
+            // Disk images mounted as files
+            return if is_optical {
+                vec!["application-x-cd-image", "media-optical"]
+            } else {
+                vec!["application-x-raw-disk-image", "drive-harddisk"]
+            };
+        }
+        let Some(disk) = self.disk_of(&device) else {
+            return vec!["drive-harddisk"];
+        };
+        let block = self.sys.join("block").join(&disk);
+        let attribute = |name: &str| {
+            std::fs::read_to_string(block.join(name))
+                .map(|value| value.trim().to_string())
+                .unwrap_or_default()
+        };
+
+        if is_optical || disk.starts_with("sr") || attribute("device/type") == SCSI_TYPE_ROM {
+            return vec!["drive-optical"];
+        }
+        // The device path goes through the USB controller for USB drives
+        let is_usb = block
+            .canonicalize()
+            .is_ok_and(|device| device.to_string_lossy().contains("/usb"));
+        if is_usb {
+            return vec!["drive-removable-media-usb", "drive-removable-media"];
+        }
+        if disk.starts_with("mmcblk") {
+            return vec!["media-flash", "drive-removable-media"];
+        }
+        if attribute("removable") == "1" {
+            return vec!["drive-removable-media"];
+        }
+        match attribute("queue/rotational").as_str() {
+            "0" => vec!["drive-harddisk-solidstate", "drive-harddisk"],
+            _ => vec!["drive-harddisk"],
+        }
+    }
+
+    /// The disk in `sys/block` that `device` is, or is a partition of
+    fn disk_of(&self, device: &str) -> Option<String> {
+        let block = self.sys.join("block");
+        if block.join(device).is_dir() {
+            return Some(device.to_string());
+        }
+        block
+            .read_dir()
+            .ok()?
+            .filter_map(Result::ok)
+            .find(|disk| disk.path().join(device).is_dir())
+            .and_then(|disk| disk.file_name().into_string().ok())
+    }
+}
+
+/// Whether `path` is on another device than its parent, as mount points usually are. The
+/// root always is
+#[cfg(unix)]
+fn is_on_own_device(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    let Some(parent) = path.parent() else {
+        return true;
+    };
+    match (path.metadata(), parent.metadata()) {
+        (Ok(path), Ok(parent)) => path.dev() != parent.dev(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_on_own_device(_path: &Path) -> bool {
+    true
+}
+
+impl Mount {
+    /// `36 35 98:0 /mnt1 /mnt/parent rw,noatime master:1 - ext3 /dev/root rw`: the mount
+    /// point is the fifth field, the file system and source follow the `-` separator
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split(' ');
+        let mount_point = fields.nth(4)?;
+        let mut after_separator = fields.skip_while(|field| *field != "-").skip(1);
+        Some(Self {
+            mount_point: PathBuf::from(unescape(mount_point)),
+            file_system: after_separator.next()?.to_string(),
+            source: unescape(after_separator.next()?),
+        })
+    }
+}
+
+/// Undoes the octal escapes of spaces, tabs, newlines and backslashes, like `\040`
+fn unescape(field: &str) -> String {
+    let mut unescaped = Vec::with_capacity(field.len());
+    let mut bytes = field.as_bytes();
+    while let Some((&byte, rest)) = bytes.split_first() {
+        let escaped = rest
+            .get(..3)
+            .filter(|_| byte == b'\\')
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match escaped {
+            Some(escaped) => {
+                unescaped.push(escaped);
+                bytes = &rest[3..];
+            }
+            None => {
+                unescaped.push(byte);
+                bytes = rest;
+            }
+        }
+    }
+    String::from_utf8_lossy(&unescaped).into_owned()
+}
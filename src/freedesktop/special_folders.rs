@@ -0,0 +1,139 @@
+//! Folders with icons of their own: the XDG user directories, home, the trash and the root
+
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+/// Keys of `user-dirs.dirs` and the icons of their folders
+const USER_DIRS: &[(&str, &str)] = &[
+    ("XDG_DESKTOP_DIR", "user-desktop"),
+    ("XDG_DOWNLOAD_DIR", "folder-download"),
+    ("XDG_TEMPLATES_DIR", "folder-templates"),
+    ("XDG_PUBLICSHARE_DIR", "folder-publicshare"),
+    ("XDG_DOCUMENTS_DIR", "folder-documents"),
+    ("XDG_MUSIC_DIR", "folder-music"),
+    ("XDG_PICTURES_DIR", "folder-pictures"),
+    ("XDG_VIDEOS_DIR", "folder-videos"),
+];
+const HOME_ICON: &str = "user-home";
+const ROOT_ICON: &str = "drive-harddisk";
+const TRASH_ICON: &str = "user-trash";
+const FULL_TRASH_ICON: &str = "user-trash-full";
+
+/// The special folders of a user and their icons. Paths are compared with symbolic links
+/// resolved, so a linked home or a relative path still matches
+#[derive(Debug, Clone, Default)]
+pub struct SpecialFolders {
+    /// The first match wins, so the user directories come before home and the root
+    folders: Vec<(PathBuf, &'static str)>,
+    /// `Trash` in the data home. Its icon tells whether anything is in it
+    trash: Option<PathBuf>,
+}
+
+impl SpecialFolders {
+    /// The folders of the current user, from `user-dirs.dirs` in `$XDG_CONFIG_HOME`,
+    /// `$HOME` and `$XDG_DATA_HOME`
+    pub fn from_env() -> Self {
+        Self::load(
+            super::home_dir().as_deref(),
+            super::config_home().as_deref(),
+            super::data_home().as_deref(),
+        )
+    }
+
+    /// The folders listed in `user-dirs.dirs` in `config_home`, `home`, the trash in
+    /// `data_home` and the root
+    pub fn load(home: Option<&Path>, config_home: Option<&Path>, data_home: Option<&Path>) -> Self {
+        let home = home.map(|home| canonical(home).into_owned());
+        let mut folders = match (&home, config_home) {
+            (Some(home), Some(config_home)) => {
+                read_user_dirs(&config_home.join("user-dirs.dirs"), home)
+            }
+            _ => Vec::new(),
+        };
+        folders.extend(home.map(|home| (home, HOME_ICON)));
+        folders.push((PathBuf::from("/"), ROOT_ICON));
+        Self {
+            folders: folders
+                .into_iter()
+                .map(|(folder, icon)| (canonical(&folder).into_owned(), icon))
+                .collect(),
+            trash: data_home.map(|data_home| canonical(&data_home.join("Trash")).into_owned()),
+        }
+    }
+
+    /// The icon of `path` if it is a special folder. The trash can be named by its
+    /// `files` directory too
+    pub fn icon_name(&self, path: &Path) -> Option<&'static str> {
+        if let Some(icon) = self.trash_icon(path) {
+            return Some(icon);
+        }
+        let path = canonical(path);
+        self.folders
+            .iter()
+            .find(|(folder, _)| *folder == path)
+            .map(|(_, icon)| *icon)
+    }
+
+    /// The icon of `path` if it is the trash, which tells whether anything is in it. As
+    /// that changes while the path stays the same, it is read again on every call
+    pub fn trash_icon(&self, path: &Path) -> Option<&'static str> {
+        let trash = self.trash.as_deref()?;
+        // Only names that could be the trash are resolved, as this is asked of every path
+        let name = path.file_name()?;
+        if name != "files" && Some(name) != trash.file_name() {
+            return None;
+        }
+        let path = canonical(path);
+        let files = trash.join("files");
+        if path != trash && path != files {
+            return None;
+        }
+        let is_full = files
+            .read_dir()
+            .is_ok_and(|mut entries| entries.next().is_some());
+        Some(if is_full { FULL_TRASH_ICON } else { TRASH_ICON })
+    }
+}
+
+/// `path` with symbolic links resolved and made absolute, or as it is if it doesn't exist
+fn canonical(path: &Path) -> Cow<'_, Path> {
+    match path.canonicalize() {
+        Ok(path) => Cow::Owned(path),
+        Err(_) => Cow::Borrowed(path),
+    }
+}
+
+/// `XDG_DOWNLOAD_DIR="$HOME/Downloads"` lines. Paths are absolute or relative to `$HOME`,
+/// and a directory set to `$HOME` itself is disabled
+fn read_user_dirs(path: &Path, home: &Path) -> Vec<(PathBuf, &'static str)> {
+    let Ok(text) = std::fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    let mut folders = Vec::new();
+    for line in text.lines().map(str::trim) {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let Some((_, icon)) = USER_DIRS.iter().find(|(known, _)| *known == key.trim()) else {
+            continue;
+        };
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value)
+            .replace("\\\"", "\"")
+            .replace("\\\\", "\\");
+        let folder = match value.strip_prefix("$HOME") {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+                home.join(rest.trim_start_matches('/'))
+            }
+            _ if value.starts_with('/') => PathBuf::from(value),
+            _ => continue,
+        };
+        if folder != home {
+            folders.push((folder, *icon));
+        }
+    }
+    folders
+}
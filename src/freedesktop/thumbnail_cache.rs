@@ -0,0 +1,326 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::UNIX_EPOCH;
+
+use crate::image::Image;
+
+const URI_KEY: &str = "Thumb::URI";
+const MTIME_KEY: &str = "Thumb::MTime";
+const SIZE_KEY: &str = "Thumb::Size";
+const MIME_KEY: &str = "Thumb::Mimetype";
+const SOFTWARE_KEY: &str = "Software";
+const SOFTWARE: &str = concat!("getfileicon ", env!("CARGO_PKG_VERSION"));
+/// Subdirectory of `fail/` our failed attempts are recorded in
+const FAILURE_APP: &str = "getfileicon";
+
+/// Numbers the temporary files of this process, so threads never share one
+static TEMPORARY_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// The size directories of the Thumbnail Managing Standard
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ThumbnailSize {
+    /// 128 x 128
+    Normal,
+    /// 256 x 256
+    Large,
+    /// 512 x 512
+    XLarge,
+    /// 1024 x 1024
+    XXLarge,
+}
+
+impl ThumbnailSize {
+    const ALL: [ThumbnailSize; 4] = [Self::Normal, Self::Large, Self::XLarge, Self::XXLarge];
+
+    /// The smallest size that holds `pixels` without upscaling, or the largest one
+    pub fn for_pixels(pixels: u32) -> Self {
+        Self::ALL
+            .into_iter()
+            .find(|size| size.pixels() >= pixels)
+            .unwrap_or(Self::XXLarge)
+    }
+
+    /// Longest edge of thumbnails in this directory
+    pub fn pixels(self) -> u32 {
+        match self {
+            Self::Normal => 128,
+            Self::Large => 256,
+            Self::XLarge => 512,
+            Self::XXLarge => 1024,
+        }
+    }
+
+    fn directory(self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::Large => "large",
+            Self::XLarge => "x-large",
+            Self::XXLarge => "xx-large",
+        }
+    }
+}
+
+/// The thumbnail cache shared by GNOME, KDE, XFCE and other desktops in `~/.cache/thumbnails`
+#[derive(Debug, Clone)]
+pub struct ThumbnailCache {
+    root: PathBuf,
+}
+
+impl ThumbnailCache {
+    /// Uses `root` instead of `$XDG_CACHE_HOME/thumbnails`
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// The cache in `$XDG_CACHE_HOME/thumbnails`. `None` if there is no home directory
+    pub fn from_env() -> Option<Self> {
+        Some(Self::new(super::cache_home()?.join("thumbnails")))
+    }
+
+    /// Returns an up to date thumbnail of `path` of at least `size`, trying larger sizes
+    /// if that one is missing. Thumbnails of changed files are ignored
+    pub fn lookup(&self, path: &Path, size: ThumbnailSize) -> Option<Image> {
+        let source = SourceInfo::read(path)?;
+        let name = source.file_name();
+        ThumbnailSize::ALL
+            .into_iter()
+            .filter(|candidate| *candidate >= size)
+            .find_map(|candidate| {
+                let file = self.root.join(candidate.directory()).join(&name);
+                read_valid_thumbnail(&file, &source)
+            })
+    }
+
+    /// Whether an earlier attempt to thumbnail `path` failed and the file hasn't changed since
+    pub fn has_failed(&self, path: &Path) -> bool {
+        let Some(source) = SourceInfo::read(path) else {
+            return false;
+        };
+        let file = self.failure_dir().join(source.file_name());
+        read_valid_thumbnail(&file, &source).is_some()
+    }
+
+    /// Saves `image` as the thumbnail of `path`, scaled down to fit `size`.
+    /// Nothing is stored for files inside the cache itself
+    pub fn store(
+        &self,
+        path: &Path,
+        image: &Image,
+        size: ThumbnailSize,
+        mime_type: Option<&str>,
+    ) -> std::io::Result<()> {
+        let Some(source) = SourceInfo::read(path) else {
+            return Ok(());
+        };
+        if source.path.starts_with(&self.root) {
+            return Ok(());
+        }
+        let image = scale_down(image, size.pixels());
+        let file = self.root.join(size.directory()).join(source.file_name());
+        write_thumbnail(&file, &image, &source, mime_type)
+    }
+
+    /// Records that `path` couldn't be thumbnailed, so it isn't retried until it changes
+    pub fn store_failure(&self, path: &Path) -> std::io::Result<()> {
+        let Some(source) = SourceInfo::read(path) else {
+            return Ok(());
+        };
+        let file = self.failure_dir().join(source.file_name());
+        write_thumbnail(&file, &Image::from_rgba(vec![0; 4], 1, 1), &source, None)
+    }
+
+    fn failure_dir(&self) -> PathBuf {
+        self.root.join("fail").join(FAILURE_APP)
+    }
+}
+
+/// What a thumbnail records about the file it was made from
+struct SourceInfo {
+    path: PathBuf,
+    uri: String,
+    mtime: u64,
+    size: u64,
+}
+
+impl SourceInfo {
+    fn read(path: &Path) -> Option<Self> {
+        let path = std::fs::canonicalize(path).ok()?;
+        let metadata = path.metadata().ok()?;
+        let mtime = metadata
+            .modified()
+            .ok()?
+            .duration_since(UNIX_EPOCH)
+            .ok()?
+            .as_secs();
+        Some(Self {
+            uri: file_uri(&path)?,
+            path,
+            mtime,
+            size: metadata.len(),
+        })
+    }
+
+    /// Thumbnails are named after the MD5 of the URI in lowercase hex
+    fn file_name(&self) -> String {
+        format!("{:x}.png", md5::compute(self.uri.as_bytes()))
+    }
+}
+
+/// Reads a thumbnail if its metadata matches the source file
+fn read_valid_thumbnail(file: &Path, source: &SourceInfo) -> Option<Image> {
+    let decoder = png::Decoder::new(BufReader::new(File::open(file).ok()?));
+    let reader = decoder.read_info().ok()?;
+    let info = reader.info();
+    let text = |key: &str| {
+        info.uncompressed_latin1_text
+            .iter()
+            .find(|chunk| chunk.keyword == key)
+            .map(|chunk| chunk.text.clone())
+            .or_else(|| {
+                info.utf8_text
+                    .iter()
+                    .find(|chunk| chunk.keyword == key)
+                    .and_then(|chunk| chunk.get_text().ok())
+            })
+    };
+    if text(URI_KEY)? != source.uri || text(MTIME_KEY)?.parse::<u64>().ok()? != source.mtime {
+        return None;
+    }
+    // Optional, but when present a mismatch means the file changed within the same second
+    if text(SIZE_KEY).is_some_and(|size| size.parse::<u64>().ok() != Some(source.size)) {
+        return None;
+    }
+    drop(reader);
+
+    let image = image::open(file).ok()?.to_rgba8();
+    let (width, height) = image.dimensions();
+    Some(
+        Image::from_rgba(image.into_raw(), width, height)
+            .with_origin(file)
+            .with_thumbnail(true),
+    )
+}
+
+/// Writes to a temporary file and renames it, so readers never see a partial thumbnail.
+/// Thumbnails are private to the user, as the standard requires
+fn write_thumbnail(
+    file: &Path,
+    image: &Image,
+    source: &SourceInfo,
+    mime_type: Option<&str>,
+) -> std::io::Result<()> {
+    let directory = file.parent().unwrap_or(file);
+    create_private_dir(directory)?;
+    let temporary = directory.join(format!(
+        ".{}.{}.{}.tmp",
+        file.file_name().unwrap_or_default().to_string_lossy(),
+        std::process::id(),
+        TEMPORARY_FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = (|| -> Result<(), Box<dyn std::error::Error>> {
+        let mut encoder = png::Encoder::new(
+            BufWriter::new(create_private_file(&temporary)?),
+            image.width,
+            image.height,
+        );
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut chunks = vec![
+            (URI_KEY, source.uri.clone()),
+            (MTIME_KEY, source.mtime.to_string()),
+            (SIZE_KEY, source.size.to_string()),
+            (SOFTWARE_KEY, SOFTWARE.to_string()),
+        ];
+        if let Some(mime_type) = mime_type {
+            chunks.push((MIME_KEY, mime_type.to_string()));
+        }
+        for (key, value) in chunks {
+            encoder.add_text_chunk(key.to_string(), value)?;
+        }
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(image.as_rgba())?;
+        writer.finish()?;
+        std::fs::rename(&temporary, file)?;
+        Ok(())
+    })();
+    if result.is_err() {
+        _ = std::fs::remove_file(&temporary);
+    }
+    result.map_err(|error| std::io::Error::other(error.to_string()))
+}
+
+/// Scales `image` down so its longest edge is at most `pixels`, keeping its aspect ratio
+fn scale_down(image: &Image, pixels: u32) -> Image {
+    let longest = image.width.max(image.height);
+    if longest <= pixels {
+        return image.resize(image.width, image.height);
+    }
+    let width = (image.width as u64 * pixels as u64 / longest as u64).max(1) as u32;
+    let height = (image.height as u64 * pixels as u64 / longest as u64).max(1) as u32;
+    image.resize(width, height)
+}
+
+/// `file://` URI of an absolute path, escaped the way GLib's `g_filename_to_uri` does,
+/// since the thumbnail name must match the one other programs compute
+fn file_uri(path: &Path) -> Option<String> {
+    let bytes = path_bytes(path)?;
+    let mut uri = String::from("file://");
+    for byte in bytes {
+        if byte.is_ascii_alphanumeric() || b"!$&'()*+,-./:=@_~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    Some(uri)
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Option<Vec<u8>> {
+    use std::os::unix::ffi::OsStrExt;
+    Some(path.as_os_str().as_bytes().to_vec())
+}
+
+/// `file:///C:/dir/file`, with the path as UTF-8
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Option<Vec<u8>> {
+    let path = path.to_str()?;
+    let path = path
+        .strip_prefix(r"\\?\")
+        .unwrap_or(path)
+        .replace('\\', "/");
+    Some(format!("/{}", path).into_bytes())
+}
+
+#[cfg(unix)]
+fn create_private_dir(directory: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(directory)
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(directory: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(directory)
+}
+
+#[cfg(unix)]
+fn create_private_file(path: &Path) -> std::io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private_file(path: &Path) -> std::io::Result<File> {
+    File::create(path)
+}
@@ -1,9 +1,15 @@
 use base64::Engine;
 use image::{ImageBuffer, ImageEncoder, Rgba};
-use std::path::Path;
-use windows::Win32::Graphics::Gdi::DeleteObject;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 
-use crate::{renderer, shell};
+use crate::loader::Loader;
+use crate::mime::MimeGuess;
+use crate::request::{IconRequest, OutputFormat};
+use crate::thumbnail;
+
+/// The generic icon the shell hands out when a file has no icon of its own
+const DEFAULT_ICON_BASE64_PNG: &str = "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAABQAAAAUCAYAAACNiR0NAAABZElEQVR4Ae3AA6AkWZbG8f937o3IzKdyS2Oubdu2bdu2bdu2bWmMnpZKr54yMyLu+Xa3anqmhztr1a/yAJ/8CZ/wDg5v8kKEvUrVX/qSL/mSSzwvxAN80zd83dE7vvO7Lnghfu1XfinvvOP2X7vznrPv/pVf+ZXneE4EDxCllO3tbba3t9ne3mZ7e5vt7W22t7fZ3t5me3ubruvj7d7xnd/gIQ+66Xs/5mM+5iTPieDf4Nprr413eud3e5OHP+zBP/jJn/zJp3g2gn8TcfzECd76rd/uDU+f2Pm+T/qkTzrGFVT+lR728Ifzcz/z0xgD6Nrrrn/jsxcuvh3wnQCVf6XHvtiL89gXe3Hut7t7Uf/w+L+vXEHlPxaV/1hU/mNR+Y9F5T8Wlf9YVP5jUfmPReU/FpX/WFT+Y1F5gNVqNdz69Kf3/CvsH+xZSeMKKg9w9z13vvZ3fte39fwrRIb7yU/hCv4Rx8VNRaZSeusAAAAASUVORK5CYII=";
 
 #[derive(Debug, Clone)]
 pub struct Base64Png {
@@ -16,58 +22,232 @@ pub struct Image {
     pixels: Vec<u8>,
     pub width: u32,
     pub height: u32,
+    origin: Option<PathBuf>,
+    is_thumbnail: bool,
 }
 
 impl Image{
-    /// Try to get the icon image using the recommended aspect ratio provided by the system
-    pub fn try_new_from_file_recommended(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        match shell::get_recommended_icon_size(path) {
-            Ok((width, height)) => {
-                tracing::debug!("Got recommended size: {}x{}", width, height);
-                Self::try_new_from_file(path, width, height)
-            }
-            Err(err) => Err(err.into()),
+    /// Expects pixels in RGBA format
+    pub fn from_rgba(pixels: Vec<u8>, width: u32, height: u32) -> Self {
+        Self {
+            pixels,
+            width,
+            height,
+            origin: None,
+            is_thumbnail: false,
         }
     }
 
-    /// Expects pixels in RGBA format
+    /// Same metadata as this image, different pixels
+    fn with_pixels(&self, pixels: Vec<u8>, width: u32, height: u32) -> Self {
+        Self {
+            pixels,
+            width,
+            height,
+            origin: self.origin.clone(),
+            is_thumbnail: self.is_thumbnail,
+        }
+    }
+
+    /// Records the file the pixels were read from
+    pub fn with_origin(mut self, origin: impl Into<PathBuf>) -> Self {
+        self.origin = Some(origin.into());
+        self
+    }
+
+    /// Returns the file the pixels were read from, e.g. the theme icon a name resolved to.
+    /// `None` when the backend doesn't say, as with the Windows shell
+    pub fn origin(&self) -> Option<&Path> {
+        self.origin.as_deref()
+    }
+
+    /// Marks the image as a preview of the file's content rather than an icon
+    pub fn with_thumbnail(mut self, is_thumbnail: bool) -> Self {
+        self.is_thumbnail = is_thumbnail;
+        self
+    }
+
+    /// Whether the image shows the file's content, like a photo preview, rather than
+    /// an icon for its type. Always `false` from the Windows shell, which doesn't say
+    pub fn is_thumbnail(&self) -> bool {
+        self.is_thumbnail
+    }
+
+    /// Try to get the icon image using the recommended aspect ratio provided by the system
+    pub fn try_new_from_file_recommended(
+        path: impl AsRef<Path>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Loader::default().load_recommended(path)?)
+    }
+
     pub fn try_new_from_file(
-        path: &str,
+        path: impl AsRef<Path>,
         width: u32,
         height: u32,
     ) -> Result<Self, Box<dyn std::error::Error>> {
-        match shell::get_custom_sized_icon(path, width, height) {
-            Ok(bitmap) => match renderer::extract_bitmap_pixels(bitmap) {
-                Ok(pixels) => {
-                    let rgba_pixels = Self::bgra_to_rgba(&pixels.0);
-                    unsafe {
-                        _ = DeleteObject(bitmap);
-                    }
-                    Ok(Self {
-                        pixels: rgba_pixels,
-                        width,
-                        height,
-                    })
-                }
-                Err(err) => {
-                    unsafe {
-                        _ = DeleteObject(bitmap);
-                    }
-                    Err(err.into())
-                }
-            },
-            Err(err) => Err(err.into()),
+        Ok(Loader::default().load(path, width, height)?)
+    }
+
+    /// Loads the icon described by `request`. The image has the request's pixel size
+    pub fn try_new(request: &IconRequest) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Loader::default().load_request(request)?)
+    }
+
+    /// Returns the icon any file with `extension` would get, e.g. `psd` or `.psd`.
+    /// No such file has to exist
+    pub fn for_extension(extension: &str, size: u32) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::try_new(&IconRequest::for_extension(extension, size, size))
+    }
+
+    /// Returns the icon any file of `mime_type` would get, e.g. `image/png`
+    pub fn for_mime_type(mime_type: &str, size: u32) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::try_new(&IconRequest::for_mime_type(mime_type, size, size))
+    }
+
+    /// Returns the icon `name` from the active icon theme, e.g. `folder` or `user-trash-full`.
+    /// Falls back through the theme's parents and then to shorter names (`text-x-rust`,
+    /// `text-x`, `text`). `origin` tells which file was used. Windows has no icon theme
+    /// unless one is installed, so there only common names map to the shell's stock icons
+    pub fn from_icon_name(
+        name: &str,
+        size: u32,
+        scale: u32,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::try_new(&IconRequest::for_icon_name(name, size, size).with_scale(scale))
+    }
+
+    /// Returns the icon of an installed application by desktop file ID, such as
+    /// `org.gnome.Nautilus`, from its `.desktop` file or the AppStream catalog
+    pub fn for_application(id: &str, size: u32) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::try_new(&IconRequest::for_application(id, size, size))
+    }
+
+    /// Returns the icon of content that isn't a file on disk, like an upload, as if it were
+    /// a file named `name_hint`: a preview when a thumbnailer reads its type, otherwise the
+    /// icon of the type. The type is told by the name, or by the content when the name
+    /// doesn't tell or the content is clearly of another type. Nothing is written to disk
+    pub fn from_bytes(
+        bytes: &[u8],
+        name_hint: &str,
+        size: u32,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::from_reader(Cursor::new(bytes), name_hint, size)
+    }
+
+    /// Like `from_bytes`, for the content of `reader` from position 0. Only the parts
+    /// a thumbnailer needs are read
+    pub fn from_reader(
+        mut reader: impl Read + Seek,
+        name_hint: &str,
+        size: u32,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let name = Path::new(name_hint);
+        let guess = MimeGuess::of_reader(name, &mut reader)?;
+        let mime_type = guess.mime_type.as_str();
+
+        let thumbnailers = thumbnail::default_thumbnailers();
+        let readers = thumbnailers.iter().filter(|thumbnailer| thumbnailer.supports(mime_type));
+        for thumbnailer in readers {
+            reader.seek(SeekFrom::Start(0))?;
+            match thumbnailer.thumbnail_reader(&mut reader, name, size) {
+                Ok(preview) => return Ok(preview.fit(size, size)),
+                Err(error) => tracing::debug!("Cannot preview {}: {}", name_hint, error),
+            }
+        }
+        Self::for_mime_type(mime_type, size)
+    }
+
+    /// The generic file icon, scaled to the given size. Used when a real icon can't be loaded in time
+    pub fn fallback(width: u32, height: u32) -> Self {
+        let png = base64::engine::general_purpose::STANDARD
+            .decode(DEFAULT_ICON_BASE64_PNG.trim_start_matches("data:image/png;base64,"))
+            .expect("default icon should be valid base64");
+        let icon = image::load_from_memory(&png)
+            .expect("default icon should be a valid PNG")
+            .to_rgba8();
+        let (icon_width, icon_height) = icon.dimensions();
+        Self::from_rgba(icon.into_raw(), icon_width, icon_height).resize(width, height)
+    }
+
+    /// Returns a copy scaled to exactly `width` x `height`
+    pub fn resize(&self, width: u32, height: u32) -> Self {
+        if (width, height) == (self.width, self.height) {
+            return self.clone();
+        }
+        let Some(buffer) =
+            ImageBuffer::<Rgba<u8>, _>::from_raw(self.width, self.height, self.pixels.clone())
+        else {
+            return self.with_pixels(transparent_pixels(width, height), width, height);
+        };
+        let resized = image::imageops::resize(
+            &buffer,
+            width,
+            height,
+            image::imageops::FilterType::CatmullRom,
+        );
+        self.with_pixels(resized.into_raw(), width, height)
+    }
+
+    /// Returns a copy scaled to fit inside `width` x `height` with its aspect ratio kept,
+    /// centered on a transparent canvas of exactly that size
+    pub fn fit(&self, width: u32, height: u32) -> Self {
+        if width == 0 || height == 0 {
+            return self.with_pixels(Vec::new(), width, height);
+        }
+        let scale = (width as f64 / self.width.max(1) as f64)
+            .min(height as f64 / self.height.max(1) as f64);
+        let scaled_width = ((self.width as f64 * scale).round() as u32).clamp(1, width.max(1));
+        let scaled_height = ((self.height as f64 * scale).round() as u32).clamp(1, height.max(1));
+        let scaled = self.resize(scaled_width, scaled_height);
+        if (scaled_width, scaled_height) == (width, height) {
+            return scaled;
+        }
+        let mut canvas = self.with_pixels(transparent_pixels(width, height), width, height);
+        canvas.overlay(&scaled, (width - scaled_width) / 2, (height - scaled_height) / 2);
+        canvas
+    }
+
+    /// Draws `overlay` on top of this image with its top left corner at `x`, `y`
+    pub fn overlay(&mut self, overlay: &Image, x: u32, y: u32) {
+        let (Some(mut base), Some(top)) = (
+            ImageBuffer::<Rgba<u8>, _>::from_raw(self.width, self.height, self.pixels.clone()),
+            ImageBuffer::<Rgba<u8>, _>::from_raw(
+                overlay.width,
+                overlay.height,
+                overlay.pixels.clone(),
+            ),
+        ) else {
+            return;
+        };
+        image::imageops::overlay(&mut base, &top, x as i64, y as i64);
+        self.pixels = base.into_raw();
+    }
+
+    /// Greys out the image and makes it half transparent, like a disabled item
+    pub fn dim(&mut self) {
+        for pixel in self.pixels.chunks_exact_mut(4) {
+            let luma =
+                (pixel[0] as u32 * 299 + pixel[1] as u32 * 587 + pixel[2] as u32 * 114) / 1000;
+            pixel[0] = luma as u8;
+            pixel[1] = luma as u8;
+            pixel[2] = luma as u8;
+            pixel[3] /= 2;
         }
     }
 
+    /// Returns the pixels in RGBA format
+    pub fn as_rgba(&self) -> &[u8] {
+        &self.pixels
+    }
+
     pub fn as_base64_raw(&self) -> String {
         base64::engine::general_purpose::STANDARD.encode(&self.pixels)
     }
 
-    /// Returns the image encoded as a base64 PNG string
-    pub fn as_base64_png(&self) -> Result<Base64Png, Box<dyn std::error::Error>> {
+    /// Returns the image encoded as a PNG file
+    pub fn to_png(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
         // Validate dimensions
-        let expected_size = (self.width * self.height * 4) as usize;
+        let expected_size = pixel_length(self.width, self.height).unwrap_or(usize::MAX);
         if self.pixels.len() != expected_size {
             tracing::debug!(
                 "Pixel data length: {}, Expected size: {}x{}x4 = {}",
@@ -99,6 +279,12 @@ impl Image{
             self.height,
             image::ColorType::Rgba8,
         )?;
+        Ok(png_data)
+    }
+
+    /// Returns the image encoded as a base64 PNG string
+    pub fn as_base64_png(&self) -> Result<Base64Png, Box<dyn std::error::Error>> {
+        let png_data = self.to_png()?;
 
         // Base64 encode the PNG data
         let base64_png = base64::engine::general_purpose::STANDARD.encode(png_data);
@@ -108,31 +294,40 @@ impl Image{
         Ok(Base64Png { base64, is_default })
     }
 
+    /// Returns the image in the given format
+    pub fn encode(&self, format: OutputFormat) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        match format {
+            OutputFormat::Rgba => Ok(self.pixels.clone()),
+            OutputFormat::Png => self.to_png(),
+            OutputFormat::Base64Png => Ok(self.as_base64_png()?.base64.into_bytes()),
+        }
+    }
+
     pub fn save_as_png(
         &self,
         width: u32,
         height: u32,
-        output_path: &str,
+        output_path: impl AsRef<Path>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         let buffer = ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, self.pixels.to_vec())
             .ok_or("Failed to create ImageBuffer from raw pixels")?;
 
         // Save the ImageBuffer as a PNG file
-        buffer.save(Path::new(output_path))?;
+        buffer.save(output_path.as_ref())?;
 
         Ok(())
     }
 
     fn is_default_base64_png(&self, base64_png: &str) -> bool {
-        let default = "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAABQAAAAUCAYAAACNiR0NAAABZElEQVR4Ae3AA6AkWZbG8f937o3IzKdyS2Oubdu2bdu2bdu2bWmMnpZKr54yMyLu+Xa3anqmhztr1a/yAJ/8CZ/wDg5v8kKEvUrVX/qSL/mSSzwvxAN80zd83dE7vvO7Lnghfu1XfinvvOP2X7vznrPv/pVf+ZXneE4EDxCllO3tbba3t9ne3mZ7e5vt7W22t7fZ3t5me3ubruvj7d7xnd/gIQ+66Xs/5mM+5iTPieDf4Nprr413eud3e5OHP+zBP/jJn/zJp3g2gn8TcfzECd76rd/uDU+f2Pm+T/qkTzrGFVT+lR728Ifzcz/z0xgD6Nrrrn/jsxcuvh3wnQCVf6XHvtiL89gXe3Hut7t7Uf/w+L+vXEHlPxaV/1hU/mNR+Y9F5T8Wlf9YVP5jUfmPReU/FpX/WFT+Y1F5gNVqNdz69Kf3/CvsH+xZSeMKKg9w9z13vvZ3fte39fwrRIb7yU/hCv4Rx8VNRaZSeusAAAAASUVORK5CYII=";
-        base64_png == default
+        base64_png == DEFAULT_ICON_BASE64_PNG
     }
+}
+/// Returns the length of an RGBA buffer of the given size, if it is addressable
+fn pixel_length(width: u32, height: u32) -> Option<usize> {
+    (width as usize).checked_mul(height as usize)?.checked_mul(4)
+}
 
-    fn bgra_to_rgba(pixels: &[u8]) -> Vec<u8> {
-        let mut rgba_pixels = pixels.to_vec();
-        for chunk in rgba_pixels.chunks_exact_mut(4) {
-            chunk.swap(0, 2); // Swap Red (chunk[2]) and Blue (chunk[0])
-        }
-        rgba_pixels
-    }
+/// Returns the pixels of a transparent image of the given size
+fn transparent_pixels(width: u32, height: u32) -> Vec<u8> {
+    vec![0; pixel_length(width, height).expect("Image size overflows the address space")]
 }
@@ -1,7 +1,18 @@
 pub mod prelude;
+mod archive;
+mod backend;
 mod caches;
+mod custom_icon;
+mod decode;
+mod freedesktop;
 mod image;
+mod loader;
+mod mime;
+#[cfg(windows)]
 mod renderer;
+mod request;
+#[cfg(windows)]
 mod shell;
+mod thumbnail;
 #[cfg(test)]
-mod tests;
+mod tests;
\ No newline at end of file
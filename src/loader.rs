@@ -0,0 +1,220 @@
+use parking_lot::Mutex;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use crate::backend::{self, Backend, BackendError};
+use crate::image::Image;
+use crate::request::{IconRequest, IconSource};
+
+/// How many hung backend calls are tolerated before new loads fail immediately
+const DEFAULT_MAX_STUCK: usize = 4;
+
+#[derive(Debug)]
+pub enum LoadError {
+    /// The backend did not answer within the configured timeout. The call is left running
+    TimedOut {
+        source: IconSource,
+        timeout: Duration,
+    },
+    /// Too many earlier loads are still hung in the backend, so the load was not attempted
+    Unresponsive { stuck: usize },
+    /// The backend returned an error
+    Backend(BackendError),
+}
+
+impl LoadError {
+    /// Returns true if the load failed because the backend is hung rather than because of the file
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, Self::TimedOut { .. } | Self::Unresponsive { .. })
+    }
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TimedOut { source, timeout } => {
+                write!(
+                    f,
+                    "Loading icon for {} timed out after {:?}",
+                    source, timeout
+                )
+            }
+            Self::Unresponsive { stuck } => {
+                write!(
+                    f,
+                    "Icon backend is unresponsive ({} loads still hung)",
+                    stuck
+                )
+            }
+            Self::Backend(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Backend(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<BackendError> for LoadError {
+    fn from(err: BackendError) -> Self {
+        Self::Backend(err)
+    }
+}
+
+/// Loads icons through a backend, optionally with a per-load timeout.
+///
+/// With a timeout, each backend call runs on its own thread. If it does not finish in time the
+/// caller gets `LoadError::TimedOut` and the thread is abandoned. Once too many threads are
+/// stuck, loads fail with `LoadError::Unresponsive` until some of them return
+#[derive(Clone)]
+pub struct Loader {
+    backend: Arc<dyn Backend>,
+    timeout: Option<Duration>,
+    max_stuck: usize,
+    stuck: Arc<AtomicUsize>,
+}
+
+impl Default for Loader {
+    fn default() -> Self {
+        Self::new(backend::default_backend())
+    }
+}
+
+impl Loader {
+    pub fn new(backend: Arc<dyn Backend>) -> Self {
+        Self {
+            backend,
+            timeout: None,
+            max_stuck: DEFAULT_MAX_STUCK,
+            stuck: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Gives up on a load if the backend takes longer than `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how many hung backend calls are tolerated before loads fail immediately
+    pub fn with_max_stuck(mut self, max_stuck: usize) -> Self {
+        self.max_stuck = max_stuck;
+        self
+    }
+
+    /// Returns the number of timed out backend calls that have not returned yet
+    pub fn stuck(&self) -> usize {
+        self.stuck.load(Ordering::Acquire)
+    }
+
+    pub fn load(
+        &self,
+        path: impl AsRef<Path>,
+        width: u32,
+        height: u32,
+    ) -> Result<Image, LoadError> {
+        self.load_request(&IconRequest::new(path, width, height))
+    }
+
+    pub fn load_request(&self, request: &IconRequest) -> Result<Image, LoadError> {
+        let owned_request = request.clone();
+        self.run(&request.source, move |backend| backend.load(&owned_request))
+    }
+
+    /// The key to cache the result of `request` under, see `Backend::cache_key`
+    pub fn cache_key(&self, request: &IconRequest) -> IconRequest {
+        self.backend.cache_key(request)
+    }
+
+    pub fn recommended_size(&self, path: impl AsRef<Path>) -> Result<(u32, u32), LoadError> {
+        let path = path.as_ref();
+        let owned_path = path.to_path_buf();
+        let source = IconSource::Path(path.to_path_buf());
+        self.run(&source, move |backend| {
+            backend.recommended_size(&owned_path)
+        })
+    }
+
+    /// Loads the icon using the size recommended by the backend
+    pub fn load_recommended(&self, path: impl AsRef<Path>) -> Result<Image, LoadError> {
+        let path = path.as_ref();
+        let (width, height) = self.recommended_size(path)?;
+        tracing::debug!("Got recommended size: {}x{}", width, height);
+        self.load(path, width, height)
+    }
+
+    fn run<T, F>(&self, source: &IconSource, job: F) -> Result<T, LoadError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn Backend) -> Result<T, BackendError> + Send + 'static,
+    {
+        let Some(timeout) = self.timeout else {
+            return Ok(job(self.backend.as_ref())?);
+        };
+
+        let stuck = self.stuck();
+        if stuck >= self.max_stuck {
+            tracing::warn!(
+                "Skipping load for {}: {} backend calls are hung",
+                source,
+                stuck
+            );
+            return Err(LoadError::Unresponsive { stuck });
+        }
+
+        // Set by the caller once it stops waiting. Checked under the lock so that a result
+        // is either delivered or counted as stuck, never both
+        let abandoned = Arc::new(Mutex::new(false));
+        let (sender, receiver) = mpsc::channel();
+
+        let backend = Arc::clone(&self.backend);
+        let stuck_count = Arc::clone(&self.stuck);
+        let thread_abandoned = Arc::clone(&abandoned);
+        let owned_source = source.clone();
+        std::thread::Builder::new()
+            .name("getfileicon-load".to_string())
+            .spawn(move || {
+                // A panic must still be counted back, or a hung call that later panics would
+                // stay stuck forever
+                let result = panic::catch_unwind(AssertUnwindSafe(|| job(backend.as_ref())))
+                    .unwrap_or_else(|_| Err("Icon backend panicked".into()));
+                let abandoned = thread_abandoned.lock();
+                if *abandoned {
+                    stuck_count.fetch_sub(1, Ordering::AcqRel);
+                    tracing::warn!("Hung load for {} finally returned", owned_source);
+                } else {
+                    _ = sender.send(result);
+                }
+            })
+            .map_err(|err| LoadError::Backend(err.into()))?;
+
+        match receiver.recv_timeout(timeout) {
+            Ok(result) => Ok(result?),
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err(LoadError::Backend("Icon backend panicked".into()))
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                let mut abandoned = abandoned.lock();
+                // The result may have arrived while we were acquiring the lock
+                if let Ok(result) = receiver.try_recv() {
+                    return Ok(result?);
+                }
+                *abandoned = true;
+                self.stuck.fetch_add(1, Ordering::AcqRel);
+                tracing::warn!("Load for {} timed out after {:?}", source, timeout);
+                Err(LoadError::TimedOut {
+                    source: source.clone(),
+                    timeout,
+                })
+            }
+        }
+    }
+}
@@ -0,0 +1,439 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Common extensions and their MIME types. The first extension listed for a MIME type
+/// is the one used when going from MIME type to extension
+const EXTENSIONS: &[(&str, &str)] = &[
+    // Images
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("bmp", "image/bmp"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    ("ico", "image/vnd.microsoft.icon"),
+    ("icns", "image/x-icns"),
+    ("svg", "image/svg+xml"),
+    ("svgz", "image/svg+xml-compressed"),
+    ("psd", "image/vnd.adobe.photoshop"),
+    ("heic", "image/heic"),
+    ("avif", "image/avif"),
+    ("dng", "image/x-adobe-dng"),
+    ("cr2", "image/x-canon-cr2"),
+    ("nef", "image/x-nikon-nef"),
+    // Audio
+    ("mp3", "audio/mpeg"),
+    ("flac", "audio/flac"),
+    ("ogg", "audio/ogg"),
+    ("oga", "audio/ogg"),
+    ("opus", "audio/ogg"),
+    ("m4a", "audio/mp4"),
+    ("wav", "audio/wav"),
+    // Video
+    ("mp4", "video/mp4"),
+    ("m4v", "video/mp4"),
+    ("mkv", "video/x-matroska"),
+    ("webm", "video/webm"),
+    ("avi", "video/x-msvideo"),
+    ("mov", "video/quicktime"),
+    // Documents
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("csv", "text/csv"),
+    ("xml", "application/xml"),
+    ("json", "application/json"),
+    ("pdf", "application/pdf"),
+    ("rtf", "application/rtf"),
+    ("doc", "application/msword"),
+    ("xls", "application/vnd.ms-excel"),
+    ("ppt", "application/vnd.ms-powerpoint"),
+    (
+        "docx",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    ),
+    (
+        "xlsx",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    ),
+    (
+        "pptx",
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+    ),
+    ("odt", "application/vnd.oasis.opendocument.text"),
+    ("ods", "application/vnd.oasis.opendocument.spreadsheet"),
+    ("odp", "application/vnd.oasis.opendocument.presentation"),
+    ("odg", "application/vnd.oasis.opendocument.graphics"),
+    ("epub", "application/epub+zip"),
+    ("3mf", "model/3mf"),
+    // Source code and scripts
+    ("rs", "text/x-rust"),
+    ("c", "text/x-csrc"),
+    ("h", "text/x-chdr"),
+    ("cpp", "text/x-c++src"),
+    ("py", "text/x-python"),
+    ("js", "text/javascript"),
+    ("sh", "application/x-shellscript"),
+    // Fonts
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttc", "font/collection"),
+    // Archives and packages
+    ("zip", "application/zip"),
+    ("tar", "application/x-tar"),
+    ("gz", "application/gzip"),
+    ("xz", "application/x-xz"),
+    ("zst", "application/zstd"),
+    ("7z", "application/x-7z-compressed"),
+    ("rar", "application/vnd.rar"),
+    ("deb", "application/vnd.debian.binary-package"),
+    ("rpm", "application/x-rpm"),
+    ("apk", "application/vnd.android.package-archive"),
+    ("appimage", "application/vnd.appimage"),
+    ("iso", "application/x-cd-image"),
+    // Executables
+    ("exe", "application/vnd.microsoft.portable-executable"),
+    ("dll", "application/vnd.microsoft.portable-executable"),
+    ("desktop", "application/x-desktop"),
+];
+
+/// Returns the MIME type for a lowercase extension without the leading dot
+pub fn mime_type_for_extension(extension: &str) -> Option<&'static str> {
+    EXTENSIONS
+        .iter()
+        .find(|(known, _)| *known == extension)
+        .map(|(_, mime_type)| *mime_type)
+}
+
+/// Returns the usual extension for a lowercase MIME type
+pub fn extension_for_mime_type(mime_type: &str) -> Option<&'static str> {
+    EXTENSIONS
+        .iter()
+        .find(|(_, known)| *known == mime_type)
+        .map(|(extension, _)| *extension)
+}
+
+/// Magic bytes at an offset. All of them must be found for the signature to match
+type Signature = &'static [(usize, &'static [u8])];
+
+/// Signatures of common formats, the more specific ones of a container first
+const SIGNATURES: &[(Signature, &str)] = &[
+    // Images
+    (&[(0, b"\x89PNG\r\n\x1a\n")], "image/png"),
+    (&[(0, b"\xff\xd8\xff")], "image/jpeg"),
+    (&[(0, b"GIF87a")], "image/gif"),
+    (&[(0, b"GIF89a")], "image/gif"),
+    (&[(0, b"RIFF"), (8, b"WEBP")], "image/webp"),
+    (&[(0, b"II*\0")], "image/tiff"),
+    (&[(0, b"MM\0*")], "image/tiff"),
+    (&[(0, b"\0\0\x01\0")], "image/vnd.microsoft.icon"),
+    (&[(0, b"icns")], "image/x-icns"),
+    (&[(0, b"8BPS")], "image/vnd.adobe.photoshop"),
+    (&[(4, b"ftypheic")], "image/heic"),
+    (&[(4, b"ftypavif")], "image/avif"),
+    // Audio and video
+    (&[(0, b"ID3")], "audio/mpeg"),
+    (&[(0, b"fLaC")], "audio/flac"),
+    (&[(0, b"OggS")], "audio/ogg"),
+    (&[(0, b"RIFF"), (8, b"WAVE")], "audio/wav"),
+    (&[(0, b"RIFF"), (8, b"AVI ")], "video/x-msvideo"),
+    (&[(4, b"ftypM4A ")], "audio/mp4"),
+    (&[(4, b"ftypqt  ")], "video/quicktime"),
+    (&[(4, b"ftyp")], "video/mp4"),
+    (&[(0, b"\x1a\x45\xdf\xa3")], "video/x-matroska"),
+    // Documents and fonts
+    (&[(0, b"%PDF-")], "application/pdf"),
+    (&[(0, b"{\\rtf")], "application/rtf"),
+    (&[(0, b"wOFF")], "font/woff"),
+    (&[(0, b"wOF2")], "font/woff2"),
+    (&[(0, b"OTTO")], "font/otf"),
+    (&[(0, b"\0\x01\0\0\0")], "font/ttf"),
+    (&[(0, b"ttcf")], "font/collection"),
+    // Archives, packages and executables
+    (&[(0, b"PK\x03\x04")], "application/zip"),
+    (&[(0, b"PK\x05\x06")], "application/zip"),
+    (&[(0, b"\x1f\x8b")], "application/gzip"),
+    (&[(0, b"\xfd7zXZ\0")], "application/x-xz"),
+    (&[(0, b"\x28\xb5\x2f\xfd")], "application/zstd"),
+    (&[(0, b"7z\xbc\xaf\x27\x1c")], "application/x-7z-compressed"),
+    (&[(0, b"Rar!\x1a\x07")], "application/vnd.rar"),
+    (
+        &[(0, b"!<arch>\ndebian")],
+        "application/vnd.debian.binary-package",
+    ),
+    (&[(0, b"\xed\xab\xee\xdb")], "application/x-rpm"),
+    (&[(257, b"ustar")], "application/x-tar"),
+    (
+        &[(0, b"\x7fELF"), (8, b"AI\x02")],
+        "application/vnd.appimage",
+    ),
+    (
+        &[(0, b"MZ")],
+        "application/vnd.microsoft.portable-executable",
+    ),
+    (&[(0, b"\x7fELF")], "application/x-executable"),
+    (
+        &[(0, b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1")],
+        "application/x-ole-storage",
+    ),
+    // Bitmaps by the size of their info header, as `BM` alone starts plenty of text
+    (&[(0, b"BM"), (14, b"\x28\0\0\0")], "image/bmp"),
+    (&[(0, b"BM"), (14, b"\x6c\0\0\0")], "image/bmp"),
+    (&[(0, b"BM"), (14, b"\x7c\0\0\0")], "image/bmp"),
+    (&[(0, b"[Desktop Entry]")], "application/x-desktop"),
+];
+
+/// Types whose signature other formats share, by building on them or by chance. When
+/// the extension tells a type, content of these types doesn't override it
+const AMBIGUOUS: &[&str] = &[
+    "image/tiff",
+    "image/bmp",
+    "image/vnd.microsoft.icon",
+    "audio/mpeg",
+    "audio/ogg",
+    "audio/mp4",
+    "video/mp4",
+    "video/x-matroska",
+    "application/pdf",
+    "font/ttf",
+    "application/zip",
+    "application/gzip",
+    "application/x-xz",
+    "application/zstd",
+    "application/x-7z-compressed",
+    "application/vnd.rar",
+    "application/x-tar",
+    "application/vnd.microsoft.portable-executable",
+    "application/x-executable",
+    "application/x-ole-storage",
+    "application/x-desktop",
+];
+
+/// Interpreters named by the `#!` line of scripts, without version numbers
+const INTERPRETERS: &[(&str, &str)] = &[
+    ("sh", "application/x-shellscript"),
+    ("bash", "application/x-shellscript"),
+    ("dash", "application/x-shellscript"),
+    ("zsh", "application/x-shellscript"),
+    ("ksh", "application/x-shellscript"),
+    ("python", "text/x-python"),
+    ("perl", "application/x-perl"),
+    ("ruby", "application/x-ruby"),
+    ("node", "text/javascript"),
+    ("php", "application/x-php"),
+    ("lua", "text/x-lua"),
+    ("tclsh", "text/x-tcl"),
+];
+
+/// The type of content that isn't known to be anything more specific
+pub const UNKNOWN_MIME_TYPE: &str = "application/octet-stream";
+
+/// How many bytes `sniff` looks at
+pub const SNIFF_LENGTH: usize = 512;
+
+/// What told the type of a file
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+pub enum MimeBasis {
+    /// The extension. The content agreed, told nothing definite or wasn't read
+    Extension,
+    /// The first bytes, as the name has no known extension
+    Content,
+    /// The first bytes, which are clearly of another type than the extension tells,
+    /// like a PNG image named `notes.txt`
+    ContentOverExtension,
+    /// Nothing, so the type is `application/octet-stream`
+    Unknown,
+}
+
+/// The type chosen for a file, and why
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct MimeGuess {
+    pub mime_type: String,
+    pub basis: MimeBasis,
+    /// The type the extension tells, if it is a known one
+    pub by_extension: Option<String>,
+    /// The type the first bytes tell, if they were read and tell one
+    pub by_content: Option<String>,
+}
+
+impl MimeGuess {
+    /// Chooses between the type `by_extension` and the one the first bytes `head` tell.
+    /// Content wins when the extension tells nothing, or when its signature belongs to
+    /// one format only and that isn't the extension's
+    pub fn decide(by_extension: Option<String>, head: &[u8]) -> Self {
+        Self::decide_with_aliases(by_extension, head, str::to_string)
+    }
+
+    /// Like `decide`, comparing and reporting both types by the type `canonical` gives for
+    /// them, so that an alias like `audio/wav` agrees with `audio/x-wav`
+    pub fn decide_with_aliases(
+        by_extension: Option<String>,
+        head: &[u8],
+        canonical: impl Fn(&str) -> String,
+    ) -> Self {
+        let by_extension = by_extension.map(|mime_type| canonical(&mime_type));
+        let sniffed = sniff_content(head);
+        let by_content = sniffed.map(|(mime_type, _)| canonical(mime_type));
+        let (mime_type, basis) = match (&by_extension, &by_content, sniffed) {
+            (Some(extension), Some(content), Some((_, true))) if extension != content => {
+                (content.clone(), MimeBasis::ContentOverExtension)
+            }
+            (Some(extension), _, _) => (extension.clone(), MimeBasis::Extension),
+            (None, Some(content), _) => (content.clone(), MimeBasis::Content),
+            (None, _, _) => (UNKNOWN_MIME_TYPE.to_string(), MimeBasis::Unknown),
+        };
+        Self {
+            mime_type,
+            basis,
+            by_extension,
+            by_content,
+        }
+    }
+
+    /// The type of the file at `path`, by its extension and its first bytes. Only regular
+    /// files are read, as reading a FIFO or device could block or have side effects
+    pub fn of_file(path: &Path) -> std::io::Result<Self> {
+        if !path.metadata()?.is_file() {
+            return Self::of_reader(path, &mut std::io::empty());
+        }
+        Self::of_reader(path, &mut File::open(path)?)
+    }
+
+    /// The type of the content of `reader`, by the extension of `name` and the first
+    /// bytes. Leaves `reader` at position 0
+    pub fn of_reader(
+        name: &Path,
+        reader: &mut (impl Read + Seek + ?Sized),
+    ) -> std::io::Result<Self> {
+        let by_extension = name
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(|extension| mime_type_for_extension(&extension.to_lowercase()));
+        let head = read_head(reader)?;
+        Ok(Self::decide(by_extension.map(str::to_string), &head))
+    }
+
+    /// The type, unless nothing told it
+    pub fn known(&self) -> Option<&str> {
+        (self.basis != MimeBasis::Unknown).then_some(self.mime_type.as_str())
+    }
+}
+
+/// Tells the MIME type of a file from its first bytes, up to `SNIFF_LENGTH` of them
+pub fn sniff(head: &[u8]) -> Option<&'static str> {
+    sniff_content(head).map(|(mime_type, _)| mime_type)
+}
+
+/// Like `sniff`, for the first bytes of `reader`. Leaves `reader` at position 0
+pub fn sniff_reader(
+    reader: &mut (impl Read + Seek + ?Sized),
+) -> std::io::Result<Option<&'static str>> {
+    Ok(sniff(&read_head(reader)?))
+}
+
+/// Reads the first `SNIFF_LENGTH` bytes of `reader`, then seeks back to position 0
+pub fn read_head(reader: &mut (impl Read + Seek + ?Sized)) -> std::io::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(SNIFF_LENGTH);
+    (&mut *reader)
+        .take(SNIFF_LENGTH as u64)
+        .read_to_end(&mut head)?;
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(head)
+}
+
+/// The type `head` tells, and whether it is definite: found by a signature that only
+/// one format has
+fn sniff_content(head: &[u8]) -> Option<(&'static str, bool)> {
+    if let Some(mime_type) = SIGNATURES.iter().find_map(|(signature, mime_type)| {
+        signature
+            .iter()
+            .all(|(offset, magic)| head.get(*offset..).is_some_and(|at| at.starts_with(magic)))
+            .then_some(*mime_type)
+    }) {
+        // OpenDocument and EPUB files start with an uncompressed `mimetype` member
+        if let Some(declared) = zip_mime_type(head) {
+            return Some((declared, true));
+        }
+        return Some((mime_type, !AMBIGUOUS.contains(&mime_type)));
+    }
+
+    // Text formats are told by how they usually start, which any text could
+    if let Some(line) = head.strip_prefix(b"#!") {
+        return Some((interpreter_mime_type(line).unwrap_or("text/plain"), false));
+    }
+    let text = head
+        .strip_prefix(b"\xef\xbb\xbf")
+        .unwrap_or(head)
+        .trim_ascii_start();
+    let starts_with = |prefix: &[u8]| {
+        text.get(..prefix.len())
+            .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+    };
+    let mime_type =
+        if text.starts_with(b"<svg") || (text.starts_with(b"<?xml") && contains(text, b"<svg")) {
+            "image/svg+xml"
+        } else if starts_with(b"<!doctype html") || starts_with(b"<html") {
+            "text/html"
+        } else if text.starts_with(b"<?xml") {
+            "application/xml"
+        } else if is_text(head) {
+            "text/plain"
+        } else {
+            return None;
+        };
+    Some((mime_type, false))
+}
+
+/// The type of a script run by the interpreter on its `#!` line, e.g. `/bin/sh` or
+/// `/usr/bin/env python3`
+fn interpreter_mime_type(line: &[u8]) -> Option<&'static str> {
+    let line = line.split(|byte| *byte == b'\n').next()?;
+    let line = std::str::from_utf8(line).ok()?;
+    let mut words = line.split_ascii_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        // Skips options like `-S`
+        program = words.find(|word| !word.starts_with('-'))?;
+    }
+    let name = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    INTERPRETERS
+        .iter()
+        .find(|(interpreter, _)| *interpreter == name)
+        .map(|(_, mime_type)| *mime_type)
+}
+
+/// Whether `head` looks like the start of UTF-8 text. The last character may be cut off
+fn is_text(head: &[u8]) -> bool {
+    if head.is_empty() || head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(error) => error.error_len().is_none(),
+    }
+}
+
+/// The type a zip based document declares in its first member, if it is a known one
+fn zip_mime_type(head: &[u8]) -> Option<&'static str> {
+    let declared = head
+        .strip_prefix(b"PK\x03\x04")?
+        .get(26..)?
+        .strip_prefix(b"mimetype")?;
+    EXTENSIONS
+        .iter()
+        .map(|(_, mime_type)| *mime_type)
+        .find(|mime_type| declared.starts_with(mime_type.as_bytes()))
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
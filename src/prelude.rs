@@ -1,3 +1,29 @@
 pub use crate::image::Image;
+pub use crate::request::{
+    ArchiveFingerprint, ColorScheme, FileKind, IconRequest, IconSource, IconState, OutputFormat,
+    BROKEN_LINK_EMBLEM,
+};
+pub use crate::backend::{Backend, BackendError};
+pub use crate::backend::freedesktop::FreedesktopBackend;
+pub use crate::freedesktop::desktop_entry::DesktopEntry;
+pub use crate::freedesktop::mounts::MountTable;
+pub use crate::freedesktop::special_folders::SpecialFolders;
+pub use crate::freedesktop::thumbnail_cache::{ThumbnailCache, ThumbnailSize};
+pub use crate::backend::sandbox::{run_helper, run_helper_if_requested, SandboxBackend};
+pub use crate::loader::{LoadError, Loader};
+pub use crate::mime::{MimeBasis, MimeGuess};
 pub use crate::caches::png_cache::PngCache;
-pub use crate::caches::easy_png_cache::EasyPngCache;
\ No newline at end of file
+pub use crate::caches::easy_png_cache::EasyPngCache;
+pub use crate::caches::load_queue::{LoadHandle, LoadQueue, Priority};
+pub use crate::thumbnail::Thumbnailer;
+pub use crate::thumbnail::apk::ApkThumbnailer;
+pub use crate::thumbnail::appimage::AppImageThumbnailer;
+pub use crate::thumbnail::cover_art::CoverArtThumbnailer;
+pub use crate::thumbnail::document::DocumentThumbnailer;
+pub use crate::thumbnail::embedded::RawThumbnailer;
+pub use crate::thumbnail::executable::ExecutableThumbnailer;
+pub use crate::thumbnail::font::FontThumbnailer;
+pub use crate::thumbnail::ico::IcoThumbnailer;
+pub use crate::thumbnail::package::PackageThumbnailer;
+pub use crate::thumbnail::raster::RasterThumbnailer;
+pub use crate::thumbnail::svg::SvgThumbnailer;
\ No newline at end of file
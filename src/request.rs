@@ -0,0 +1,398 @@
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::archive;
+use crate::mime;
+
+/// Light or dark variant of an icon
+#[derive(Debug, Clone, Copy, Default, Hash, Eq, PartialEq)]
+pub enum ColorScheme {
+    /// Whatever the system is currently using
+    #[default]
+    System,
+    Light,
+    Dark,
+}
+
+/// State of the item the icon is shown for
+#[derive(Debug, Clone, Copy, Default, Hash, Eq, PartialEq)]
+pub enum IconState {
+    #[default]
+    Normal,
+    /// An expanded folder
+    Open,
+    Selected,
+    Disabled,
+}
+
+/// Encoding of the pixels handed back by `Image::encode`
+#[derive(Debug, Clone, Copy, Default, Hash, Eq, PartialEq)]
+pub enum OutputFormat {
+    /// Raw RGBA pixels
+    #[default]
+    Rgba,
+    Png,
+    /// A `data:image/png;base64,` URL
+    Base64Png,
+}
+
+/// Emblem drawn over the icon of a symbolic link whose target doesn't exist
+pub const BROKEN_LINK_EMBLEM: &str = "emblem-unreadable";
+
+/// What kind of entry a path is in the file system
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+#[repr(u8)]
+pub enum FileKind {
+    File = 0,
+    Directory = 1,
+    /// A symbolic link whose target doesn't exist. Links to something are the kind of
+    /// what they point to
+    Symlink = 2,
+    Socket = 3,
+    Fifo = 4,
+    BlockDevice = 5,
+    CharDevice = 6,
+}
+
+impl FileKind {
+    /// The kind of entry at `path`, following symbolic links. `None` if there is none
+    pub fn of(path: &Path) -> Option<Self> {
+        let metadata = path.symlink_metadata().ok()?;
+        let file_type = if metadata.file_type().is_symlink() {
+            match path.metadata() {
+                Ok(target) => target.file_type(),
+                Err(_) => return Some(Self::Symlink),
+            }
+        } else {
+            metadata.file_type()
+        };
+        if file_type.is_dir() {
+            return Some(Self::Directory);
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+            if file_type.is_socket() {
+                return Some(Self::Socket);
+            } else if file_type.is_fifo() {
+                return Some(Self::Fifo);
+            } else if file_type.is_block_device() {
+                return Some(Self::BlockDevice);
+            } else if file_type.is_char_device() {
+                return Some(Self::CharDevice);
+            }
+        }
+        Some(Self::File)
+    }
+
+    /// The `inode/` MIME type of the kind. Files of no known type are
+    /// `application/octet-stream`
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Self::File => mime::UNKNOWN_MIME_TYPE,
+            Self::Directory => "inode/directory",
+            Self::Symlink => "inode/symlink",
+            Self::Socket => "inode/socket",
+            Self::Fifo => "inode/fifo",
+            Self::BlockDevice => "inode/blockdevice",
+            Self::CharDevice => "inode/chardevice",
+        }
+    }
+}
+
+/// Length and modification time of an archive. Sources for its members carry it, so
+/// icons cached for one version of the archive aren't served for the next
+#[derive(Debug, Clone, Copy, Default, Hash, Eq, PartialEq)]
+pub struct ArchiveFingerprint {
+    pub length: u64,
+    /// Nanoseconds since the Unix epoch
+    pub modified: u64,
+}
+
+impl ArchiveFingerprint {
+    /// The fingerprint of the archive at `path` as it is now. Zero if it can't be read
+    pub fn of(path: &Path) -> Self {
+        let Ok(metadata) = path.metadata() else {
+            return Self::default();
+        };
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since_epoch| since_epoch.as_nanos() as u64);
+        Self {
+            length: metadata.len(),
+            modified,
+        }
+    }
+}
+
+/// What an icon is looked up for
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub enum IconSource {
+    /// A file or directory on disk
+    Path(PathBuf),
+    /// A file or directory inside a zip or tar archive, compressed tars included.
+    /// `member` is relative to the root of the archive, without a trailing `/`
+    ArchiveMember {
+        archive: PathBuf,
+        member: String,
+        fingerprint: ArchiveFingerprint,
+    },
+    /// Any entry of this kind, e.g. any directory or any named pipe
+    FileKind(FileKind),
+    /// Any file with this extension. Stored lowercase without the leading dot
+    Extension(String),
+    /// Any file of this MIME type, e.g. `image/png`. Stored lowercase
+    MimeType(String),
+    /// A freedesktop icon name like `folder` or `network-server`
+    IconName(String),
+    /// An installed application by desktop file ID, e.g. `org.gnome.Nautilus`.
+    /// Stored without `.desktop`
+    Application(String),
+}
+
+impl IconSource {
+    /// `path`, or the archive member it names in the form `photos.zip!/2024/a.jpg`
+    /// when there is no such file but the archive exists
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        let virtual_path = path.to_str().filter(|_| path.symlink_metadata().is_err());
+        let member = virtual_path.and_then(|virtual_path| {
+            virtual_path
+                .match_indices(archive::SEPARATOR)
+                .map(|(index, _)| {
+                    let (archive, member) = virtual_path.split_at(index);
+                    (archive, &member[archive::SEPARATOR.len()..])
+                })
+                .find(|(archive, _)| Path::new(archive).is_file())
+        });
+        match member {
+            Some((archive, member)) => Self::archive_member(archive, member),
+            None => Self::Path(path.to_path_buf()),
+        }
+    }
+
+    /// The file or directory `member` of `archive`, fingerprinting the archive as it is now
+    pub fn archive_member(archive: impl AsRef<Path>, member: &str) -> Self {
+        let archive = archive.as_ref();
+        Self::ArchiveMember {
+            archive: archive.to_path_buf(),
+            member: archive::member_path(member).to_string(),
+            fingerprint: ArchiveFingerprint::of(archive),
+        }
+    }
+
+    /// Returns the path if the icon is for a file on disk
+    pub fn as_path(&self) -> Option<&Path> {
+        match self {
+            Self::Path(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Returns the lowercase extension, looking it up for MIME types
+    pub fn extension(&self) -> Option<String> {
+        match self {
+            Self::Path(path) => Some(path.extension()?.to_str()?.to_lowercase()),
+            Self::ArchiveMember { member, .. } => {
+                Some(Path::new(member).extension()?.to_str()?.to_lowercase())
+            }
+            Self::Extension(extension) => Some(extension.clone()),
+            Self::MimeType(mime_type) => {
+                mime::extension_for_mime_type(mime_type).map(str::to_string)
+            }
+            Self::FileKind(_) | Self::IconName(_) | Self::Application(_) => None,
+        }
+    }
+
+    /// Returns the MIME type when it can be told from the name alone
+    pub fn mime_type(&self) -> Option<String> {
+        match self {
+            Self::Path(_) | Self::ArchiveMember { .. } => {
+                let extension = self.extension()?;
+                mime::mime_type_for_extension(&extension).map(str::to_string)
+            }
+            Self::Extension(extension) => {
+                mime::mime_type_for_extension(extension).map(str::to_string)
+            }
+            Self::MimeType(mime_type) => Some(mime_type.clone()),
+            Self::FileKind(kind) => Some(kind.mime_type().to_string()),
+            Self::IconName(_) | Self::Application(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for IconSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Path(path) => write!(f, "{}", path.display()),
+            Self::ArchiveMember {
+                archive, member, ..
+            } => write!(f, "{}{}{}", archive.display(), archive::SEPARATOR, member),
+            Self::FileKind(kind) => write!(f, "{}", kind.mime_type()),
+            Self::Extension(extension) => write!(f, "*.{}", extension),
+            Self::MimeType(mime_type) => write!(f, "{}", mime_type),
+            Self::IconName(name) => write!(f, "icon:{}", name),
+            Self::Application(id) => write!(f, "{}.desktop", id),
+        }
+    }
+}
+
+/// Describes everything about an icon lookup. Two requests that differ in any field
+/// are loaded and cached separately
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct IconRequest {
+    pub source: IconSource,
+    /// Width in logical pixels. The icon is rendered at `width * scale` pixels
+    pub width: u32,
+    /// Height in logical pixels. The icon is rendered at `height * scale` pixels
+    pub height: u32,
+    /// Display scale in percent: 100 for 1x, 125 for the 1.25x of fractional scaling
+    pub scale_percent: u32,
+    /// Icon theme to use instead of the system theme
+    pub theme: Option<String>,
+    pub color_scheme: ColorScheme,
+    pub state: IconState,
+    /// Emblems drawn over the icon, e.g. `emblem-symbolic-link`
+    pub overlays: Vec<String>,
+    /// Only use the icon for the file type, never a thumbnail of the file content
+    pub type_icon_only: bool,
+    /// Show folders with the plain folder icon even when the user set their own with
+    /// `.directory`, `desktop.ini` or a `.folder.png`
+    pub ignore_custom_icon: bool,
+    pub format: OutputFormat,
+}
+
+impl IconRequest {
+    /// Request for a file or directory. Members of archives can be named with a virtual
+    /// path like `photos.zip!/2024/a.jpg`
+    pub fn new(path: impl AsRef<Path>, width: u32, height: u32) -> Self {
+        Self::from_source(IconSource::from_path(path), width, height)
+    }
+
+    /// Request for the file or directory `member` inside the zip or tar `archive`
+    pub fn for_archive_member(
+        archive: impl AsRef<Path>,
+        member: &str,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        Self::from_source(IconSource::archive_member(archive, member), width, height)
+    }
+
+    /// Request for the icon any entry of `kind` would get, e.g. any directory
+    pub fn for_file_kind(kind: FileKind, width: u32, height: u32) -> Self {
+        Self::from_source(IconSource::FileKind(kind), width, height)
+    }
+
+    /// Request for the icon any file with `extension` would get. A leading dot is ignored
+    pub fn for_extension(extension: &str, width: u32, height: u32) -> Self {
+        let extension = extension.trim_start_matches('.').to_lowercase();
+        Self::from_source(IconSource::Extension(extension), width, height)
+    }
+
+    /// Request for the icon any file of `mime_type` would get
+    pub fn for_mime_type(mime_type: &str, width: u32, height: u32) -> Self {
+        let mime_type = mime_type.trim().to_lowercase();
+        Self::from_source(IconSource::MimeType(mime_type), width, height)
+    }
+
+    /// Request for a named icon from the icon theme, e.g. `user-trash-full`
+    pub fn for_icon_name(name: &str, width: u32, height: u32) -> Self {
+        Self::from_source(IconSource::IconName(name.to_string()), width, height)
+    }
+
+    /// Request for the icon of an installed application. A `.desktop` suffix is ignored
+    pub fn for_application(id: &str, width: u32, height: u32) -> Self {
+        let id = id.strip_suffix(".desktop").unwrap_or(id).to_string();
+        Self::from_source(IconSource::Application(id), width, height)
+    }
+
+    pub fn from_source(source: IconSource, width: u32, height: u32) -> Self {
+        Self {
+            source,
+            width,
+            height,
+            scale_percent: 100,
+            theme: None,
+            color_scheme: ColorScheme::System,
+            state: IconState::Normal,
+            overlays: Vec::new(),
+            type_icon_only: false,
+            ignore_custom_icon: false,
+            format: OutputFormat::Rgba,
+        }
+    }
+
+    /// Sets the display scale factor. Values below 1 are treated as 1
+    pub fn with_scale(mut self, scale: u32) -> Self {
+        self.scale_percent = scale.max(1).saturating_mul(100);
+        self
+    }
+
+    /// Sets a fractional display scale factor like 1.25, kept to hundredths. Values below 1
+    /// are treated as 1
+    pub fn with_fractional_scale(mut self, scale: f32) -> Self {
+        // Float to int casts saturate, and NaN becomes 0
+        self.scale_percent = ((scale * 100.0).round() as u32).max(100);
+        self
+    }
+
+    pub fn with_theme(mut self, theme: &str) -> Self {
+        self.theme = Some(theme.to_string());
+        self
+    }
+
+    pub fn with_color_scheme(mut self, color_scheme: ColorScheme) -> Self {
+        self.color_scheme = color_scheme;
+        self
+    }
+
+    pub fn with_state(mut self, state: IconState) -> Self {
+        self.state = state;
+        self
+    }
+
+    /// Adds an emblem on top of the icon. Overlays are drawn in the order they were added
+    pub fn with_overlay(mut self, overlay: &str) -> Self {
+        self.overlays.push(overlay.to_string());
+        self
+    }
+
+    pub fn type_icon_only(mut self, type_icon_only: bool) -> Self {
+        self.type_icon_only = type_icon_only;
+        self
+    }
+
+    pub fn ignore_custom_icon(mut self, ignore_custom_icon: bool) -> Self {
+        self.ignore_custom_icon = ignore_custom_icon;
+        self
+    }
+
+    pub fn with_format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Width of the rendered icon in physical pixels
+    pub fn pixel_width(&self) -> u32 {
+        self.scaled(self.width)
+    }
+
+    /// Height of the rendered icon in physical pixels
+    pub fn pixel_height(&self) -> u32 {
+        self.scaled(self.height)
+    }
+
+    /// The scale of the icon theme directories to look in. Fractional scales round up, so
+    /// that icons are drawn larger and scaled down rather than blurred
+    pub fn theme_scale(&self) -> u32 {
+        self.scale_percent.div_ceil(100).max(1)
+    }
+
+    fn scaled(&self, length: u32) -> u32 {
+        let pixels = (length as u64 * self.scale_percent as u64).div_ceil(100);
+        pixels.min(u32::MAX as u64) as u32
+    }
+}
@@ -1,36 +1,52 @@
+use std::ffi::OsString;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
 use windows::core::PCWSTR;
 use windows::Win32;
-use windows::Win32::Graphics::Gdi::{GetObjectW, BITMAP, HBITMAP};
-use windows::Win32::Storage::FileSystem::FILE_ATTRIBUTE_NORMAL;
+use windows::Win32::Graphics::Gdi::{DeleteObject, GetObjectW, BITMAP, HBITMAP};
+use windows::Win32::Storage::FileSystem::{
+    FILE_ATTRIBUTE_DIRECTORY, FILE_ATTRIBUTE_NORMAL, FILE_FLAGS_AND_ATTRIBUTES,
+};
 use windows::Win32::System::Com::{CoInitializeEx, CoUninitialize, COINIT_MULTITHREADED};
+use windows::Win32::UI::Controls::{IImageList, ILD_TRANSPARENT};
 use windows::Win32::UI::Shell::{
-    IShellItemImageFactory, SHCreateItemFromParsingName, SHGetFileInfoW, SHFILEINFOW,
-    SHGFI_SYSICONINDEX, SHGFI_USEFILEATTRIBUTES, SIIGBF_BIGGERSIZEOK, SIIGBF_RESIZETOFIT,
+    IShellItemImageFactory, SHCreateItemFromParsingName, SHGetFileInfoW, SHGetImageList,
+    SHGetStockIconInfo, SHFILEINFOW, SHGFI_SYSICONINDEX, SHGFI_USEFILEATTRIBUTES,
+    SHGSI_ICONLOCATION, SHGSI_SYSICONINDEX, SHIL_EXTRALARGE, SHIL_JUMBO, SHIL_LARGE, SHIL_SMALL,
+    SHSTOCKICONID, SHSTOCKICONINFO, SIIGBF_BIGGERSIZEOK, SIIGBF_ICONONLY, SIIGBF_RESIZETOFIT,
 };
+use windows::Win32::UI::WindowsAndMessaging::{DestroyIcon, GetIconInfo, ICONINFO};
 
 pub fn get_custom_sized_icon(
-    file_path: &str,
+    file_path: &Path,
     width: u32,
     height: u32,
+    icon_only: bool,
 ) -> Result<HBITMAP, windows::core::Error> {
     unsafe {
         _ = CoInitializeEx(None, COINIT_MULTITHREADED);
 
         let result = {
             // Convert file path to a PCWSTR
-            let file_path_wide: Vec<u16> = file_path.encode_utf16().chain(Some(0)).collect();
+            let file_path_wide = to_wide(file_path);
 
             // Create the shell item from the file path
             let image_factory: IShellItemImageFactory =
                 SHCreateItemFromParsingName(PCWSTR(file_path_wide.as_ptr()), None)?;
 
+            // Skip thumbnails if only the type icon was asked for
+            let mut flags = SIIGBF_BIGGERSIZEOK | SIIGBF_RESIZETOFIT;
+            if icon_only {
+                flags |= SIIGBF_ICONONLY;
+            }
+
             // Get the bitmap with the desired size
             image_factory.GetImage(
                 Win32::Foundation::SIZE {
                     cx: width as i32,
                     cy: height as i32,
                 },
-                SIIGBF_BIGGERSIZEOK | SIIGBF_RESIZETOFIT,
+                flags,
             )
         };
 
@@ -39,13 +55,104 @@ pub fn get_custom_sized_icon(
     }
 }
 
+/// Gets the icon the shell shows for files with `extension`, without needing such a file.
+/// The bitmap comes from the system image list closest to `size` and may need scaling
+pub fn get_extension_icon(extension: &str, size: u32) -> Result<HBITMAP, windows::core::Error> {
+    get_system_icon(&format!("file.{}", extension), FILE_ATTRIBUTE_NORMAL, size)
+}
+
+/// Gets the icon the shell shows for folders without a custom icon. Like
+/// `get_extension_icon`, the bitmap may need scaling
+pub fn get_folder_icon(size: u32) -> Result<HBITMAP, windows::core::Error> {
+    get_system_icon("folder", FILE_ATTRIBUTE_DIRECTORY, size)
+}
+
+/// Gets the system image list icon for an item named `name` with `attributes`, from the
+/// list closest to `size`
+fn get_system_icon(
+    name: &str,
+    attributes: FILE_FLAGS_AND_ATTRIBUTES,
+    size: u32,
+) -> Result<HBITMAP, windows::core::Error> {
+    unsafe {
+        _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+
+        let result = (|| {
+            // SHGFI_USEFILEATTRIBUTES makes the shell go by the name and attributes alone
+            let file_name: Vec<u16> = name.encode_utf16().chain(Some(0)).collect();
+            let mut file_info = SHFILEINFOW::default();
+            let found = SHGetFileInfoW(
+                PCWSTR(file_name.as_ptr()),
+                attributes,
+                Some(&mut file_info),
+                std::mem::size_of::<SHFILEINFOW>() as u32,
+                SHGFI_SYSICONINDEX | SHGFI_USEFILEATTRIBUTES,
+            );
+            if found == 0 {
+                return Err(windows::core::Error::from_win32());
+            }
+            image_list_icon(file_info.iIcon, size)
+        })();
+
+        CoUninitialize();
+        result
+    }
+}
+
+/// Gets a stock icon of the shell, like the recycle bin or a CD drive, and the file it
+/// comes from. Like `get_extension_icon`, the bitmap may need scaling
+pub fn get_stock_icon(
+    id: SHSTOCKICONID,
+    size: u32,
+) -> Result<(HBITMAP, PathBuf), windows::core::Error> {
+    unsafe {
+        _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+
+        let result = (|| {
+            let mut info = SHSTOCKICONINFO {
+                cbSize: std::mem::size_of::<SHSTOCKICONINFO>() as u32,
+                ..Default::default()
+            };
+            SHGetStockIconInfo(id, SHGSI_SYSICONINDEX | SHGSI_ICONLOCATION, &mut info)?;
+            let length = info.szPath.iter().position(|&unit| unit == 0);
+            let path = &info.szPath[..length.unwrap_or(info.szPath.len())];
+            let bitmap = image_list_icon(info.iSysImageIndex, size)?;
+            Ok((bitmap, PathBuf::from(OsString::from_wide(path))))
+        })();
+
+        CoUninitialize();
+        result
+    }
+}
+
+/// Gets icon `index` of the system image list closest to `size`. COM must be initialized
+unsafe fn image_list_icon(index: i32, size: u32) -> Result<HBITMAP, windows::core::Error> {
+    let image_list_id = match size {
+        0..=16 => SHIL_SMALL,
+        17..=32 => SHIL_LARGE,
+        33..=48 => SHIL_EXTRALARGE,
+        _ => SHIL_JUMBO,
+    };
+    let image_list: IImageList = SHGetImageList(image_list_id as i32)?;
+    let icon = image_list.GetIcon(index, ILD_TRANSPARENT.0)?;
+
+    let mut icon_info = ICONINFO::default();
+    let info_result = GetIconInfo(icon, &mut icon_info);
+    _ = DestroyIcon(icon);
+    info_result?;
+
+    // 32-bit icons carry their alpha in the color bitmap, so the mask isn't needed
+    _ = DeleteObject(icon_info.hbmMask);
+    Ok(icon_info.hbmColor)
+}
+
 /// Gets the recommended icon size for a file
-pub fn get_recommended_icon_size(file_path: &str) -> Result<(u32, u32), windows::core::Error> {
+pub fn get_recommended_icon_size(file_path: &Path) -> Result<(u32, u32), windows::core::Error> {
     unsafe {
         _ = CoInitializeEx(None, COINIT_MULTITHREADED);
 
         let result = {
-            let file_path_wide: Vec<u16> = file_path.encode_utf16().chain(Some(0)).collect();
+            let file_path_wide = to_wide(file_path);
             let mut file_info = SHFILEINFOW::default();
 
             // Get the system icon index
@@ -120,3 +227,8 @@ pub fn get_recommended_icon_size(file_path: &str) -> Result<(u32, u32), windows:
         result
     }
 }
+
+/// Null-terminated UTF-16 path. Goes through `OsStr` so unpaired surrogates survive
+fn to_wide(path: &Path) -> Vec<u16> {
+    path.as_os_str().encode_wide().chain(Some(0)).collect()
+}
@@ -1,8 +1,107 @@
 #[cfg(test)]
 mod test {
+    use crate::backend::freedesktop::FreedesktopBackend;
+    use crate::backend::sandbox::{self, SandboxBackend, HELPER_ENV};
+    use crate::backend::{Backend, BackendError};
+    use crate::caches::load_queue::LoadQueue;
+    use crate::caches::png_cache::PngCache;
+    use crate::freedesktop::desktop_entry::DesktopEntry;
+    use crate::freedesktop::mounts::MountTable;
+    use crate::freedesktop::special_folders::SpecialFolders;
+    use crate::freedesktop::thumbnail_cache::{ThumbnailCache, ThumbnailSize};
     use crate::image::Image;
+    use crate::loader::{LoadError, Loader};
+    use crate::mime::{MimeBasis, MimeGuess};
+    use crate::request::{
+        ColorScheme, FileKind, IconRequest, IconSource, IconState, OutputFormat, BROKEN_LINK_EMBLEM,
+    };
+    use crate::thumbnail::apk::ApkThumbnailer;
+    use crate::thumbnail::appimage::AppImageThumbnailer;
+    use crate::thumbnail::cover_art::{read_pictures, CoverArtThumbnailer};
+    use crate::thumbnail::document::DocumentThumbnailer;
+    use crate::thumbnail::embedded::RawThumbnailer;
+    use crate::thumbnail::executable::{read_icon, ExecutableThumbnailer};
+    use crate::thumbnail::font::FontThumbnailer;
+    use crate::thumbnail::ico::IcoThumbnailer;
+    use crate::thumbnail::package::PackageThumbnailer;
+    use crate::thumbnail::raster::RasterThumbnailer;
+    use crate::thumbnail::svg::SvgThumbnailer;
+    use crate::thumbnail::Thumbnailer;
     use std::env;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
 
+    fn solid_image(request: &IconRequest, value: u8) -> Image {
+        let (width, height) = (request.pixel_width(), request.pixel_height());
+        Image::from_rgba(vec![value; (width * height * 4) as usize], width, height)
+    }
+
+    /// Simulates a shell extension that hangs
+    struct SleepingBackend(Duration);
+
+    impl Backend for SleepingBackend {
+        fn load(&self, request: &IconRequest) -> Result<Image, BackendError> {
+            std::thread::sleep(self.0);
+            Ok(solid_image(request, 0))
+        }
+
+        fn recommended_size(&self, _path: &Path) -> Result<(u32, u32), BackendError> {
+            std::thread::sleep(self.0);
+            Ok((32, 32))
+        }
+    }
+
+    /// Hangs past the timeout, then panics
+    struct PanickingBackend(Duration);
+
+    impl Backend for PanickingBackend {
+        fn load(&self, _request: &IconRequest) -> Result<Image, BackendError> {
+            std::thread::sleep(self.0);
+            panic!("Shell extension crashed");
+        }
+
+        fn recommended_size(&self, _path: &Path) -> Result<(u32, u32), BackendError> {
+            panic!("Shell extension crashed");
+        }
+    }
+
+    /// Records the order of loads. Loads of `gate.png` wait until the gate opens
+    #[derive(Default)]
+    struct GatedBackend {
+        loaded: std::sync::Mutex<Vec<PathBuf>>,
+        open: std::sync::Mutex<bool>,
+        opened: std::sync::Condvar,
+    }
+
+    impl GatedBackend {
+        fn loaded(&self) -> Vec<PathBuf> {
+            self.loaded.lock().unwrap().clone()
+        }
+
+        fn open(&self) {
+            *self.open.lock().unwrap() = true;
+            self.opened.notify_all();
+        }
+    }
+
+    impl Backend for GatedBackend {
+        fn load(&self, request: &IconRequest) -> Result<Image, BackendError> {
+            let path = request.source.as_path().unwrap().to_path_buf();
+            self.loaded.lock().unwrap().push(path.clone());
+            if path == Path::new("gate.png") {
+                let open = self.open.lock().unwrap();
+                drop(self.opened.wait_while(open, |open| !*open).unwrap());
+            }
+            Ok(solid_image(request, 0))
+        }
+
+        fn recommended_size(&self, _path: &Path) -> Result<(u32, u32), BackendError> {
+            Ok((32, 32))
+        }
+    }
+
+    #[cfg(windows)]
     fn get_system32_dir() -> String {
         format!(
             "{}\\System32",
@@ -10,7 +109,29 @@ mod test {
         )
     }
 
+    /// Fails, hangs or lies on demand so crash handling can be tested through the sandbox.
+    /// Reports the encoded length of the path as its recommended width
+    struct CrashingBackend;
+
+    impl Backend for CrashingBackend {
+        fn load(&self, request: &IconRequest) -> Result<Image, BackendError> {
+            match request.source.as_path().and_then(Path::to_str) {
+                Some("crash.exe") => std::process::abort(),
+                Some("hang.exe") => std::thread::sleep(Duration::from_secs(60)),
+                // Fewer pixels than the size calls for
+                Some("short.png") => return Ok(Image::from_rgba(vec![7; 12], 4, 4)),
+                _ => {}
+            }
+            Ok(solid_image(request, 7))
+        }
+
+        fn recommended_size(&self, path: &Path) -> Result<(u32, u32), BackendError> {
+            Ok((path.as_os_str().len() as u32, 1))
+        }
+    }
+
     #[test]
+    #[cfg(windows)]
     fn get_random_file() {
         let path = "MISC.png";
 
@@ -26,6 +147,7 @@ mod test {
     }
 
     #[test]
+    #[cfg(windows)]
     fn test_image_creation() {
         // Test with cmd.exe which should definitely exist
         let test_file = format!("{}\\cmd.exe", get_system32_dir());
@@ -35,6 +157,7 @@ mod test {
     }
 
     #[test]
+    #[cfg(windows)]
     fn test_invalid_file() {
         // Test with a non-existent file
         let test_file = "nonexistent_file_that_should_not_exist_12345.exe";
@@ -43,6 +166,7 @@ mod test {
     }
 
     #[test]
+    #[cfg(windows)]
     fn test_image_dimensions() {
         let test_file = format!("{}\\cmd.exe", get_system32_dir());
         if let Ok(image) = Image::try_new_from_file(&test_file, 32, 32) {
@@ -52,6 +176,7 @@ mod test {
     }
 
     #[test]
+    #[cfg(windows)]
     fn test_base64_conversion() {
         let test_file = format!("{}\\cmd.exe", get_system32_dir());
         if let Ok(image) = Image::try_new_from_file(&test_file, 32, 32) {
@@ -59,4 +184,2328 @@ mod test {
             assert!(!base64.is_empty());
         }
     }
-}
+
+    #[test]
+    #[cfg(windows)]
+    fn test_extension_icon() {
+        // No such file needs to exist
+        let image = Image::for_extension("psd", 48).unwrap();
+        assert_eq!((image.width, image.height), (48, 48));
+        assert!(Image::for_mime_type("text/plain", 16).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_load_queue_cancellation() {
+        // No workers, so nothing leaves the queue unless it is cancelled
+        let queue = LoadQueue::new(Arc::new(PngCache::new(8)), 0);
+        let visible = queue.submit("visible.png", 32, 32, 10);
+        let offscreen = queue.submit("offscreen.png", 32, 32, 0);
+        assert_eq!(queue.pending(), 2);
+
+        offscreen.set_priority(-1);
+        assert_eq!(offscreen.priority(), -1);
+        drop(offscreen);
+        assert_eq!(queue.pending(), 1);
+
+        drop(visible);
+        assert_eq!(queue.pending(), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_load_queue_priorities() {
+        let backend = Arc::new(GatedBackend::default());
+        let cache = PngCache::with_loader(8, Loader::new(backend.clone()));
+        let queue = LoadQueue::new(Arc::new(cache), 1);
+
+        // The one worker is held by the first load while the others queue up behind it
+        let gate = queue.submit("gate.png", 16, 16, 0);
+        let started = Instant::now();
+        while backend.loaded().is_empty() && started.elapsed() < Duration::from_secs(5) {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(backend.loaded(), [PathBuf::from("gate.png")]);
+        let low = queue.submit("low.png", 16, 16, 0);
+        let high = queue.submit("high.png", 16, 16, 10);
+        let raised = queue.submit("raised.png", 16, 16, 0);
+        let dropped = queue.submit("dropped.png", 16, 16, 5);
+        raised.set_priority(20);
+        drop(dropped);
+        assert_eq!(queue.pending(), 3);
+
+        backend.open();
+        for handle in [gate, low, high, raised] {
+            assert!(handle.await.is_some());
+        }
+        let order = ["gate.png", "raised.png", "high.png", "low.png"];
+        assert_eq!(backend.loaded(), order.map(PathBuf::from));
+    }
+
+    #[test]
+    fn test_load_timeout() {
+        let loader = Loader::new(Arc::new(SleepingBackend(Duration::from_secs(5))))
+            .with_timeout(Duration::from_millis(50))
+            .with_max_stuck(1);
+
+        let started = Instant::now();
+        let result = loader.load("hung.png", 32, 32);
+        assert!(matches!(result, Err(LoadError::TimedOut { .. })));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(loader.stuck(), 1);
+
+        // The backend is still hung, so the next load should fail without waiting
+        let result = loader.load("hung.png", 32, 32);
+        assert!(matches!(result, Err(LoadError::Unresponsive { stuck: 1 })));
+    }
+
+    #[test]
+    fn test_hung_load_panics() {
+        let loader = Loader::new(Arc::new(PanickingBackend(Duration::from_millis(100))))
+            .with_timeout(Duration::from_millis(20))
+            .with_max_stuck(1);
+        let result = loader.load("hung.png", 32, 32);
+        assert!(matches!(result, Err(LoadError::TimedOut { .. })));
+        assert_eq!(loader.stuck(), 1);
+
+        // The panic frees the slot like a late result would
+        let started = Instant::now();
+        while loader.stuck() > 0 && started.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(loader.stuck(), 0);
+        let result = loader.recommended_size("crash.exe");
+        assert!(matches!(result, Err(LoadError::Backend(_))));
+        assert_eq!(loader.stuck(), 0);
+    }
+
+    #[test]
+    fn test_load_within_timeout() {
+        let loader = Loader::new(Arc::new(SleepingBackend(Duration::from_millis(1))))
+            .with_timeout(Duration::from_secs(5));
+        let image = loader.load_recommended("fast.png").unwrap();
+        assert_eq!((image.width, image.height), (32, 32));
+        assert_eq!(loader.stuck(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cache_timeout_returns_fallback() {
+        let loader = Loader::new(Arc::new(SleepingBackend(Duration::from_secs(5))))
+            .with_timeout(Duration::from_millis(50));
+        let cache = PngCache::with_loader(8, loader);
+
+        let image = cache.get("hung.png", 24, 24).await.unwrap();
+        assert_eq!((image.width, image.height), (24, 24));
+        assert!(image.as_base64_png().is_ok());
+        // Fallbacks are not cached so the real icon can be loaded later
+        assert!(cache.is_empty().await);
+    }
+
+    /// Entry point for sandbox helpers started by `test_sandbox_backend`. Does nothing otherwise
+    #[test]
+    fn sandbox_helper_entry() {
+        if env::var_os(HELPER_ENV).is_some() {
+            sandbox::run_helper(Arc::new(CrashingBackend));
+        }
+    }
+
+    #[test]
+    fn test_sandbox_backend() {
+        let backend = SandboxBackend::new(1)
+            .unwrap()
+            .with_program(
+                env::current_exe().unwrap(),
+                [
+                    "tests::test::sandbox_helper_entry",
+                    "--exact",
+                    "--test-threads=1",
+                ],
+            )
+            .with_memory_limit(Some(4 * 1024 * 1024 * 1024))
+            .with_timeout(Some(Duration::from_secs(2)));
+
+        let request = IconRequest::new("ok.png", 4, 4)
+            .with_scale(2)
+            .with_theme("Adwaita")
+            .with_overlay("emblem-shared");
+        let image = backend.load(&request).unwrap();
+        assert_eq!((image.width, image.height), (8, 8));
+        assert!(image.as_rgba().iter().all(|&byte| byte == 7));
+        assert_eq!(backend.recommended_size(Path::new("ok.png")).unwrap(), (6, 1));
+
+        // A crash is reported as an error and the helper is replaced on the next request
+        assert!(backend.load(&IconRequest::new("crash.exe", 4, 4)).is_err());
+        assert_eq!(backend.restarts(), 1);
+        assert!(backend.load(&IconRequest::new("ok.png", 2, 2)).is_ok());
+        assert!(backend
+            .load(&IconRequest::for_mime_type("image/png", 2, 2))
+            .is_ok());
+
+        // So are helpers that hang or send images that don't add up
+        let started = Instant::now();
+        let error = backend.load(&IconRequest::new("hang.exe", 4, 4)).unwrap_err();
+        assert!(error.to_string().contains("in time"), "{}", error);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(backend.load(&IconRequest::new("short.png", 4, 4)).is_err());
+        assert_eq!(backend.restarts(), 3);
+        assert!(backend.load(&IconRequest::new("ok.png", 2, 2)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_icon_request_cache_key() {
+        let loader = Loader::new(Arc::new(SleepingBackend(Duration::ZERO)));
+        let cache = PngCache::with_loader(8, loader);
+
+        let request = IconRequest::new("folder", 16, 16).with_scale(2);
+        let image = cache.get_request(&request).await.unwrap();
+        assert_eq!((image.width, image.height), (32, 32));
+        cache.get_request(&request).await.unwrap();
+        assert_eq!(cache.len().await, 1);
+
+        // Each differing field is a separate entry
+        let variants = [
+            request.clone().with_color_scheme(ColorScheme::Dark),
+            request.clone().with_state(IconState::Open),
+            request.clone().with_overlay("emblem-symbolic-link"),
+            request.clone().type_icon_only(true),
+            request.clone().ignore_custom_icon(true),
+            request.clone().with_format(OutputFormat::Png),
+            request.clone().with_fractional_scale(1.25),
+        ];
+        for variant in &variants {
+            cache.get_request(variant).await.unwrap();
+        }
+        assert_eq!(cache.len().await, 1 + variants.len());
+
+        let png = image.encode(OutputFormat::Png).unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+
+        // Fractional scales round the pixel size up and look in the next larger theme scale
+        let request = IconRequest::new("folder", 16, 15).with_fractional_scale(1.25);
+        assert_eq!((request.pixel_width(), request.pixel_height()), (20, 19));
+        assert_eq!(request.theme_scale(), 2);
+        assert_eq!(IconRequest::new("folder", 16, 16).with_scale(2).theme_scale(), 2);
+        let huge = IconRequest::new("folder", u32::MAX, 1).with_scale(u32::MAX);
+        assert_eq!(huge.pixel_width(), u32::MAX);
+        let request = IconRequest::new("folder", 16, 16).with_fractional_scale(f32::NAN);
+        assert_eq!(request.scale_percent, 100);
+
+        // Fields the backend ignores don't split entries
+        let cache = PngCache::with_loader(8, Loader::new(Arc::new(ThemelessBackend)));
+        let request = IconRequest::for_extension("txt", 16, 16);
+        cache.get_request(&request).await.unwrap();
+        cache.get_request(&request.clone().with_theme("Adwaita")).await.unwrap();
+        assert_eq!(cache.len().await, 1);
+    }
+
+    /// Has one theme only, like the Windows shell
+    struct ThemelessBackend;
+
+    impl Backend for ThemelessBackend {
+        fn load(&self, request: &IconRequest) -> Result<Image, BackendError> {
+            Ok(solid_image(request, 3))
+        }
+
+        fn recommended_size(&self, _path: &Path) -> Result<(u32, u32), BackendError> {
+            Ok((32, 32))
+        }
+
+        fn cache_key(&self, request: &IconRequest) -> IconRequest {
+            IconRequest {
+                theme: None,
+                ..request.clone()
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_non_utf8_paths() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        // Latin-1 "café.txt", as left behind by old Samba shares
+        let path = Path::new(OsStr::from_bytes(b"caf\xe9.txt"));
+        let lossy = path.to_string_lossy().into_owned();
+        assert_ne!(IconRequest::new(path, 16, 16), IconRequest::new(&lossy, 16, 16));
+
+        let backend = SandboxBackend::new(1).unwrap().with_program(
+            env::current_exe().unwrap(),
+            [
+                "tests::test::sandbox_helper_entry",
+                "--exact",
+                "--test-threads=1",
+            ],
+        );
+        // The helper sees the original 8 bytes, not the lossy replacement character
+        assert_eq!(backend.recommended_size(path).unwrap(), (8, 1));
+    }
+
+    #[tokio::test]
+    async fn test_type_icons_are_cached_by_type() {
+        let loader = Loader::new(Arc::new(SleepingBackend(Duration::ZERO)));
+        let cache = PngCache::with_loader(8, loader);
+
+        cache
+            .get_request(&IconRequest::for_extension(".PSD", 48, 48))
+            .await
+            .unwrap();
+        cache
+            .get_request(&IconRequest::for_extension("psd", 48, 48))
+            .await
+            .unwrap();
+        assert_eq!(cache.len().await, 1);
+
+        let mime = IconRequest::for_mime_type("Image/PNG", 48, 48);
+        cache.get_request(&mime).await.unwrap();
+        assert_eq!(cache.len().await, 2);
+
+        assert_eq!(mime.source.extension().as_deref(), Some("png"));
+        let source = IconSource::Path("photo.JPG".into());
+        assert_eq!(source.mime_type().as_deref(), Some("image/jpeg"));
+    }
+
+    /// Writes a small icon theme tree under a fresh temporary directory
+    fn write_fixture(name: &str, files: &[(&str, &[u8])]) -> PathBuf {
+        let root = env::temp_dir().join(format!("getfileicon-{}-{}", name, std::process::id()));
+        _ = std::fs::remove_dir_all(&root);
+        for (path, contents) in files {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn test_icon_name_lookup() {
+        let png = Image::from_rgba(vec![255; 16 * 16 * 4], 16, 16)
+            .to_png()
+            .unwrap();
+        let root = write_fixture(
+            "themes",
+            &[
+                (
+                    "icons/Child/index.theme",
+                    b"[Icon Theme]\nInherits=Parent\nDirectories=16x16/mimetypes\n\n\
+                      [16x16/mimetypes]\nSize=16\n",
+                ),
+                (
+                    "icons/Parent/index.theme",
+                    b"[Icon Theme]\nDirectories=16x16/mimetypes,scalable/places\n\n\
+                      [16x16/mimetypes]\nSize=16\n\n\
+                      [scalable/places]\nSize=48\nType=Scalable\nMinSize=8\nMaxSize=512\n",
+                ),
+                ("icons/Parent/16x16/mimetypes/text.png", &png),
+                (
+                    "icons/Parent/scalable/places/folder.svg",
+                    b"<svg xmlns='http://www.w3.org/2000/svg' width='16' height='16'>\
+                      <rect width='16' height='16' fill='#00f'/></svg>",
+                ),
+                (
+                    "icons/hicolor/index.theme",
+                    b"[Icon Theme]\nDirectories=16x16/apps\n\n[16x16/apps]\nSize=16\n",
+                ),
+                ("icons/hicolor/16x16/apps/unknown.png", &png),
+                (
+                    "icons/Vast/index.theme",
+                    b"[Icon Theme]\nDirectories=huge,doubled\n\n\
+                      [huge]\nSize=4294967295\n\n[doubled]\nSize=4294967295\nScale=2\n",
+                ),
+                ("icons/Vast/doubled/vast.png", &png),
+            ],
+        );
+        let backend =
+            FreedesktopBackend::with_dirs(vec![root.join("icons")], &[root.join("mime")], "Child");
+        // Sizes in the index are compared without overflowing
+        let image = backend
+            .load(&IconRequest::for_icon_name("vast", 16, 16).with_theme("Vast"))
+            .unwrap();
+        assert!(image.origin().unwrap().ends_with("doubled/vast.png"));
+
+        // No text-x-rust or text-x icon, so the name is shortened until the parent's `text` matches
+        let image = backend
+            .load(&IconRequest::for_icon_name("text-x-rust", 16, 16))
+            .unwrap();
+        let text_icon = root.join("icons/Parent/16x16/mimetypes/text.png");
+        assert_eq!(image.origin(), Some(text_icon.as_path()));
+
+        // Extensions go through the MIME type. Unknown types end at hicolor's `unknown`
+        let image = backend
+            .load(&IconRequest::for_extension("rs", 16, 16))
+            .unwrap();
+        assert_eq!(image.origin(), Some(text_icon.as_path()));
+        let image = backend
+            .load(&IconRequest::for_extension("nope", 16, 16))
+            .unwrap();
+        assert_eq!(
+            image.origin(),
+            Some(root.join("icons/hicolor/16x16/apps/unknown.png").as_path())
+        );
+
+        // SVGs are rendered at the pixel size, not scaled up from their own
+        let image = backend
+            .load(&IconRequest::for_icon_name("folder-open", 32, 32).with_scale(2))
+            .unwrap();
+        assert_eq!((image.width, image.height), (64, 64));
+        assert_eq!(&image.as_rgba()[..4], &[0, 0, 255, 255]);
+        assert!(image.origin().unwrap().ends_with("folder.svg"));
+
+        _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_application_icons() {
+        let png = Image::from_rgba(vec![255; 16 * 16 * 4], 16, 16).to_png().unwrap();
+        let root = write_fixture(
+            "applications",
+            &[
+                (
+                    "icons/hicolor/index.theme",
+                    b"[Icon Theme]\nDirectories=16x16/apps\n\n[16x16/apps]\nSize=16\n",
+                ),
+                ("icons/hicolor/16x16/apps/org.example.Editor.png", &png),
+                ("icons/hicolor/16x16/apps/application-x-executable.png", &png),
+                (
+                    "applications/org.example.Editor.desktop",
+                    b"[Desktop Entry]\nType=Application\nName=Editor\nName[de]=Bearbeiter\n\
+                      Name[de_CH]=Editor\\sCH\nIcon=org.example.Editor.png\n",
+                ),
+                (
+                    "applications/org.example.Gone.desktop",
+                    b"[Desktop Entry]\nName=Gone\nIcon=org.example.Editor\n\
+                      TryExec=getfileicon-missing-program\n",
+                ),
+                (
+                    "applications/org.example.Deleted.desktop",
+                    b"[Desktop Entry]\nName=Deleted\nIcon=org.example.Editor\nHidden=true\n",
+                ),
+                (
+                    "applications/vendor/viewer.desktop",
+                    b"[Desktop Entry]\nName=Viewer\nIcon=viewer-not-installed\n",
+                ),
+                (
+                    "swcatalog/xml/test.xml",
+                    b"<components origin=\"test-main\"><component type=\"desktop-application\">\
+                      <id>org.example.Viewer</id>\
+                      <launchable type=\"desktop-id\">vendor-viewer.desktop</launchable>\
+                      <icon type=\"cached\" width=\"64\" height=\"64\">viewer.png</icon>\
+                      <icon type=\"cached\" width=\"4294967295\" height=\"4294967295\" \
+                      scale=\"2\">viewer.png</icon>\
+                      </component></components>",
+                ),
+                ("swcatalog/icons/test-main/64x64/viewer.png", &png),
+                ("swcatalog/icons/test-main/4294967295x4294967295@2/viewer.png", &png),
+                (
+                    "swcatalog/yaml/test.yml",
+                    b"---\nFile: DEP-11\nOrigin: test-main\n---\nType: desktop-application\n\
+                      ID: org.example.Player\nIcon:\n  stock: org.example.Editor\n",
+                ),
+            ],
+        );
+
+        let applications = root.join("applications");
+        let entry = DesktopEntry::find_in(&[applications], "org.example.Editor.desktop").unwrap();
+        assert_eq!(entry.id(), "org.example.Editor");
+        assert_eq!(entry.name_for_locale("de_DE.UTF-8").as_deref(), Some("Bearbeiter"));
+        assert_eq!(entry.name_for_locale("de_CH").as_deref(), Some("Editor CH"));
+        assert_eq!(entry.name_for_locale("fr_FR").as_deref(), Some("Editor"));
+        assert!(entry.is_installed() && !entry.is_hidden());
+        let gone = DesktopEntry::find_in(&[root.join("applications")], "org.example.Gone");
+        assert!(!gone.unwrap().is_installed());
+
+        let backend = FreedesktopBackend::with_dirs(vec![root.join("icons")], &[], "hicolor")
+            .with_application_dirs(vec![root.join("applications")], vec![root.join("swcatalog")]);
+        let image = backend
+            .load(&IconRequest::for_application("org.example.Editor", 16, 16))
+            .unwrap();
+        assert!(image.origin().unwrap().ends_with("apps/org.example.Editor.png"));
+
+        // The entry lives in a subdirectory and its icon isn't installed, so the cached
+        // AppStream icon closest in size is used. Catalog sizes are compared without overflow
+        let image = backend
+            .load(&IconRequest::for_application("vendor-viewer", 16, 16))
+            .unwrap();
+        assert_eq!(
+            image.origin(),
+            Some(root.join("swcatalog/icons/test-main/64x64/viewer.png").as_path())
+        );
+
+        let image = backend
+            .load(&IconRequest::for_application("org.example.Player", 16, 16))
+            .unwrap();
+        assert!(image.origin().unwrap().ends_with("apps/org.example.Editor.png"));
+
+        // Uninstalled and deleted applications get the generic icon, like unknown ones
+        for id in ["org.example.Missing", "org.example.Gone", "org.example.Deleted"] {
+            let image = backend.load(&IconRequest::for_application(id, 16, 16)).unwrap();
+            assert!(image.origin().unwrap().ends_with("application-x-executable.png"), "{}", id);
+        }
+
+        _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_thumbnail_cache() {
+        let root = write_fixture("thumbnails", &[("photo.jpg", b"not really a photo")]);
+        let photo = root.join("photo.jpg");
+        let cache = ThumbnailCache::new(root.join("cache"));
+        assert!(cache.lookup(&photo, ThumbnailSize::Normal).is_none());
+
+        // Stored at most 256 pixels wide with the aspect ratio kept, then found for smaller sizes
+        let wide = Image::from_rgba(vec![200; 400 * 200 * 4], 400, 200);
+        cache
+            .store(&photo, &wide, ThumbnailSize::Large, Some("image/jpeg"))
+            .unwrap();
+        let thumbnail = cache.lookup(&photo, ThumbnailSize::Normal).unwrap();
+        assert_eq!((thumbnail.width, thumbnail.height), (256, 128));
+        assert!(thumbnail.origin().unwrap().starts_with(root.join("cache/large")));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = thumbnail.origin().unwrap().metadata().unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // The backend serves it fitted into the requested box
+        let backend = FreedesktopBackend::with_dirs(Vec::new(), &[], "hicolor")
+            .with_thumbnail_cache(Some(cache.clone()));
+        let image = backend.load(&IconRequest::new(&photo, 64, 64)).unwrap();
+        assert_eq!((image.width, image.height), (64, 64));
+        assert_eq!(image.origin(), thumbnail.origin());
+        assert!(backend
+            .load(&IconRequest::new(&photo, 64, 64).type_icon_only(true))
+            .is_err());
+
+        // Threads storing the same thumbnail at once each write their own temporary file
+        std::thread::scope(|scope| {
+            for value in 0..8 {
+                let (cache, photo) = (&cache, &photo);
+                scope.spawn(move || {
+                    let image = Image::from_rgba(vec![value; 300 * 300 * 4], 300, 300);
+                    cache.store(photo, &image, ThumbnailSize::Large, None).unwrap();
+                });
+            }
+        });
+        let thumbnail = cache.lookup(&photo, ThumbnailSize::Large).unwrap();
+        let first = thumbnail.as_rgba()[0];
+        assert!(thumbnail.as_rgba().iter().all(|&byte| byte == first));
+        let directory = thumbnail.origin().unwrap().parent().unwrap().read_dir().unwrap();
+        assert_eq!(directory.count(), 1);
+
+        // A changed file invalidates the thumbnail and any recorded failure
+        cache.store_failure(&photo).unwrap();
+        assert!(cache.has_failed(&photo));
+        let file = std::fs::File::options().write(true).open(&photo).unwrap();
+        file.set_modified(std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(86400))
+            .unwrap();
+        assert!(cache.lookup(&photo, ThumbnailSize::Normal).is_none());
+        assert!(!cache.has_failed(&photo));
+
+        _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_image_thumbnails() {
+        // A landscape JPEG with EXIF orientation 6, shown rotated to portrait by viewers
+        let mut jpeg = Vec::new();
+        image::DynamicImage::new_rgb8(40, 20)
+            .write_to(&mut std::io::Cursor::new(&mut jpeg), image::ImageFormat::Jpeg)
+            .unwrap();
+        let exif: &[u8] = b"\xff\xe1\x00\x22Exif\x00\x00II*\x00\x08\x00\x00\x00\x01\x00\
+                            \x12\x01\x03\x00\x01\x00\x00\x00\x06\x00\x00\x00\x00\x00\x00\x00";
+        jpeg.splice(2..2, exif.iter().copied());
+        let root = write_fixture("photos", &[("photo.jpg", &jpeg)]);
+        let photo = root.join("photo.jpg");
+
+        let thumbnailer = RasterThumbnailer::new();
+        assert!(thumbnailer.supports("image/jpeg"));
+        let thumbnail = thumbnailer.thumbnail(&photo, 10).unwrap();
+        assert_eq!((thumbnail.width, thumbnail.height), (5, 10));
+        assert!(thumbnail.is_thumbnail());
+
+        // Too many pixels is refused before decoding
+        let limited = RasterThumbnailer::new().with_max_pixels(799);
+        assert!(limited.thumbnail(&photo, 10).is_err());
+
+        // Made on first use, then stored in the cache for the desktop and later lookups
+        let cache = ThumbnailCache::new(root.join("cache"));
+        let backend = FreedesktopBackend::with_dirs(Vec::new(), &[], "hicolor")
+            .with_thumbnail_cache(Some(cache.clone()));
+        let image = backend.load(&IconRequest::new(&photo, 64, 64)).unwrap();
+        assert!(image.is_thumbnail());
+        assert_eq!((image.width, image.height), (64, 64));
+        let cached = cache.lookup(&photo, ThumbnailSize::Normal).unwrap();
+        assert_eq!((cached.width, cached.height), (20, 40));
+
+        _ = std::fs::remove_dir_all(root);
+    }
+
+    fn solid_jpeg(width: u32, height: u32, color: [u8; 3]) -> Vec<u8> {
+        let pixels = image::RgbImage::from_pixel(width, height, image::Rgb(color));
+        let mut jpeg = Vec::new();
+        image::DynamicImage::ImageRgb8(pixels)
+            .write_to(&mut std::io::Cursor::new(&mut jpeg), image::ImageFormat::Jpeg)
+            .unwrap();
+        jpeg
+    }
+
+    /// A little endian TIFF IFD of LONG entries, followed by the offset of the next IFD
+    fn tiff_ifd(entries: &[(u16, u32)], next: u32) -> Vec<u8> {
+        let mut ifd = (entries.len() as u16).to_le_bytes().to_vec();
+        for (tag, value) in entries {
+            ifd.extend(tag.to_le_bytes());
+            ifd.extend(4u16.to_le_bytes());
+            ifd.extend(1u32.to_le_bytes());
+            ifd.extend(value.to_le_bytes());
+        }
+        ifd.extend(next.to_le_bytes());
+        ifd
+    }
+
+    #[test]
+    fn test_embedded_previews() {
+        // A 400x200 black photo whose EXIF IFD1 holds a red 160x80 thumbnail
+        let thumbnail = solid_jpeg(160, 80, [255, 0, 0]);
+        let mut tiff = b"II*\x00\x08\x00\x00\x00".to_vec();
+        tiff.extend(tiff_ifd(&[(0x0112, 1)], 26));
+        tiff.extend(tiff_ifd(&[(0x0201, 56), (0x0202, thumbnail.len() as u32)], 0));
+        tiff.extend(&thumbnail);
+        let mut app1 = vec![0xff, 0xe1];
+        app1.extend((tiff.len() as u16 + 8).to_be_bytes());
+        app1.extend(b"Exif\x00\x00");
+        app1.extend(&tiff);
+        let mut photo = solid_jpeg(400, 200, [0, 0, 0]);
+        photo.splice(2..2, app1);
+
+        // A raw file that only has a JPEG preview in IFD0
+        let preview = solid_jpeg(64, 48, [0, 0, 255]);
+        let mut raw = b"II*\x00\x08\x00\x00\x00".to_vec();
+        raw.extend(tiff_ifd(
+            &[(0x0103, 6), (0x0111, 50), (0x0117, preview.len() as u32)],
+            0,
+        ));
+        raw.extend(&preview);
+
+        let root = write_fixture("previews", &[("photo.jpg", &photo), ("photo.dng", &raw)]);
+        let red = |image: &Image| image.as_rgba()[0] > 200 && image.as_rgba()[2] < 50;
+
+        // The embedded thumbnail is big enough for 100 pixels, but not for 300
+        let thumbnailer = RasterThumbnailer::new();
+        let small = thumbnailer.thumbnail(&root.join("photo.jpg"), 100).unwrap();
+        assert_eq!((small.width, small.height), (100, 50));
+        assert!(red(&small));
+        let large = thumbnailer.thumbnail(&root.join("photo.jpg"), 300).unwrap();
+        assert_eq!((large.width, large.height), (300, 150));
+        assert!(!red(&large));
+
+        // Raw files use their largest preview even when it is smaller than asked for
+        assert!(RawThumbnailer.supports("image/x-adobe-dng"));
+        let raw = RawThumbnailer.thumbnail(&root.join("photo.dng"), 128).unwrap();
+        assert_eq!((raw.width, raw.height), (64, 48));
+        assert!(raw.as_rgba()[2] > 200);
+
+        _ = std::fs::remove_dir_all(root);
+    }
+
+    /// A FLAC `PICTURE` block body, also used base64 encoded in Vorbis comments
+    fn flac_picture(kind: u32, data: &[u8]) -> Vec<u8> {
+        let mut block = kind.to_be_bytes().to_vec();
+        block.extend(10u32.to_be_bytes());
+        block.extend(b"image/jpeg");
+        block.extend(0u32.to_be_bytes());
+        block.extend([0; 16]);
+        block.extend((data.len() as u32).to_be_bytes());
+        block.extend(data);
+        block
+    }
+
+    fn mp4_atom(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut atom = (body.len() as u32 + 8).to_be_bytes().to_vec();
+        atom.extend(kind);
+        atom.extend(body);
+        atom
+    }
+
+    #[test]
+    fn test_cover_art() {
+        use base64::Engine;
+
+        let front = solid_jpeg(40, 20, [255, 0, 0]);
+        let back = solid_jpeg(20, 20, [0, 0, 255]);
+
+        // ID3v2.3 with the back cover stored before the front cover
+        let mut frames = Vec::new();
+        for (kind, data) in [(4u8, &back), (3u8, &front)] {
+            let mut body = vec![0];
+            body.extend(b"image/jpeg\x00");
+            body.push(kind);
+            body.extend(b"cover\x00");
+            body.extend(data.iter());
+            frames.extend(b"APIC");
+            frames.extend((body.len() as u32).to_be_bytes());
+            frames.extend([0, 0]);
+            frames.extend(body);
+        }
+        frames.extend([0; 16]);
+        let mut mp3 = b"ID3\x03\x00\x00".to_vec();
+        let size = frames.len() as u32;
+        mp3.extend((0..4).rev().map(|shift| (size >> (shift * 7)) as u8 & 0x7f));
+        mp3.extend(frames);
+        mp3.extend([0xff, 0xfb, 0x90, 0x00]);
+
+        // STREAMINFO, then the last block holding the picture
+        let picture = flac_picture(3, &front);
+        let mut flac = b"fLaC\x00\x00\x00\x22".to_vec();
+        flac.extend([0; 34]);
+        flac.push(0x86);
+        flac.extend(&(picture.len() as u32).to_be_bytes()[1..]);
+        flac.extend(&picture);
+
+        // An identification page and a comment page, each a single packet
+        let mut ogg = Vec::new();
+        let encoded = base64::engine::general_purpose::STANDARD.encode(&picture);
+        let comment = format!("METADATA_BLOCK_PICTURE={}", encoded);
+        let mut tags = b"OpusTags\x04\x00\x00\x00test\x02\x00\x00\x00".to_vec();
+        for comment in ["TITLE=Song", comment.as_str()] {
+            tags.extend((comment.len() as u32).to_le_bytes());
+            tags.extend(comment.as_bytes());
+        }
+        for (sequence, packet) in [b"OpusHead\x01\x02".to_vec(), tags].iter().enumerate() {
+            let mut lacing = vec![255; packet.len() / 255];
+            lacing.push((packet.len() % 255) as u8);
+            ogg.extend(b"OggS\x00");
+            ogg.push(if sequence == 0 { 2 } else { 0 });
+            ogg.extend([0; 8]);
+            ogg.extend(7u32.to_le_bytes());
+            ogg.extend((sequence as u32).to_le_bytes());
+            ogg.extend([0; 4]);
+            ogg.push(lacing.len() as u8);
+            ogg.extend(lacing);
+            ogg.extend(packet);
+        }
+
+        // iTunes style moov/udta/meta/ilst/covr/data
+        let mut data = 13u32.to_be_bytes().to_vec();
+        data.extend([0; 4]);
+        data.extend(&front);
+        let ilst = mp4_atom(b"ilst", &mp4_atom(b"covr", &mp4_atom(b"data", &data)));
+        let mut meta = vec![0; 4];
+        meta.extend(mp4_atom(b"hdlr", &[0; 25]));
+        meta.extend(ilst);
+        let moov = mp4_atom(b"moov", &mp4_atom(b"udta", &mp4_atom(b"meta", &meta)));
+        let mut m4a = mp4_atom(b"ftyp", b"M4A \x00\x00\x00\x00");
+        m4a.extend(mp4_atom(b"mdat", &[0; 32]));
+        m4a.extend(moov);
+
+        let root = write_fixture(
+            "cover-art",
+            &[
+                ("song.mp3", &mp3),
+                ("song.flac", &flac),
+                ("song.opus", &ogg),
+                ("song.m4a", &m4a),
+                ("silence.mp3", &[0xff, 0xfb, 0x90, 0x00]),
+            ],
+        );
+
+        assert_eq!(read_pictures(std::io::Cursor::new(&mp3)).unwrap().len(), 2);
+        for (file, mime_type) in [
+            ("song.mp3", "audio/mpeg"),
+            ("song.flac", "audio/flac"),
+            ("song.opus", "audio/x-opus+ogg"),
+            ("song.m4a", "audio/mp4"),
+        ] {
+            assert!(CoverArtThumbnailer.supports(mime_type));
+            let image = CoverArtThumbnailer.thumbnail(&root.join(file), 16).unwrap();
+            assert_eq!((image.width, image.height), (16, 8), "{}", file);
+            assert!(image.as_rgba()[0] > 200 && image.as_rgba()[2] < 50, "{}", file);
+            assert!(image.is_thumbnail());
+            assert_eq!(image.origin(), Some(root.join(file).as_path()));
+        }
+        assert!(CoverArtThumbnailer
+            .thumbnail(&root.join("silence.mp3"), 16)
+            .is_err());
+
+        _ = std::fs::remove_dir_all(root);
+    }
+
+    fn zip_file(members: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, contents) in members {
+            writer
+                .start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            std::io::Write::write_all(&mut writer, contents).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_document_thumbnails() {
+        let jpeg = solid_jpeg(40, 20, [255, 0, 0]);
+        let mut png = Vec::new();
+        let pixels = image::RgbImage::from_pixel(40, 20, image::Rgb([255, 0, 0]));
+        image::DynamicImage::ImageRgb8(pixels)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let rels = |target: &str| {
+            format!(
+                r#"<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+                    <Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="word/document.xml"/>
+                    <Relationship Id="rId2" Type="http://schemas.openxmlformats.org/package/2006/relationships/metadata/thumbnail" Target="{}"/>
+                </Relationships>"#,
+                target
+            )
+        };
+        let docx_rels = rels("docProps/thumbnail.jpeg");
+        let model_rels = rels("/Metadata/thumbnail.png");
+        let docx = zip_file(&[
+            ("_rels/.rels", docx_rels.as_bytes()),
+            ("word/document.xml", b"<document/>"),
+            ("docProps/thumbnail.jpeg", &jpeg),
+        ]);
+        let model = zip_file(&[
+            ("_rels/.rels", model_rels.as_bytes()),
+            ("Metadata/thumbnail.png", &png),
+        ]);
+        let odt = zip_file(&[
+            ("mimetype", b"application/vnd.oasis.opendocument.text"),
+            ("Thumbnails/thumbnail.png", &png),
+        ]);
+        // EPUB 2 names the cover in the metadata; its href is relative to the package
+        let epub = zip_file(&[
+            ("mimetype", b"application/epub+zip"),
+            (
+                "META-INF/container.xml",
+                br#"<container><rootfiles><rootfile full-path="OEBPS/content.opf"/></rootfiles></container>"#,
+            ),
+            (
+                "OEBPS/content.opf",
+                br#"<package xmlns="http://www.idpf.org/2007/opf">
+                    <metadata><meta name="cover" content="cover-image"/></metadata>
+                    <manifest>
+                        <item id="chapter" href="chapter.xhtml" media-type="application/xhtml+xml"/>
+                        <item id="cover-image" href="../images/cover%20art.jpg" media-type="image/jpeg"/>
+                    </manifest>
+                </package>"#,
+            ),
+            ("images/cover art.jpg", &jpeg),
+        ]);
+        let plain = zip_file(&[("word/document.xml", b"<document/>")]);
+        let root = write_fixture(
+            "documents",
+            &[
+                ("report.docx", &docx),
+                ("part.3mf", &model),
+                ("notes.odt", &odt),
+                ("book.epub", &epub),
+                ("plain.docx", &plain),
+            ],
+        );
+
+        for (file, mime_type) in [
+            (
+                "report.docx",
+                "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+            ),
+            ("part.3mf", "model/3mf"),
+            ("notes.odt", "application/vnd.oasis.opendocument.text"),
+            ("book.epub", "application/epub+zip"),
+        ] {
+            assert!(DocumentThumbnailer.supports(mime_type));
+            let image = DocumentThumbnailer.thumbnail(&root.join(file), 16).unwrap();
+            assert_eq!((image.width, image.height), (16, 8), "{}", file);
+            assert!(image.as_rgba()[0] > 200 && image.as_rgba()[2] < 50, "{}", file);
+            assert!(image.is_thumbnail());
+        }
+        // Without a preview the backend falls back to the type icon
+        assert!(DocumentThumbnailer
+            .thumbnail(&root.join("plain.docx"), 16)
+            .is_err());
+
+        _ = std::fs::remove_dir_all(root);
+    }
+
+    /// The tables of a TrueType font whose `A` and `a` are the same rectangle
+    fn rectangle_font_tables() -> Vec<(&'static [u8; 4], Vec<u8>)> {
+        let mut head = vec![0; 54];
+        head[..4].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+        head[12..16].copy_from_slice(&0x5f0f_3cf5u32.to_be_bytes());
+        head[18..20].copy_from_slice(&1000u16.to_be_bytes());
+        head[50..52].copy_from_slice(&1u16.to_be_bytes());
+        let mut hhea = vec![0; 36];
+        hhea[..4].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+        hhea[4..6].copy_from_slice(&800i16.to_be_bytes());
+        hhea[6..8].copy_from_slice(&(-200i16).to_be_bytes());
+        hhea[34..36].copy_from_slice(&3u16.to_be_bytes());
+        let maxp = [0x0000_5000u32.to_be_bytes().as_slice(), &3u16.to_be_bytes()].concat();
+        let hmtx = [[1, 244, 0, 0], [1, 244, 0, 100], [1, 244, 0, 100]].concat();
+
+        // Format 4 with segments for `A`, `a` and the required final 0xFFFF
+        let words: [u16; 20] = [
+            4, 40, 0, 6, 4, 1, 2, // header
+            0x41, 0x61, 0xffff, 0, // end codes and padding
+            0x41, 0x61, 0xffff, // start codes
+            1u16.wrapping_sub(0x41), 2u16.wrapping_sub(0x61), 1, // deltas
+            0, 0, 0, // range offsets
+        ];
+        let mut cmap = vec![0, 0, 0, 1, 0, 3, 0, 1, 0, 0, 0, 12];
+        cmap.extend(words.iter().flat_map(|word| word.to_be_bytes()));
+
+        let mut rectangle = Vec::new();
+        for word in [1, 100, 0, 400, 500, 3, 0] {
+            rectangle.extend((word as i16).to_be_bytes());
+        }
+        rectangle.extend([1, 1, 1, 1]);
+        for delta in [100, 0, 300, 0, 0, 500, 0, -500] {
+            rectangle.extend((delta as i16).to_be_bytes());
+        }
+        rectangle.resize(36, 0);
+        let glyf = [rectangle.clone(), rectangle].concat();
+        let loca = [0u32, 0, 36, 72]
+            .iter()
+            .flat_map(|offset| offset.to_be_bytes())
+            .collect();
+        vec![
+            (b"cmap", cmap),
+            (b"head", head),
+            (b"hhea", hhea),
+            (b"hmtx", hmtx),
+            (b"maxp", maxp),
+            (b"glyf", glyf),
+            (b"loca", loca),
+        ]
+    }
+
+    #[test]
+    fn test_font_previews() {
+        let tables = rectangle_font_tables();
+        let mut ttf = [0x0001_0000u32.to_be_bytes(), [0, 7, 0, 64]].concat();
+        ttf.extend([0, 2, 0, 48]);
+        let mut sorted = tables.clone();
+        sorted.sort_by_key(|(tag, _)| **tag);
+        let mut offset = 12 + 16 * sorted.len();
+        for (tag, data) in &sorted {
+            ttf.extend(*tag);
+            ttf.extend([0; 4]);
+            ttf.extend((offset as u32).to_be_bytes());
+            ttf.extend((data.len() as u32).to_be_bytes());
+            offset += data.len().next_multiple_of(4);
+        }
+        for (_, data) in &sorted {
+            ttf.extend(data);
+            ttf.resize(ttf.len().next_multiple_of(4), 0);
+        }
+
+        // WOFF2 with transformed `glyf`, `loca` and `hmtx`. Each rectangle is four points
+        // whose triplets take a byte each, then an instruction length of zero
+        let mut glyf: Vec<u8> = [0u16, 0, 3, 1]
+            .iter()
+            .flat_map(|word| word.to_be_bytes())
+            .collect();
+        for length in [6u32, 2, 8, 10, 0, 4, 0] {
+            glyf.extend(length.to_be_bytes());
+        }
+        glyf.extend([0, 0, 0, 1, 0, 1, 4, 4]);
+        glyf.extend([11, 3, 13, 2, 11, 3, 13, 2]);
+        glyf.extend([100, 244, 44, 244, 0, 100, 244, 44, 244, 0]);
+        glyf.extend([0; 4]);
+        let hmtx = vec![3, 1, 244, 1, 244, 1, 244];
+        let mut directory = Vec::new();
+        let mut stream = Vec::new();
+        for (tag, data) in &tables {
+            let (index, transformed) = match *tag {
+                b"cmap" => (0, None),
+                b"head" => (1, None),
+                b"hhea" => (2, None),
+                b"hmtx" => (3 | 1 << 6, Some(&hmtx)),
+                b"maxp" => (4, None),
+                b"glyf" => (10, Some(&glyf)),
+                _ => (11, Some(&Vec::new())),
+            };
+            directory.extend([index, data.len() as u8]);
+            match transformed {
+                Some(transformed) => {
+                    directory.push(transformed.len() as u8);
+                    stream.extend(transformed);
+                }
+                None => stream.extend(data),
+            }
+        }
+        // A single stored Brotli meta-block followed by an empty last one
+        let mut brotli = ((stream.len() as u32 - 1) << 4 | 1 << 20).to_le_bytes()[..3].to_vec();
+        brotli.extend(&stream);
+        brotli.push(3);
+        let mut woff2 = b"wOF2".to_vec();
+        woff2.extend(0x0001_0000u32.to_be_bytes());
+        woff2.extend([0; 4]);
+        woff2.extend([0, 7, 0, 0]);
+        woff2.extend((ttf.len() as u32).to_be_bytes());
+        woff2.extend((brotli.len() as u32).to_be_bytes());
+        woff2.extend([0; 24]);
+        woff2.extend(directory);
+        woff2.extend(brotli);
+
+        // More empty tables than an sfnt directory can count
+        let mut crafted = b"wOFF".to_vec();
+        crafted.extend(0x0001_0000u32.to_be_bytes());
+        crafted.extend([0; 4]);
+        crafted.extend(0xffffu16.to_be_bytes());
+        crafted.resize(44 + 0xffff * 20, 0);
+
+        let root = write_fixture(
+            "fonts",
+            &[
+                ("sample.ttf", &ttf),
+                ("sample.woff2", &woff2),
+                ("broken.ttf", b"not a font"),
+                ("crafted.woff", &crafted),
+            ],
+        );
+        let thumbnailer = FontThumbnailer;
+        assert!(thumbnailer.supports("font/woff2"));
+        let preview = thumbnailer.thumbnail(&root.join("sample.ttf"), 64).unwrap();
+        assert_eq!((preview.width, preview.height), (64, 64));
+        assert!(preview.is_thumbnail());
+        let pixel = |x: usize, y: usize| &preview.as_rgba()[(y * 64 + x) * 4..][..4];
+        // Transparent around the page, white paper with dark letters in the middle
+        assert_eq!(pixel(0, 0)[3], 0);
+        assert_eq!(pixel(8, 8), [255, 255, 255, 255]);
+        assert!(pixel(20, 32)[0] < 100);
+
+        let from_woff2 = thumbnailer.thumbnail(&root.join("sample.woff2"), 64).unwrap();
+        assert_eq!(from_woff2.as_rgba(), preview.as_rgba());
+        assert!(thumbnailer.thumbnail(&root.join("broken.ttf"), 64).is_err());
+        assert!(thumbnailer.thumbnail(&root.join("crafted.woff"), 64).is_err());
+
+        _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_svg_documents() {
+        let red = br#"<svg xmlns="http://www.w3.org/2000/svg" width="200" height="100">
+            <rect width="200" height="100" fill="red"/>
+        </svg>"#;
+        let root = write_fixture("svg-documents", &[("red.svg", red)]);
+        // Loading the other file would draw it; documents must not reach outside themselves
+        let linking = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="100" height="100">
+                <image xlink:href="{}" width="100" height="100"/>
+            </svg>"#,
+            root.join("red.svg").display()
+        );
+        std::fs::write(root.join("linking.svg"), linking).unwrap();
+        let many = (0..500).map(|i| format!(r#"<circle cx="{}" cy="50" r="40"/>"#, i % 100));
+        let crowded = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">{}</svg>"#,
+            many.collect::<String>()
+        );
+        std::fs::write(root.join("crowded.svg"), crowded).unwrap();
+
+        let thumbnailer = SvgThumbnailer::new();
+        assert!(thumbnailer.supports("image/svg+xml"));
+        // Fitted to 64 x 32 and centered on a square transparent canvas
+        let image = thumbnailer.thumbnail(&root.join("red.svg"), 64).unwrap();
+        assert_eq!((image.width, image.height), (64, 64));
+        let pixel = |x: usize, y: usize| &image.as_rgba()[(y * 64 + x) * 4..][..4];
+        assert_eq!(pixel(0, 15)[3], 0);
+        assert_eq!(pixel(0, 16), [255, 0, 0, 255]);
+        assert_eq!(pixel(63, 47), [255, 0, 0, 255]);
+        assert_eq!(pixel(63, 48)[3], 0);
+        assert!(image.is_thumbnail());
+
+        let linking = thumbnailer.thumbnail(&root.join("linking.svg"), 64).unwrap();
+        assert!(linking.as_rgba().iter().all(|&value| value == 0));
+        let checkered = SvgThumbnailer::new()
+            .with_checkerboard(true)
+            .thumbnail(&root.join("linking.svg"), 64)
+            .unwrap();
+        assert_eq!(&checkered.as_rgba()[..4], [255, 255, 255, 255]);
+
+        assert!(thumbnailer.thumbnail(&root.join("crowded.svg"), 64).is_ok());
+        let strict = SvgThumbnailer::new().with_max_nodes(100);
+        assert!(strict.thumbnail(&root.join("crowded.svg"), 64).is_err());
+        let impatient = SvgThumbnailer::new().with_timeout(Duration::ZERO);
+        assert!(impatient.thumbnail(&root.join("crowded.svg"), 64).is_err());
+
+        // Renders left running past their timeout are counted across the process, and capped
+        let wait_for_abandoned = || {
+            let started = Instant::now();
+            while thumbnailer.abandoned() > 0 && started.elapsed() < Duration::from_secs(10) {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            assert_eq!(thumbnailer.abandoned(), 0);
+        };
+        wait_for_abandoned();
+        let blurred = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+            <filter id="blur"><feGaussianBlur stdDeviation="30"/></filter>
+            <rect width="100" height="100" fill="red" filter="url(#blur)"/>
+        </svg>"#;
+        std::fs::write(root.join("blurred.svg"), blurred).unwrap();
+        let impatient = impatient.with_max_abandoned(1);
+        assert!(impatient.thumbnail(&root.join("blurred.svg"), 256).is_err());
+        let error = impatient.thumbnail(&root.join("blurred.svg"), 256).unwrap_err();
+        assert!(error.to_string().contains("still running"), "{}", error);
+        wait_for_abandoned();
+        assert!(impatient
+            .with_timeout(Duration::from_secs(30))
+            .thumbnail(&root.join("red.svg"), 16)
+            .is_ok());
+
+        _ = std::fs::remove_dir_all(root);
+    }
+
+    /// A chunk of a compiled Android resource file
+    fn res_chunk(kind: u16, header: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut chunk = kind.to_le_bytes().to_vec();
+        chunk.extend((8 + header.len() as u16).to_le_bytes());
+        chunk.extend((8 + header.len() as u32 + body.len() as u32).to_le_bytes());
+        chunk.extend(header);
+        chunk.extend(body);
+        chunk
+    }
+
+    /// A UTF-8 string pool. Its header is longer than usual, so readers must use its size
+    fn res_string_pool(strings: &[&str]) -> Vec<u8> {
+        let mut header = Vec::new();
+        for value in [strings.len() as u32, 0, 0x0100, 32 + 4 * strings.len() as u32, 0] {
+            header.extend(value.to_le_bytes());
+        }
+        header.extend([0xff; 4]);
+        let (mut offsets, mut data) = (Vec::new(), Vec::new());
+        for string in strings {
+            offsets.extend((data.len() as u32).to_le_bytes());
+            data.extend([string.len() as u8, string.len() as u8]);
+            data.extend(string.as_bytes());
+            data.push(0);
+        }
+        data.resize(data.len().next_multiple_of(4), 0);
+        res_chunk(0x0001, &header, &[offsets, data].concat())
+    }
+
+    /// Name, attribute ID, value type and value of an attribute in compiled XML
+    type XmlAttribute<'a> = (&'a str, u32, u8, u32);
+
+    /// A compiled XML document of `root` and its `children`
+    fn compiled_xml(root: &str, children: &[(&str, &[XmlAttribute])]) -> Vec<u8> {
+        let attributes: Vec<_> = children.iter().flat_map(|(_, a)| a.iter()).collect();
+        let mut strings: Vec<&str> = attributes.iter().map(|(name, ..)| *name).collect();
+        // Attribute names come first, so the resource map lines up with them
+        strings.push(root);
+        strings.extend(children.iter().map(|(name, _)| *name));
+        let index = |name: &str| strings.iter().position(|s| *s == name).unwrap() as u32;
+        let ids: Vec<u8> = attributes.iter().flat_map(|a| a.1.to_le_bytes()).collect();
+        let mut body = res_chunk(0x0180, &[], &ids);
+        let element = |name: &str, attributes: &[XmlAttribute], start: bool| {
+            let mut fields = u32::MAX.to_le_bytes().to_vec();
+            fields.extend(index(name).to_le_bytes());
+            if start {
+                for value in [20u16, 20, attributes.len() as u16, 0, 0, 0] {
+                    fields.extend(value.to_le_bytes());
+                }
+                for (attribute_name, _, kind, data) in attributes {
+                    fields.extend(u32::MAX.to_le_bytes());
+                    fields.extend(index(attribute_name).to_le_bytes());
+                    fields.extend(u32::MAX.to_le_bytes());
+                    fields.extend([8, 0, 0, *kind]);
+                    fields.extend(data.to_le_bytes());
+                }
+            }
+            res_chunk(if start { 0x0102 } else { 0x0103 }, &[0; 8], &fields)
+        };
+        let mut elements = element(root, &[], true);
+        for (name, attributes) in children {
+            elements.extend(element(name, attributes, true));
+            elements.extend(element(name, &[], false));
+        }
+        elements.extend(element(root, &[], false));
+        body.extend(elements);
+        res_chunk(0x0003, &[], &[res_string_pool(&strings), body].concat())
+    }
+
+    /// Type ID, density and the (value type, value) of each entry of a resource type
+    type ResourceType<'a> = (u8, u16, &'a [(u8, u32)]);
+
+    /// A resources.arsc of package 0x7f, whose string values index `strings`
+    fn resource_table(strings: &[&str], types: &[ResourceType]) -> Vec<u8> {
+        let mut package_header = 0x7fu32.to_le_bytes().to_vec();
+        package_header.resize(280, 0);
+        let mut type_chunks = Vec::new();
+        for (id, density, entries) in types {
+            let mut header = vec![*id, 0, 0, 0];
+            header.extend((entries.len() as u32).to_le_bytes());
+            header.extend((84 + 4 * entries.len() as u32).to_le_bytes());
+            let mut config = [0; 64];
+            config[14..16].copy_from_slice(&density.to_le_bytes());
+            header.extend(config);
+            let (mut offsets, mut data) = (Vec::new(), Vec::new());
+            for (kind, value) in entries.iter() {
+                offsets.extend((data.len() as u32).to_le_bytes());
+                data.extend([8, 0, 0, 0, 0, 0, 0, 0, 8, 0, 0, *kind]);
+                data.extend(value.to_le_bytes());
+            }
+            type_chunks.extend(res_chunk(0x0201, &header, &[offsets, data].concat()));
+        }
+        let package = res_chunk(0x0200, &package_header, &type_chunks);
+        let body = [res_string_pool(strings), package].concat();
+        res_chunk(0x0002, &1u32.to_le_bytes(), &body)
+    }
+
+    #[test]
+    fn test_apk_icons() {
+        let png = |image: image::RgbaImage| {
+            let mut png = Vec::new();
+            image::DynamicImage::ImageRgba8(image)
+                .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+                .unwrap();
+            png
+        };
+        let blue = png(image::RgbaImage::from_pixel(48, 48, image::Rgba([0, 0, 255, 255])));
+        let red = png(image::RgbaImage::from_pixel(192, 192, image::Rgba([255, 0, 0, 255])));
+        let (icon, color, drawable) = (0x7f01_0000, 0x7f02_0000, 0x7f03_0000);
+        let application: &[XmlAttribute] = &[("icon", 0x0101_0002, 0x01, icon)];
+        let manifest = compiled_xml("manifest", &[("application", application)]);
+
+        let densities = resource_table(
+            &["res/mipmap-mdpi/icon.png", "res/mipmap-xxxhdpi/icon.png"],
+            &[(1, 160, &[(0x03, 0)]), (1, 640, &[(0x03, 1)])],
+        );
+        let densities = zip_file(&[
+            ("AndroidManifest.xml", &manifest),
+            ("resources.arsc", &densities),
+            ("res/mipmap-mdpi/icon.png", &blue),
+            ("res/mipmap-xxxhdpi/icon.png", &red),
+        ]);
+
+        // Red in the middle of the foreground, over a green background color
+        let foreground = png(image::RgbaImage::from_fn(108, 108, |x, y| {
+            match (36..72).contains(&x) && (36..72).contains(&y) {
+                true => image::Rgba([255, 0, 0, 255]),
+                false => image::Rgba([0, 0, 0, 0]),
+            }
+        }));
+        let adaptive_icon = compiled_xml(
+            "adaptive-icon",
+            &[
+                ("background", &[("drawable", 0x0101_0199, 0x01, color)]),
+                ("foreground", &[("drawable", 0x0101_0199, 0x01, drawable)]),
+            ],
+        );
+        let adaptive = resource_table(
+            &["res/mipmap-anydpi-v26/icon.xml", "res/drawable/foreground.png"],
+            &[
+                (1, 0xfffe, &[(0x03, 0)]),
+                (2, 0, &[(0x1c, 0xff00_ff00)]),
+                (3, 0, &[(0x03, 1)]),
+            ],
+        );
+        let adaptive = zip_file(&[
+            ("AndroidManifest.xml", &manifest),
+            ("resources.arsc", &adaptive),
+            ("res/mipmap-anydpi-v26/icon.xml", &adaptive_icon),
+            ("res/drawable/foreground.png", &foreground),
+        ]);
+        // An adaptive icon whose foreground is the adaptive icon itself
+        let looping_icon = compiled_xml(
+            "adaptive-icon",
+            &[("foreground", &[("drawable", 0x0101_0199, 0x03, 3)]), ("loop.xml", &[])],
+        );
+        let looping = zip_file(&[
+            ("AndroidManifest.xml", &manifest),
+            ("resources.arsc", &resource_table(&["loop.xml"], &[(1, 0xfffe, &[(0x03, 0)])])),
+            ("loop.xml", &looping_icon),
+        ]);
+        // A sparse type claiming four billion entries, none of them the icon
+        let mut sparse = resource_table(&["res/mipmap-mdpi/icon.png"], &[(1, 160, &[(0x03, 0)])]);
+        let chunk = sparse.windows(4).position(|bytes| bytes == [1, 2, 84, 0]).unwrap();
+        sparse[chunk + 9] = 0x01;
+        sparse[chunk + 12..chunk + 16].copy_from_slice(&u32::MAX.to_le_bytes());
+        sparse[chunk + 84..chunk + 86].copy_from_slice(&5u16.to_le_bytes());
+        let sparse = zip_file(&[
+            ("AndroidManifest.xml", &manifest),
+            ("resources.arsc", &sparse),
+            ("res/mipmap-mdpi/icon.png", &blue),
+        ]);
+        let root = write_fixture(
+            "apk-icons",
+            &[
+                ("densities.apk", &densities),
+                ("adaptive.apk", &adaptive),
+                ("sparse.apk", &sparse),
+                ("looping.apk", &looping),
+                ("broken.apk", &zip_file(&[("classes.dex", b"dex")])),
+            ],
+        );
+
+        let thumbnailer = ApkThumbnailer;
+        assert!(thumbnailer.supports("application/vnd.android.package-archive"));
+        // The smallest variant that is large enough, or else the largest one
+        let small = thumbnailer.thumbnail(&root.join("densities.apk"), 48).unwrap();
+        assert_eq!((small.width, small.height), (48, 48));
+        assert_eq!(&small.as_rgba()[..4], [0, 0, 255, 255]);
+        assert!(small.is_thumbnail());
+        let large = thumbnailer.thumbnail(&root.join("densities.apk"), 128).unwrap();
+        assert_eq!((large.width, large.height), (128, 128));
+        assert_eq!(&large.as_rgba()[..4], [255, 0, 0, 255]);
+
+        let masked = thumbnailer.thumbnail(&root.join("adaptive.apk"), 72).unwrap();
+        assert_eq!((masked.width, masked.height), (72, 72));
+        let pixel = |x: usize, y: usize| &masked.as_rgba()[(y * 72 + x) * 4..][..4];
+        assert_eq!(pixel(36, 36), [255, 0, 0, 255]);
+        assert_eq!(pixel(36, 2), [0, 255, 0, 255]);
+        assert_eq!(pixel(0, 0)[3], 0);
+
+        assert!(thumbnailer.thumbnail(&root.join("broken.apk"), 48).is_err());
+        assert!(thumbnailer.thumbnail(&root.join("looping.apk"), 48).is_err());
+        let started = Instant::now();
+        assert!(thumbnailer.thumbnail(&root.join("sparse.apk"), 48).is_err());
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        _ = std::fs::remove_dir_all(root);
+    }
+
+    enum SquashEntry<'a> {
+        File(&'a [u8]),
+        Symlink(&'a str),
+    }
+
+    /// The parts of a SquashFS image being written
+    #[derive(Default)]
+    struct SquashWriter {
+        image: Vec<u8>,
+        fragment: Vec<u8>,
+        inodes: Vec<u8>,
+        directories: Vec<u8>,
+        inode_count: u32,
+    }
+
+    impl SquashWriter {
+        /// Appends an inode, returning its reference. Everything stays in the first block
+        fn inode(&mut self, kind: u16, fields: &[u8]) -> u64 {
+            let reference = self.inodes.len() as u64;
+            self.inode_count += 1;
+            for value in [kind, 0o755, 0, 0, 0, 0] {
+                self.inodes.extend(value.to_le_bytes());
+            }
+            self.inodes.extend(self.inode_count.to_le_bytes());
+            self.inodes.extend(fields);
+            reference
+        }
+
+        /// Files shorter than a block go in the fragment, longer ones in their own blocks
+        fn file(&mut self, data: &[u8]) -> u64 {
+            let mut fields = Vec::new();
+            if data.len() < 4096 {
+                fields.extend(0u32.to_le_bytes());
+                fields.extend(0u32.to_le_bytes());
+                fields.extend((self.fragment.len() as u32).to_le_bytes());
+                self.fragment.extend(data);
+            } else {
+                fields.extend((self.image.len() as u32).to_le_bytes());
+                fields.extend(u32::MAX.to_le_bytes());
+                fields.extend(0u32.to_le_bytes());
+            }
+            fields.extend((data.len() as u32).to_le_bytes());
+            if data.len() >= 4096 {
+                for block in data.chunks(4096) {
+                    let compressed = zlib(block);
+                    fields.extend((compressed.len() as u32).to_le_bytes());
+                    self.image.extend(compressed);
+                }
+            }
+            self.inode(2, &fields)
+        }
+
+        fn directory(&mut self, entries: &[(&str, SquashEntry)], prefix: &str) -> u64 {
+            let names: std::collections::BTreeSet<&str> = entries
+                .iter()
+                .filter_map(|(path, _)| path.strip_prefix(prefix)?.split('/').next())
+                .collect();
+            let mut children = Vec::new();
+            for name in names {
+                let path = format!("{prefix}{name}");
+                let child = match entries.iter().find(|(entry, _)| *entry == path) {
+                    Some((_, SquashEntry::File(data))) => (self.file(data), 2u16),
+                    Some((_, SquashEntry::Symlink(target))) => {
+                        let mut fields = 1u32.to_le_bytes().to_vec();
+                        fields.extend((target.len() as u32).to_le_bytes());
+                        fields.extend(target.as_bytes());
+                        (self.inode(3, &fields), 3)
+                    }
+                    None => (self.directory(entries, &format!("{path}/")), 1),
+                };
+                children.push((name, child));
+            }
+
+            let listing = self.directories.len();
+            if !children.is_empty() {
+                for value in [children.len() as u32 - 1, 0, 1] {
+                    self.directories.extend(value.to_le_bytes());
+                }
+            }
+            for (name, (reference, kind)) in children {
+                for value in [reference as u16, 0, kind, name.len() as u16 - 1] {
+                    self.directories.extend(value.to_le_bytes());
+                }
+                self.directories.extend(name.as_bytes());
+            }
+            let mut fields = 0u32.to_le_bytes().to_vec();
+            fields.extend(2u32.to_le_bytes());
+            fields.extend((self.directories.len() as u16 - listing as u16 + 3).to_le_bytes());
+            fields.extend((listing as u16).to_le_bytes());
+            fields.extend(0u32.to_le_bytes());
+            self.inode(1, &fields)
+        }
+    }
+
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), Default::default());
+        std::io::Write::write_all(&mut encoder, data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// A gzip SquashFS image of `entries`, whose directories are implied by their paths.
+    /// Metadata is stored uncompressed, in one block per table
+    fn squashfs_image(entries: &[(&str, SquashEntry)]) -> Vec<u8> {
+        let mut writer = SquashWriter {
+            image: vec![0; 96],
+            ..Default::default()
+        };
+        let root = writer.directory(entries, "");
+        let mut image = std::mem::take(&mut writer.image);
+        let fragment = (image.len() as u64, zlib(&writer.fragment));
+        image.extend(&fragment.1);
+        let mut table = |data: &[u8]| {
+            let position = image.len() as u64;
+            image.extend((0x8000 | data.len() as u16).to_le_bytes());
+            image.extend(data);
+            position
+        };
+        let inode_table = table(&writer.inodes);
+        let directory_table = table(&writer.directories);
+        let mut fragment_entry = fragment.0.to_le_bytes().to_vec();
+        fragment_entry.extend((fragment.1.len() as u64).to_le_bytes());
+        let fragment_entries = table(&fragment_entry);
+        let fragment_table = image.len() as u64;
+        image.extend(fragment_entries.to_le_bytes());
+
+        let mut superblock = b"hsqs".to_vec();
+        for value in [writer.inode_count, 0, 4096, 1] {
+            superblock.extend(value.to_le_bytes());
+        }
+        for value in [1u16, 12, 0, 1, 4, 0] {
+            superblock.extend(value.to_le_bytes());
+        }
+        for value in [root, image.len() as u64, fragment_table, u64::MAX] {
+            superblock.extend(value.to_le_bytes());
+        }
+        for value in [inode_table, directory_table, fragment_table, u64::MAX] {
+            superblock.extend(value.to_le_bytes());
+        }
+        image[..96].copy_from_slice(&superblock);
+        image
+    }
+
+    /// An AppImage: a 64-bit ELF header whose one section header ends right before `payload`
+    fn appimage(payload: &[u8]) -> Vec<u8> {
+        let mut elf = vec![0; 128];
+        elf[..8].copy_from_slice(b"\x7fELF\x02\x01\x01\x00");
+        elf[0x28..0x30].copy_from_slice(&64u64.to_le_bytes());
+        elf[0x3a..0x3c].copy_from_slice(&64u16.to_le_bytes());
+        elf[0x3c..0x3e].copy_from_slice(&1u16.to_le_bytes());
+        elf.extend(payload);
+        elf
+    }
+
+    #[test]
+    fn test_appimage_icons() {
+        // Red on the left and blue on the right, noisy enough to take several blocks
+        let pixels = image::RgbaImage::from_fn(96, 96, |x, y| {
+            let noise = ((x * 31 + y * 17) ^ (x * y)) as u8 % 16;
+            match x < 48 {
+                true => image::Rgba([255 - noise, noise, noise, 255]),
+                false => image::Rgba([noise, noise, 255 - noise, 255]),
+            }
+        });
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgba8(pixels)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        assert!(png.len() > 2 * 4096);
+        let desktop = b"[Desktop Entry]\nType=Application\nName=App\nIcon=app\n";
+        let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+            <rect width="10" height="10" fill="lime"/>
+        </svg>"#;
+
+        // .DirIcon links to a link that leads into a subdirectory
+        let linked = squashfs_image(&[
+            (".DirIcon", SquashEntry::Symlink("app.png")),
+            ("app.desktop", SquashEntry::File(desktop)),
+            ("app.png", SquashEntry::Symlink("usr/share/icons/hicolor/96x96/apps/app.png")),
+            ("usr/bin/app", SquashEntry::File(b"#!/bin/sh\n")),
+            ("usr/share/icons/hicolor/96x96/apps/app.png", SquashEntry::File(&png)),
+        ]);
+        let from_desktop = squashfs_image(&[
+            ("app.desktop", SquashEntry::File(desktop)),
+            ("app.svg", SquashEntry::File(svg)),
+        ]);
+        let looping = squashfs_image(&[(".DirIcon", SquashEntry::Symlink(".DirIcon"))]);
+        // Offsets near the end of the address space must fail rather than wrap
+        let mut far_headers = appimage(&linked);
+        far_headers[0x28..0x30].copy_from_slice(&u64::MAX.to_le_bytes());
+        let mut far_inodes = linked.clone();
+        far_inodes[64..72].copy_from_slice(&(u64::MAX - 1).to_le_bytes());
+        let root = write_fixture(
+            "appimage-icons",
+            &[
+                ("Linked.AppImage", &appimage(&linked)),
+                ("Desktop.AppImage", &appimage(&from_desktop)),
+                ("Looping.AppImage", &appimage(&looping)),
+                ("FarHeaders.AppImage", &far_headers),
+                ("FarInodes.AppImage", &appimage(&far_inodes)),
+                ("Script.AppImage", b"#!/bin/sh\n"),
+            ],
+        );
+
+        let thumbnailer = AppImageThumbnailer;
+        assert!(thumbnailer.supports("application/vnd.appimage"));
+        let icon = thumbnailer.thumbnail(&root.join("Linked.AppImage"), 48).unwrap();
+        assert_eq!((icon.width, icon.height), (48, 48));
+        assert!(icon.is_thumbnail());
+        let pixel = |x: usize, y: usize| &icon.as_rgba()[(y * 48 + x) * 4..][..4];
+        assert!(pixel(4, 24)[0] > 200 && pixel(4, 24)[2] < 40);
+        assert!(pixel(44, 24)[2] > 200 && pixel(44, 24)[0] < 40);
+
+        let icon = thumbnailer.thumbnail(&root.join("Desktop.AppImage"), 32).unwrap();
+        assert_eq!((icon.width, icon.height), (32, 32));
+        assert_eq!(&icon.as_rgba()[..4], [0, 255, 0, 255]);
+
+        assert!(thumbnailer.thumbnail(&root.join("Looping.AppImage"), 32).is_err());
+        assert!(thumbnailer.thumbnail(&root.join("FarHeaders.AppImage"), 32).is_err());
+        assert!(thumbnailer.thumbnail(&root.join("FarInodes.AppImage"), 32).is_err());
+        assert!(thumbnailer.thumbnail(&root.join("Script.AppImage"), 32).is_err());
+
+        _ = std::fs::remove_dir_all(root);
+    }
+
+    fn solid_png(size: u32, color: [u8; 4]) -> Vec<u8> {
+        let pixels = image::RgbaImage::from_pixel(size, size, image::Rgba(color));
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgba8(pixels)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        png
+    }
+
+    /// A Debian package whose data.tar.gz holds `files`, where `None` contents are
+    /// links to the given path
+    fn deb_package(files: &[(&str, Option<&[u8]>, &str)]) -> Vec<u8> {
+        let gzip = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+        let mut data = tar::Builder::new(gzip);
+        for (path, contents, target) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(0o644);
+            match contents {
+                Some(contents) => {
+                    header.set_size(contents.len() as u64);
+                    data.append_data(&mut header, path, *contents).unwrap();
+                }
+                None => {
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    data.append_link(&mut header, path, target).unwrap();
+                }
+            }
+        }
+        let data = data.into_inner().unwrap().finish().unwrap();
+        let mut deb = b"!<arch>\n".to_vec();
+        for (name, contents) in [("debian-binary", &b"2.0\n"[..]), ("data.tar.gz", &data)] {
+            // Name, modification time, owner, group, mode and size
+            let length = contents.len();
+            let header = format!("{name:<16}{:<12}{:<6}{:<6}{:<8}{length:<10}`\n", 0, 0, 0, 100644);
+            deb.extend(header.as_bytes());
+            deb.extend(contents);
+            if contents.len() % 2 == 1 {
+                deb.push(b'\n');
+            }
+        }
+        deb
+    }
+
+    /// An RPM package whose zstd compressed cpio payload holds `files`, as `deb_package`
+    fn rpm_package(files: &[(&str, Option<&[u8]>, &str)]) -> Vec<u8> {
+        let mut cpio = Vec::new();
+        let mut entry = |name: &str, mode: u32, contents: &[u8]| {
+            cpio.extend(b"070701");
+            let name_length = name.len() as u32 + 1;
+            for value in [0, mode, 0, 0, 1, 0, contents.len() as u32, 0, 0, 0, 0, name_length, 0] {
+                cpio.extend(format!("{value:08x}").as_bytes());
+            }
+            cpio.extend(name.as_bytes());
+            cpio.push(0);
+            cpio.resize(cpio.len().next_multiple_of(4), 0);
+            cpio.extend(contents);
+            cpio.resize(cpio.len().next_multiple_of(4), 0);
+        };
+        for (path, contents, target) in files {
+            match contents {
+                Some(contents) => entry(path, 0o100644, contents),
+                None => entry(path, 0o120777, target.as_bytes()),
+            }
+        }
+        entry("TRAILER!!!", 0, &[]);
+
+        let mut rpm = vec![0xed, 0xab, 0xee, 0xdb];
+        rpm.resize(96, 0);
+        // A signature with four bytes of data, padded to eight, then a header with one tag
+        for (entries, data_length) in [(0u32, 4u32), (1, 8)] {
+            rpm.extend([0x8e, 0xad, 0xe8, 0x01, 0, 0, 0, 0]);
+            rpm.extend(entries.to_be_bytes());
+            rpm.extend(data_length.to_be_bytes());
+            rpm.resize(rpm.len() + entries as usize * 16 + data_length as usize, 0);
+            rpm.resize(rpm.len().next_multiple_of(8), 0);
+        }
+        rpm.extend(ruzstd::encoding::compress_to_vec(
+            &cpio[..],
+            ruzstd::encoding::CompressionLevel::Fastest,
+        ));
+        rpm
+    }
+
+    #[test]
+    fn test_package_icons() {
+        let red = solid_png(16, [255, 0, 0, 255]);
+        let blue = solid_png(48, [0, 0, 255, 255]);
+        let green = br#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10">
+            <rect width="10" height="10" fill="lime"/>
+        </svg>"#;
+        let desktop = b"[Desktop Entry]\nType=Application\nName=App\nIcon=app\n";
+        let deb = deb_package(&[
+            ("./usr/bin/app", Some(b"#!/bin/sh\n"), ""),
+            ("./usr/share/icons/hicolor/16x16/apps/app.png", Some(&red), ""),
+            ("./usr/share/icons/hicolor/48x48/apps/app.png", Some(&blue), ""),
+            ("./usr/share/icons/hicolor/48x48/apps/other.png", Some(&red), ""),
+            ("./usr/share/icons/hicolor/scalable/apps/app.svg", Some(green), ""),
+            ("./usr/share/applications/app.desktop", Some(desktop), ""),
+        ]);
+        // The desktop file names a pixmap, which links to a themed icon
+        let pixmap_desktop = b"[Desktop Entry]\nName=App\nIcon=/usr/share/pixmaps/app.png\n";
+        let rpm = rpm_package(&[
+            ("./usr/share/applications/app.desktop", Some(pixmap_desktop), ""),
+            ("./usr/share/pixmaps/app.png", None, "../icons/hicolor/16x16/apps/app.png"),
+            ("./usr/share/icons/hicolor/16x16/apps/app.png", Some(&red), ""),
+        ]);
+        let library = deb_package(&[("./usr/lib/libapp.so", Some(b"\x7fELF"), "")]);
+        let root = write_fixture(
+            "package-icons",
+            &[
+                ("app.deb", &deb),
+                ("app.rpm", &rpm),
+                ("libapp.deb", &library),
+                ("fake.rpm", b"not a package"),
+            ],
+        );
+
+        let thumbnailer = PackageThumbnailer;
+        assert!(thumbnailer.supports("application/vnd.debian.binary-package"));
+        assert!(thumbnailer.supports("application/x-rpm"));
+        // Sizes are chosen as in icon themes: the matching one, else the closest
+        let deb = root.join("app.deb");
+        let icon = thumbnailer.thumbnail(&deb, 16).unwrap();
+        assert_eq!((icon.width, icon.height), (16, 16));
+        assert_eq!(&icon.as_rgba()[..4], [255, 0, 0, 255]);
+        assert!(icon.is_thumbnail());
+        assert_eq!(&thumbnailer.thumbnail(&deb, 48).unwrap().as_rgba()[..4], [0, 0, 255, 255]);
+        let scalable = thumbnailer.thumbnail(&deb, 128).unwrap();
+        assert_eq!((scalable.width, scalable.height), (128, 128));
+        assert_eq!(&scalable.as_rgba()[..4], [0, 255, 0, 255]);
+
+        let icon = thumbnailer.thumbnail(&root.join("app.rpm"), 16).unwrap();
+        assert_eq!(&icon.as_rgba()[..4], [255, 0, 0, 255]);
+
+        assert!(thumbnailer.thumbnail(&root.join("libapp.deb"), 16).is_err());
+        assert!(thumbnailer.thumbnail(&root.join("fake.rpm"), 16).is_err());
+
+        _ = std::fs::remove_dir_all(root);
+    }
+    #[test]
+    fn test_archive_members() {
+        let (red, blue) = (solid_png(8, [255, 0, 0, 255]), solid_png(8, [0, 0, 255, 255]));
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let stored = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        for (name, contents, options) in [
+            ("photos/red.png", &red[..], stored),
+            ("photos/blue.png", &blue[..], Default::default()),
+            ("notes/report", b"%PDF-1.4", Default::default()),
+        ] {
+            zip.start_file(name, options).unwrap();
+            std::io::Write::write_all(&mut zip, contents).unwrap();
+        }
+        let zip = zip.finish().unwrap().into_inner();
+        let mut tar = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(blue.len() as u64);
+        header.set_mode(0o644);
+        tar.append_data(&mut header, "./art/blue.png", &blue[..]).unwrap();
+        let tar = tar.into_inner().unwrap();
+        let mut tar_gz = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+        std::io::Write::write_all(&mut tar_gz, &tar).unwrap();
+        let tar_gz = tar_gz.finish().unwrap();
+        let root = write_fixture(
+            "archive-members",
+            &[
+                ("photos.zip", &zip),
+                ("art.tar", &tar),
+                ("art.tar.gz", &tar_gz),
+                (
+                    "icons/hicolor/index.theme",
+                    b"[Icon Theme]\nDirectories=16x16/places,16x16/mimetypes\n\n\
+                      [16x16/places]\nSize=16\n\n[16x16/mimetypes]\nSize=16\n",
+                ),
+                ("icons/hicolor/16x16/places/folder.png", &solid_png(16, [0, 255, 0, 255])),
+                ("icons/hicolor/16x16/mimetypes/application-pdf.png", &red),
+                ("icons/hicolor/16x16/mimetypes/image-png.png", &blue),
+            ],
+        );
+        let archive = root.join("photos.zip");
+
+        let red_member = IconRequest::new(root.join("photos.zip!/photos/red.png"), 16, 16);
+        let IconSource::ArchiveMember {
+            archive: path,
+            member,
+            fingerprint,
+        } = &red_member.source
+        else {
+            panic!("{:?} is not an archive member", red_member.source);
+        };
+        assert_eq!((path, member.as_str()), (&archive, "photos/red.png"));
+        assert_eq!(fingerprint.length, zip.len() as u64);
+        assert_eq!(red_member.source.mime_type().as_deref(), Some("image/png"));
+        let on_disk = IconRequest::new(&archive, 16, 16);
+        assert_eq!(on_disk.source, IconSource::Path(archive.clone()));
+
+        // Members are thumbnailed where they are stored, compressed or not
+        let backend = FreedesktopBackend::with_dirs(vec![root.join("icons")], &[], "hicolor");
+        for (path, color) in [
+            ("photos.zip!/photos/red.png", [255, 0, 0, 255]),
+            ("photos.zip!/photos/blue.png", [0, 0, 255, 255]),
+            ("art.tar!/art/blue.png", [0, 0, 255, 255]),
+            ("art.tar.gz!/art/blue.png", [0, 0, 255, 255]),
+        ] {
+            let image = backend.load(&IconRequest::new(root.join(path), 16, 16)).unwrap();
+            assert!(image.is_thumbnail(), "{}", path);
+            assert_eq!(&image.as_rgba()[..4], color, "{}", path);
+            assert_eq!(image.origin(), Some(root.join(path).as_path()));
+        }
+
+        // Without an extension the type is sniffed, and directories are folders
+        let request = IconRequest::for_archive_member(&archive, "notes/report", 16, 16);
+        let image = backend.load(&request).unwrap();
+        assert!(image.origin().unwrap().ends_with("application-pdf.png"));
+        for member in ["photos/", "./notes"] {
+            let request = IconRequest::for_archive_member(&archive, member, 16, 16);
+            let image = backend.load(&request).unwrap();
+            assert!(image.origin().unwrap().ends_with("folder.png"), "{}", member);
+        }
+        // Missing members still get the icon of their type
+        let missing = IconRequest::for_archive_member(&archive, "photos/green.png", 16, 16);
+        let image = backend.load(&missing).unwrap();
+        assert!(image.origin().unwrap().ends_with("image-png.png"));
+
+        // Thumbnailers seek to offsets read from the member, which can be past any archive
+        let member = crate::archive::open_member(&root.join("art.tar"), "art/blue.png");
+        let Ok(crate::archive::Member::File(mut member)) = member else {
+            panic!("art/blue.png is not a file in art.tar");
+        };
+        let far = std::io::Seek::seek(&mut member, std::io::SeekFrom::Start(u64::MAX - 8));
+        assert_eq!(far.unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+
+        // A changed archive makes a different cache key
+        std::fs::write(&archive, &tar).unwrap();
+        let changed = IconRequest::new(root.join("photos.zip!/photos/red.png"), 16, 16);
+        assert_ne!(changed, red_member);
+
+        _ = std::fs::remove_dir_all(root);
+    }
+
+    /// An ICO file of `images`, each its size and PNG or bitmap data
+    fn ico_file(images: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut ico = vec![0, 0, 1, 0, images.len() as u8, 0];
+        let mut offset = 6 + 16 * images.len();
+        for (size, data) in images {
+            ico.extend([*size, *size, 0, 0, 1, 0, 32, 0]);
+            ico.extend((data.len() as u32).to_le_bytes());
+            ico.extend((offset as u32).to_le_bytes());
+            offset += data.len();
+        }
+        images.iter().for_each(|(_, data)| ico.extend(*data));
+        ico
+    }
+
+    /// A 32-bit PE file whose resources hold the icon group 101 of one 16 pixel `png`
+    fn pe_file(png: &[u8]) -> Vec<u8> {
+        pe_file_listing(png, 1)
+    }
+
+    /// Like `pe_file`, with the image listed `times` times in the group
+    fn pe_file_listing(png: &[u8], times: u16) -> Vec<u8> {
+        const SECTION_ADDRESS: u32 = 0x1000;
+        let directory = |entries: &[(u32, u32)]| {
+            let mut directory = vec![0; 14];
+            directory.extend((entries.len() as u16).to_le_bytes());
+            for (id, target) in entries {
+                directory.extend(id.to_le_bytes());
+                directory.extend(target.to_le_bytes());
+            }
+            directory
+        };
+        let data_entry = |offset: usize, length: usize| {
+            let mut entry = (SECTION_ADDRESS + offset as u32).to_le_bytes().to_vec();
+            entry.extend((length as u32).to_le_bytes());
+            entry.extend([0; 8]);
+            entry
+        };
+        let subdirectory = |offset: u32| 0x8000_0000 | offset;
+        let mut group = vec![0, 0, 1, 0];
+        group.extend(times.to_le_bytes());
+        for _ in 0..times {
+            group.extend([16, 16, 0, 0, 1, 0, 32, 0]);
+            group.extend((png.len() as u32).to_le_bytes());
+            group.extend(1u16.to_le_bytes());
+        }
+
+        let mut resources = directory(&[(3, subdirectory(32)), (14, subdirectory(80))]);
+        resources.extend(directory(&[(1, subdirectory(56))]));
+        resources.extend(directory(&[(1033, 128)]));
+        resources.extend(directory(&[(101, subdirectory(104))]));
+        resources.extend(directory(&[(1033, 144)]));
+        resources.extend(data_entry(160, png.len()));
+        resources.extend(data_entry(160 + png.len(), group.len()));
+        resources.extend(png);
+        resources.extend(&group);
+
+        let mut pe = vec![0; 0x200];
+        pe[..2].copy_from_slice(b"MZ");
+        pe[0x3c] = 0x40;
+        pe[0x40..0x44].copy_from_slice(b"PE\0\0");
+        // One section and a standard optional header
+        pe[0x46] = 1;
+        pe[0x54] = 224;
+        let optional = 0x58;
+        pe[optional..optional + 2].copy_from_slice(&0x10bu16.to_le_bytes());
+        let resource_directory = optional + 96 + 2 * 8;
+        pe[resource_directory..resource_directory + 4]
+            .copy_from_slice(&SECTION_ADDRESS.to_le_bytes());
+        let section = optional + 224;
+        pe[section..section + 5].copy_from_slice(b".rsrc");
+        for (offset, value) in [(8, resources.len() as u32), (12, SECTION_ADDRESS)] {
+            pe[section + offset..section + offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        for (offset, value) in [(16, resources.len() as u32), (20, 0x200)] {
+            pe[section + offset..section + offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        pe.extend(resources);
+        pe
+    }
+
+    #[test]
+    fn test_in_memory_icons() {
+        let (red, blue) = (solid_png(16, [255, 0, 0, 255]), solid_png(32, [0, 0, 255, 255]));
+        // An 8 pixel 32-bit bitmap, twice as high for the mask, with green BGRA pixels
+        let mut bitmap = 40u32.to_le_bytes().to_vec();
+        bitmap.extend(8u32.to_le_bytes());
+        bitmap.extend(16u32.to_le_bytes());
+        bitmap.extend([1, 0, 32, 0]);
+        bitmap.extend([0; 24]);
+        (0..64).for_each(|_| bitmap.extend([0, 255, 0, 255]));
+        bitmap.extend([0; 32]);
+        let ico = ico_file(&[(32, &blue), (8, &bitmap), (16, &red)]);
+
+        // The smallest image at least as large as asked for, else the largest
+        assert!(IcoThumbnailer.supports("image/vnd.microsoft.icon"));
+        for (size, color) in [
+            (8, [0, 255, 0, 255]),
+            (12, [255, 0, 0, 255]),
+            (16, [255, 0, 0, 255]),
+            (32, [0, 0, 255, 255]),
+            (64, [0, 0, 255, 255]),
+        ] {
+            let image = Image::from_bytes(&ico, "app.ico", size).unwrap();
+            assert_eq!((image.width, image.height), (size, size));
+            assert_eq!(&image.as_rgba()[..4], color, "{}", size);
+            assert!(image.is_thumbnail());
+        }
+
+        let pe = pe_file(&red);
+        assert!(ExecutableThumbnailer.supports("application/vnd.microsoft.portable-executable"));
+        let image = Image::from_reader(std::io::Cursor::new(&pe), "setup.exe", 16).unwrap();
+        assert_eq!(&image.as_rgba()[..4], [255, 0, 0, 255]);
+        assert_eq!(image.origin(), Some(Path::new("setup.exe")));
+        // Groups are counted from 0, or named by their negated ID
+        assert!(read_icon(std::io::Cursor::new(&pe), -101).is_ok());
+        assert!(read_icon(std::io::Cursor::new(&pe), 1).is_err());
+        assert!(read_icon(std::io::Cursor::new(&ico), 0).is_err());
+        // Images listed again are read once
+        let icon = read_icon(std::io::Cursor::new(pe_file_listing(&red, 64)), 0).unwrap();
+        assert_eq!(icon.len(), 6 + 16 + red.len());
+
+        // Without an extension the content tells the type
+        let image = Image::from_bytes(&blue, "upload", 16).unwrap();
+        assert!(image.is_thumbnail());
+        assert_eq!(&image.as_rgba()[..4], [0, 0, 255, 255]);
+        let image = Image::from_bytes(&pe, "blob-3f2a", 16).unwrap();
+        assert_eq!(&image.as_rgba()[..4], [255, 0, 0, 255]);
+
+        // Nothing to draw at size 0
+        let image = Image::from_bytes(&blue, "blue.png", 0).unwrap();
+        assert!(image.as_rgba().is_empty());
+        assert_eq!(image.fit(0, 16).as_rgba().len(), 0);
+    }
+
+    #[test]
+    fn test_content_sniffing() {
+        let png = solid_png(8, [255, 0, 0, 255]);
+        let guess = |extension: Option<&str>, head: &[u8]| {
+            let guess = MimeGuess::decide(extension.map(str::to_string), head);
+            (guess.mime_type, guess.basis)
+        };
+        // Signatures of one format override the extension, shared ones don't
+        assert_eq!(
+            guess(Some("text/plain"), &png),
+            ("image/png".to_string(), MimeBasis::ContentOverExtension)
+        );
+        assert_eq!(
+            guess(Some("image/x-canon-cr2"), b"II*\0\x10\0\0\0CR"),
+            ("image/x-canon-cr2".to_string(), MimeBasis::Extension)
+        );
+        assert_eq!(
+            guess(Some("application/vnd.android.package-archive"), b"PK\x03\x04\x14\0"),
+            ("application/vnd.android.package-archive".to_string(), MimeBasis::Extension)
+        );
+        assert_eq!(
+            guess(Some("text/x-python"), b"#!/bin/sh\necho hi\n"),
+            ("text/x-python".to_string(), MimeBasis::Extension)
+        );
+
+        // Without an extension, any content that tells something
+        for (head, mime_type) in [
+            (&b"#!/usr/bin/env -S python3.12 -u\nprint()\n"[..], "text/x-python"),
+            (b"#! /bin/bash\n", "application/x-shellscript"),
+            (b"#!/usr/bin/perl -w\n", "application/x-perl"),
+            (b"#!/opt/tool/run\n", "text/plain"),
+            (b"\xef\xbb\xbf  <!DOCTYPE html><html>", "text/html"),
+            (b"all: build\n\tcargo build\n", "text/plain"),
+            ("Grüße aus Köln".as_bytes(), "text/plain"),
+            (b"\x7fELF\x02\x01\x01\0\0\0\0\0", "application/x-executable"),
+        ] {
+            assert_eq!(guess(None, head), (mime_type.to_string(), MimeBasis::Content));
+        }
+        // Text cut off mid character is still text
+        assert_eq!(guess(None, &"Köln".as_bytes()[..2]).0, "text/plain");
+        let unknown = MimeGuess::decide(None, b"\0\x13\x37binary");
+        assert_eq!((unknown.basis, unknown.known()), (MimeBasis::Unknown, None));
+        assert_eq!(unknown.mime_type, "application/octet-stream");
+
+        // The backend thumbnails misnamed images and gives plain text its icon
+        let mut wav = b"RIFF\x24\0\0\0WAVEfmt \x10\0\0\0\x01\0\x01\0".to_vec();
+        for value in [8000u32, 16000] {
+            wav.extend(value.to_le_bytes());
+        }
+        wav.extend(b"\x02\0\x10\0data\0\0\0\0");
+        let root = write_fixture(
+            "content-sniffing",
+            &[
+                ("notes.txt", &png),
+                ("README", b"Build with cargo build\n"),
+                ("run", b"#!/bin/sh\nexec true\n"),
+                (
+                    "icons/hicolor/index.theme",
+                    b"[Icon Theme]\nDirectories=16x16/mimetypes\n\n\
+                      [16x16/mimetypes]\nSize=16\n",
+                ),
+                ("icons/hicolor/16x16/mimetypes/text-plain.png", &png),
+                ("icons/hicolor/16x16/mimetypes/application-x-shellscript.png", &png),
+                ("icons/hicolor/16x16/mimetypes/audio-x-wav.png", &png),
+                ("sound.wav", &wav),
+                ("mime/globs2", b"50:audio/x-wav:*.wav\n"),
+                ("mime/aliases", b"audio/wav audio/x-wav\naudio/vnd.wave audio/x-wav\n"),
+            ],
+        );
+        // The sniffer calls WAV files `audio/wav`, an alias of the type their extension has
+        let aliased = FreedesktopBackend::with_dirs(
+            vec![root.join("icons")],
+            &[root.join("mime")],
+            "hicolor",
+        );
+        let sound = aliased.mime_guess(&root.join("sound.wav"));
+        assert_eq!((sound.mime_type.as_str(), sound.basis), ("audio/x-wav", MimeBasis::Extension));
+        assert_eq!(sound.by_content.as_deref(), Some("audio/x-wav"));
+        let image = aliased.load(&IconRequest::new(root.join("sound.wav"), 16, 16)).unwrap();
+        assert!(image.origin().unwrap().ends_with("audio-x-wav.png"));
+
+        let backend = FreedesktopBackend::with_dirs(vec![root.join("icons")], &[], "hicolor");
+        let misnamed = backend.mime_guess(&root.join("notes.txt"));
+        assert_eq!(misnamed.basis, MimeBasis::ContentOverExtension);
+        assert_eq!(misnamed.by_extension.as_deref(), Some("text/plain"));
+        assert_eq!(misnamed.by_content.as_deref(), Some("image/png"));
+        let image = backend.load(&IconRequest::new(root.join("notes.txt"), 16, 16)).unwrap();
+        assert!(image.is_thumbnail());
+        let expected = [("README", "text-plain.png"), ("run", "application-x-shellscript.png")];
+        for (name, icon) in expected {
+            let image = backend.load(&IconRequest::new(root.join(name), 16, 16)).unwrap();
+            assert!(image.origin().unwrap().ends_with(icon), "{}", name);
+        }
+        let image = Image::from_bytes(&png, "notes.txt", 8).unwrap();
+        assert!(image.is_thumbnail());
+
+        _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_file_kinds() {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let (red, green) = (solid_png(16, [255, 0, 0, 255]), solid_png(16, [0, 255, 0, 255]));
+        let blue = solid_png(16, [0, 0, 255, 255]);
+        let root = write_fixture(
+            "file-kinds",
+            &[
+                ("files/plain", b"\0\x01"),
+                (
+                    "icons/hicolor/index.theme",
+                    b"[Icon Theme]\nDirectories=16x16/places,16x16/mimetypes,16x16/emblems\n\n\
+                      [16x16/places]\nSize=16\n\n[16x16/mimetypes]\nSize=16\n\n\
+                      [16x16/emblems]\nSize=16\n",
+                ),
+                ("icons/hicolor/16x16/places/folder.png", &green),
+                ("icons/hicolor/16x16/mimetypes/inode-symlink.png", &red),
+                ("icons/hicolor/16x16/mimetypes/inode-socket.png", &red),
+                ("icons/hicolor/16x16/mimetypes/inode-fifo.png", &red),
+                ("icons/hicolor/16x16/mimetypes/inode-chardevice.png", &red),
+                ("icons/hicolor/16x16/emblems/emblem-unreadable.png", &blue),
+                ("icons/hicolor/16x16/emblems/emblem-corrupt.png", b"\x89PNG\r\n\x1a\nnope"),
+            ],
+        );
+        let files = root.join("files");
+        std::os::unix::fs::symlink(files.join("gone"), files.join("dangling")).unwrap();
+        std::os::unix::fs::symlink(&files, files.join("to-directory")).unwrap();
+        let fifo = CString::new(files.join("pipe").as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+        let _socket = std::os::unix::net::UnixListener::bind(files.join("socket")).unwrap();
+
+        // Links are the kind of their target, unless there is none
+        for (name, kind) in [
+            ("plain", Some(FileKind::File)),
+            ("dangling", Some(FileKind::Symlink)),
+            ("to-directory", Some(FileKind::Directory)),
+            ("pipe", Some(FileKind::Fifo)),
+            ("socket", Some(FileKind::Socket)),
+            ("missing", None),
+        ] {
+            assert_eq!(FileKind::of(&files.join(name)), kind, "{}", name);
+        }
+        assert_eq!(FileKind::of(Path::new("/dev/null")), Some(FileKind::CharDevice));
+        assert_eq!(
+            IconRequest::for_file_kind(FileKind::BlockDevice, 16, 16).source.mime_type().as_deref(),
+            Some("inode/blockdevice")
+        );
+
+        // Special files get the icon of their kind without being read
+        let backend = FreedesktopBackend::with_dirs(vec![root.join("icons")], &[], "hicolor");
+        for name in ["pipe", "socket", "/dev/null"] {
+            let image = backend.load(&IconRequest::new(files.join(name), 16, 16)).unwrap();
+            let icon = image.origin().unwrap().file_name().unwrap().to_str().unwrap();
+            assert!(icon.starts_with("inode-"), "{}: {}", name, icon);
+        }
+        let image = backend
+            .load(&IconRequest::for_file_kind(FileKind::Directory, 16, 16))
+            .unwrap();
+        assert!(image.origin().unwrap().ends_with("folder.png"));
+
+        // Dangling links get the broken link emblem in the bottom right corner
+        let image = backend.load(&IconRequest::new(files.join("dangling"), 16, 16)).unwrap();
+        assert!(image.origin().unwrap().ends_with("inode-symlink.png"));
+        assert_eq!(&image.as_rgba()[..4], [255, 0, 0, 255]);
+        assert_eq!(&image.as_rgba()[image.as_rgba().len() - 4..], [0, 0, 255, 255]);
+        let request = IconRequest::for_file_kind(FileKind::Symlink, 16, 16);
+        let image = backend.load(&request.with_overlay(BROKEN_LINK_EMBLEM)).unwrap();
+        assert_eq!(&image.as_rgba()[image.as_rgba().len() - 4..], [0, 0, 255, 255]);
+        // Emblems that can't be drawn are left out rather than failing the icon
+        let request = IconRequest::new(files.join("dangling"), 16, 16);
+        let image = backend.load(&request.with_overlay("emblem-corrupt")).unwrap();
+        assert_eq!(&image.as_rgba()[image.as_rgba().len() - 4..], [0, 0, 255, 255]);
+        let image = backend.load(&IconRequest::new(files.join("dangling"), 1, 1)).unwrap();
+        assert_eq!((image.width, image.height), (1, 1));
+
+        _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_special_folders() {
+        let (red, green) = (solid_png(16, [255, 0, 0, 255]), solid_png(16, [0, 255, 0, 255]));
+        let root = write_fixture(
+            "special-folders",
+            &[
+                (
+                    "config/user-dirs.dirs",
+                    b"# Written by xdg-user-dirs-update\n\
+                      XDG_DOWNLOAD_DIR=\"$HOME/Downloads\"\n\
+                      XDG_MUSIC_DIR=\"/srv/music\"\n\
+                      XDG_PUBLICSHARE_DIR=\"$HOME/\"\n\
+                      XDG_VIDEOS_DIR=\"Videos\"\n",
+                ),
+                ("home/Downloads/setup.exe", b"MZ"),
+                ("home/Public/notes.txt", b"notes"),
+                ("data/Trash/info/.keep", b""),
+                (
+                    "icons/hicolor/index.theme",
+                    b"[Icon Theme]\nDirectories=16x16/places\n\n[16x16/places]\nSize=16\n",
+                ),
+                ("icons/hicolor/16x16/places/folder.png", &green),
+                ("icons/hicolor/16x16/places/folder-download.png", &red),
+                ("icons/hicolor/16x16/places/user-home.png", &red),
+                ("icons/hicolor/16x16/places/user-trash.png", &red),
+                ("icons/hicolor/16x16/places/user-trash-full.png", &red),
+            ],
+        );
+        let home = root.join("home");
+        let folders =
+            SpecialFolders::load(Some(&home), Some(&root.join("config")), Some(&root.join("data")));
+        for (path, icon) in [
+            (home.join("Downloads"), Some("folder-download")),
+            (home.join("Downloads/"), Some("folder-download")),
+            (PathBuf::from("/srv/music"), Some("folder-music")),
+            (home.clone(), Some("user-home")),
+            (PathBuf::from("/"), Some("drive-harddisk")),
+            // Set to home itself, so disabled, and relative, so invalid
+            (home.join("Public"), None),
+            (home.join("Videos"), None),
+            (root.join("data/Trash"), Some("user-trash")),
+            (home.join("Public/../Downloads"), Some("folder-download")),
+        ] {
+            assert_eq!(folders.icon_name(&path), icon, "{}", path.display());
+        }
+        // A home reached through a link matches its real path, and the other way around
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&home, root.join("linked-home")).unwrap();
+            let linked = root.join("linked-home");
+            let folders = SpecialFolders::load(Some(&linked), Some(&root.join("config")), None);
+            assert_eq!(folders.icon_name(&home.join("Downloads")), Some("folder-download"));
+            assert_eq!(folders.icon_name(&linked), Some("user-home"));
+        }
+
+        // The trash is cached by its icon, which changes as it fills
+        let keyed = FreedesktopBackend::with_dirs(Vec::new(), &[], "hicolor")
+            .with_special_folders(folders.clone());
+        let trash = IconRequest::new(root.join("data/Trash"), 16, 16);
+        let empty = keyed.cache_key(&trash);
+        let downloads = IconRequest::new(home.join("Downloads"), 16, 16);
+        assert_eq!(keyed.cache_key(&downloads), downloads);
+        std::fs::create_dir_all(root.join("data/Trash/files/old.txt")).unwrap();
+        assert_eq!(folders.icon_name(&root.join("data/Trash/files")), Some("user-trash-full"));
+        assert_ne!(keyed.cache_key(&trash), empty);
+
+        // Only directories get them, with the plain folder as the fallback
+        let backend = FreedesktopBackend::with_dirs(vec![root.join("icons")], &[], "hicolor")
+            .with_special_folders(folders);
+        for (path, icon) in [
+            (home.join("Downloads"), "folder-download.png"),
+            (home.clone(), "user-home.png"),
+            (root.join("data/Trash"), "user-trash-full.png"),
+            (home.join("Public"), "folder.png"),
+        ] {
+            let image = backend.load(&IconRequest::new(&path, 16, 16)).unwrap();
+            assert!(image.origin().unwrap().ends_with(icon), "{}", path.display());
+        }
+        let request = IconRequest::new(home.join("Downloads"), 16, 16).with_state(IconState::Open);
+        let image = backend.load(&request).unwrap();
+        assert!(image.origin().unwrap().ends_with("folder-download.png"));
+
+        _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_mount_points() {
+        let usb_disk = "sys/devices/pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host6/block/sdb";
+        let (red, green) = (solid_png(16, [255, 0, 0, 255]), solid_png(16, [0, 255, 0, 255]));
+        let blue = solid_png(16, [0, 0, 255, 255]);
+        let root = write_fixture(
+            "mount-points",
+            &[
+                ("sys/block/sda/sda1/partition", b"1"),
+                ("sys/block/sda/sda2/partition", b"2"),
+                ("sys/block/sda/removable", b"0\n"),
+                ("sys/block/sda/queue/rotational", b"0\n"),
+                (&format!("{}/sdb1/partition", usb_disk), b"1"),
+                (&format!("{}/removable", usb_disk), b"1\n"),
+                ("sys/block/sr0/device/type", b"5\n"),
+                ("sys/block/mmcblk0/mmcblk0p1/partition", b"1"),
+                ("sys/block/mmcblk0/removable", b"0\n"),
+                ("sys/block/sdc/sdc1/partition", b"1"),
+                ("sys/block/sdc/queue/rotational", b"1\n"),
+                ("sys/block/dm-0/queue/rotational", b"0\n"),
+                (
+                    "icons/hicolor/index.theme",
+                    b"[Icon Theme]\nDirectories=16x16/devices,16x16/places\n\n\
+                      [16x16/devices]\nSize=16\n\n[16x16/places]\nSize=16\n",
+                ),
+                ("icons/hicolor/16x16/places/folder.png", &green),
+                ("icons/hicolor/16x16/devices/drive-harddisk.png", &red),
+                ("icons/hicolor/16x16/devices/drive-removable-media.png", &blue),
+            ],
+        );
+        std::os::unix::fs::symlink(root.join(usb_disk), root.join("sys/block/sdb")).unwrap();
+        std::fs::create_dir_all(root.join("dev/mapper")).unwrap();
+        std::fs::write(root.join("dev/dm-0"), b"").unwrap();
+        std::os::unix::fs::symlink("../dm-0", root.join("dev/mapper/vault")).unwrap();
+        let mnt = root.join("mnt");
+        let mut mountinfo = String::new();
+        for (id, point, file_system, source) in [
+            (22, "system", "ext4", "/dev/sda1"),
+            (23, "usb\\040stick", "vfat", "/dev/sdb1"),
+            (24, "cdrom", "iso9660", "/dev/sr0"),
+            (25, "image", "iso9660", "/dev/loop7"),
+            (26, "share", "cifs", "//server/share"),
+            (27, "card", "exfat", "/dev/mmcblk0p1"),
+            (28, "scratch", "tmpfs", "tmpfs"),
+            (29, "archive", "ext4", "/dev/sdc1"),
+            (30, "stacked", "cifs", "//server/old"),
+            (31, "stacked", "ext4", "/dev/sda2"),
+            (32, "vault", "ext4", "/dev/mapper/vault"),
+        ] {
+            mountinfo += &format!(
+                "{} 1 8:1 / {}/{} rw,relatime shared:1 - {} {} rw\n",
+                id,
+                mnt.display(),
+                point,
+                file_system,
+                source
+            );
+            let directory = mnt.join(point.replace("\\040", " "));
+            std::fs::create_dir_all(directory).unwrap();
+        }
+        std::fs::write(root.join("mountinfo"), mountinfo).unwrap();
+
+        let mounts = MountTable::new(root.join("mountinfo"), root.join("sys"), root.join("dev"));
+        for (point, names) in [
+            ("system", &["drive-harddisk-solidstate", "drive-harddisk"][..]),
+            ("usb stick", &["drive-removable-media-usb", "drive-removable-media"]),
+            ("cdrom", &["drive-optical"]),
+            ("image", &["application-x-cd-image", "media-optical"]),
+            ("share", &["folder-remote"]),
+            ("card", &["media-flash", "drive-removable-media"]),
+            ("scratch", &[]),
+            ("archive", &["drive-harddisk"]),
+            ("stacked", &["drive-harddisk-solidstate", "drive-harddisk"]),
+            ("vault", &["drive-harddisk-solidstate", "drive-harddisk"]),
+        ] {
+            assert_eq!(mounts.icon_names(&mnt.join(point)), names, "{}", point);
+        }
+        assert!(mounts.icon_names(&root.join("sys")).is_empty());
+
+        // Mount points fall back through the drive icons to the folder
+        let backend = FreedesktopBackend::with_dirs(vec![root.join("icons")], &[], "hicolor")
+            .with_mounts(Some(mounts));
+        for (point, icon) in [
+            ("system", "drive-harddisk.png"),
+            ("usb stick", "drive-removable-media.png"),
+            ("scratch", "folder.png"),
+        ] {
+            let image = backend.load(&IconRequest::new(mnt.join(point), 16, 16)).unwrap();
+            assert!(image.origin().unwrap().ends_with(icon), "{}", point);
+        }
+
+        _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_custom_folder_icons() {
+        let (red, green) = (solid_png(16, [255, 0, 0, 255]), solid_png(16, [0, 255, 0, 255]));
+        let (blue, white) = (solid_png(16, [0, 0, 255, 255]), solid_png(16, [255; 4]));
+        let ico = ico_file(&[(16, &white)]);
+        // Windows writes UTF-16 with a byte order mark
+        let resource = "[.ShellClassInfo]\r\nIconResource=icons\\app.exe,-101\r\n";
+        let mut utf16 = vec![0xff, 0xfe];
+        resource.encode_utf16().for_each(|unit| utf16.extend(unit.to_le_bytes()));
+        let root = write_fixture(
+            "custom-folder-icons",
+            &[
+                ("named/.directory", b"[Desktop Entry]\nIcon=folder-red\n"),
+                ("relative/.directory", b"[Desktop Entry]\nIcon=./art/cover.png\n"),
+                ("relative/art/cover.png", &blue),
+                ("resource/desktop.ini", &utf16),
+                ("resource/icons/app.exe", &pe_file(&red)),
+                (
+                    "legacy/Desktop.ini",
+                    b"[.ShellClassInfo]\r\niconfile=\"folder.ico\"\r\nIconIndex=0\r\n",
+                ),
+                ("legacy/folder.ico", &ico),
+                ("sidecar/.folder.png", &blue),
+                ("broken/.directory", b"[Desktop Entry]\nIcon=./missing.png\n"),
+                ("bare/.directory", b"[Desktop Entry]\nIcon=cover.png\n"),
+                ("bare/cover.png", &red),
+                ("piped/.directory", b"[Desktop Entry]\nIcon=./icon.png\n"),
+                ("plain/notes.txt", b"notes"),
+                (
+                    "icons/hicolor/index.theme",
+                    b"[Icon Theme]\nDirectories=16x16/places\n\n[16x16/places]\nSize=16\n",
+                ),
+                ("icons/hicolor/16x16/places/folder.png", &green),
+                ("icons/hicolor/16x16/places/folder-red.png", &red),
+            ],
+        );
+        // Reading a FIFO would wait for a writer forever
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let fifo = root.join("piped/icon.png");
+            let fifo = std::ffi::CString::new(fifo.as_os_str().as_bytes()).unwrap();
+            assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+        }
+        // Images don't get to read other files
+        let svg = "<svg xmlns='http://www.w3.org/2000/svg' width='16' height='16'>";
+        std::fs::create_dir_all(root.join("linked")).unwrap();
+        let red_svg = format!("{}<rect width='16' height='16' fill='red'/></svg>", svg);
+        std::fs::write(root.join("linked/red.svg"), red_svg).unwrap();
+        let linking = format!(
+            "{}<image href='{}' width='16' height='16'/></svg>",
+            svg,
+            root.join("linked/red.svg").display()
+        );
+        std::fs::write(root.join("linked/.folder.svg"), linking).unwrap();
+        let backend = FreedesktopBackend::with_dirs(vec![root.join("icons")], &[], "hicolor");
+        let load = |folder: &str, ignore: bool| {
+            let request = IconRequest::new(root.join(folder), 16, 16).ignore_custom_icon(ignore);
+            backend.load(&request).unwrap()
+        };
+        for (folder, origin, color) in [
+            ("named", "folder-red.png", [255, 0, 0, 255]),
+            ("relative", "cover.png", [0, 0, 255, 255]),
+            ("bare", "cover.png", [255, 0, 0, 255]),
+            ("resource", "app.exe", [255, 0, 0, 255]),
+            ("legacy", "folder.ico", [255; 4]),
+            ("sidecar", ".folder.png", [0, 0, 255, 255]),
+            ("linked", ".folder.svg", [0; 4]),
+            // Icons that can't be loaded leave the folder as it is
+            ("broken", "folder.png", [0, 255, 0, 255]),
+            ("piped", "folder.png", [0, 255, 0, 255]),
+            ("plain", "folder.png", [0, 255, 0, 255]),
+        ] {
+            let image = load(folder, false);
+            assert!(image.origin().unwrap().ends_with(origin), "{}", folder);
+            assert_eq!(&image.as_rgba()[..4], color, "{}", folder);
+        }
+
+        // Requests can opt out, and files never get them
+        for folder in ["named", "resource", "sidecar"] {
+            assert!(load(folder, true).origin().unwrap().ends_with("folder.png"));
+        }
+        let image = backend.load(&IconRequest::new(root.join("sidecar/.folder.png"), 16, 16));
+        assert!(!image.unwrap().origin().unwrap().ends_with("folder.png"));
+
+        _ = std::fs::remove_dir_all(root);
+    }
+}
\ No newline at end of file
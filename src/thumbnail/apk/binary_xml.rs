@@ -0,0 +1,216 @@
+//! The compiled XML format of Android packages, used for `AndroidManifest.xml` and the
+//! XML drawables under `res/`, and the string pools it shares with `resources.arsc`
+
+pub(super) const STRING_POOL_TYPE: u16 = 0x0001;
+const XML_TYPE: u16 = 0x0003;
+const START_ELEMENT_TYPE: u16 = 0x0102;
+const END_ELEMENT_TYPE: u16 = 0x0103;
+const RESOURCE_MAP_TYPE: u16 = 0x0180;
+
+const UTF8_FLAG: u32 = 0x0100;
+
+const TYPE_REFERENCE: u8 = 0x01;
+const TYPE_STRING: u8 = 0x03;
+const TYPE_FIRST_COLOR: u8 = 0x1c;
+const TYPE_LAST_COLOR: u8 = 0x1f;
+
+pub(super) fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset.checked_add(2)?)?.try_into().ok()?,
+    ))
+}
+
+pub(super) fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset.checked_add(4)?)?.try_into().ok()?,
+    ))
+}
+
+/// A chunk of a compiled resource file: its type, header size and all of its bytes
+pub(super) struct Chunk<'a> {
+    pub kind: u16,
+    pub header_size: usize,
+    pub data: &'a [u8],
+}
+
+/// The chunks that follow each other in `data`, starting at `start`
+pub(super) fn chunks(data: &[u8], start: usize) -> impl Iterator<Item = Chunk<'_>> {
+    let mut position = start;
+    std::iter::from_fn(move || {
+        let kind = u16_at(data, position)?;
+        let header_size = u16_at(data, position + 2)? as usize;
+        let size = u32_at(data, position + 4)? as usize;
+        if size < 8 || header_size > size {
+            return None;
+        }
+        let chunk = data.get(position..position.checked_add(size)?)?;
+        position += size;
+        Some(Chunk {
+            kind,
+            header_size,
+            data: chunk,
+        })
+    })
+}
+
+/// Strings shared by a compiled file, decoded when asked for since resource tables
+/// hold many thousands
+pub(super) struct StringPool<'a> {
+    data: &'a [u8],
+    count: usize,
+    /// The string offsets follow the header, whatever its size
+    offsets_start: usize,
+    strings_start: usize,
+    is_utf8: bool,
+}
+
+impl<'a> StringPool<'a> {
+    pub fn parse(chunk: &Chunk<'a>) -> Option<Self> {
+        if chunk.kind != STRING_POOL_TYPE {
+            return None;
+        }
+        Some(Self {
+            data: chunk.data,
+            count: u32_at(chunk.data, 8)? as usize,
+            offsets_start: chunk.header_size,
+            is_utf8: u32_at(chunk.data, 16)? & UTF8_FLAG != 0,
+            strings_start: u32_at(chunk.data, 20)? as usize,
+        })
+    }
+
+    pub fn get(&self, index: u32) -> Option<String> {
+        if index as usize >= self.count {
+            return None;
+        }
+        let offset = u32_at(self.data, self.offsets_start + index as usize * 4)? as usize;
+        let position = self.strings_start.checked_add(offset)?;
+        if self.is_utf8 {
+            // The length in characters, then in bytes, each one or two bytes long
+            let (_, position) = self.utf8_length(position)?;
+            let (length, position) = self.utf8_length(position)?;
+            let bytes = self.data.get(position..position.checked_add(length)?)?;
+            Some(String::from_utf8_lossy(bytes).into_owned())
+        } else {
+            let mut length = u16_at(self.data, position)? as usize;
+            let mut position = position + 2;
+            if length & 0x8000 != 0 {
+                length = ((length & 0x7fff) << 16) | u16_at(self.data, position)? as usize;
+                position += 2;
+            }
+            let units = (0..length)
+                .map(|index| u16_at(self.data, position + index * 2))
+                .collect::<Option<Vec<_>>>()?;
+            Some(String::from_utf16_lossy(&units))
+        }
+    }
+
+    fn utf8_length(&self, position: usize) -> Option<(usize, usize)> {
+        let first = *self.data.get(position)? as usize;
+        if first & 0x80 == 0 {
+            return Some((first, position + 1));
+        }
+        let second = *self.data.get(position + 1)? as usize;
+        Some((((first & 0x7f) << 8) | second, position + 2))
+    }
+}
+
+/// A typed attribute or resource value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum Value {
+    /// `@type/name`, as a resource ID
+    Reference(u32),
+    /// For resources, usually the path of a file in the package
+    String(String),
+    /// 0xAARRGGBB, whichever of the color forms was written
+    Color(u32),
+    Other,
+}
+
+impl Value {
+    /// Builds a value from a `Res_value`, looking strings up in `strings`
+    pub fn new(data_type: u8, data: u32, strings: &StringPool) -> Self {
+        match data_type {
+            TYPE_REFERENCE if data != 0 => Self::Reference(data),
+            TYPE_STRING => strings.get(data).map_or(Self::Other, Self::String),
+            TYPE_FIRST_COLOR..=TYPE_LAST_COLOR => Self::Color(data),
+            _ => Self::Other,
+        }
+    }
+}
+
+pub(super) struct Attribute {
+    pub name: String,
+    /// The `android:` attribute ID, which is kept even when names are stripped
+    pub resource_id: Option<u32>,
+    pub value: Value,
+}
+
+pub(super) struct Element {
+    pub name: String,
+    /// 0 for the root element
+    pub depth: usize,
+    pub attributes: Vec<Attribute>,
+}
+
+impl Element {
+    /// The value of the attribute with `resource_id`, or `name` if IDs are missing
+    pub fn attribute(&self, resource_id: u32, name: &str) -> Option<&Value> {
+        self.attributes
+            .iter()
+            .find(|attribute| attribute.resource_id == Some(resource_id))
+            .or_else(|| {
+                self.attributes
+                    .iter()
+                    .find(|attribute| attribute.resource_id.is_none() && attribute.name == name)
+            })
+            .map(|attribute| &attribute.value)
+    }
+}
+
+/// Returns the elements of a compiled XML document in document order
+pub(super) fn elements(data: &[u8]) -> Option<Vec<Element>> {
+    let document = chunks(data, 0).next()?;
+    if document.kind != XML_TYPE {
+        return None;
+    }
+    let mut strings = None;
+    let mut resource_ids: &[u8] = &[];
+    let mut elements = Vec::new();
+    let mut depth = 0;
+    for chunk in chunks(document.data, document.header_size) {
+        match chunk.kind {
+            STRING_POOL_TYPE => strings = StringPool::parse(&chunk),
+            RESOURCE_MAP_TYPE => resource_ids = &chunk.data[chunk.header_size..],
+            START_ELEMENT_TYPE => {
+                let strings = strings.as_ref()?;
+                let body = chunk.data.get(chunk.header_size..)?;
+                let name = strings.get(u32_at(body, 4)?).unwrap_or_default();
+                let attribute_start = u16_at(body, 8)? as usize;
+                let attribute_size = u16_at(body, 10)? as usize;
+                let attribute_count = u16_at(body, 12)? as usize;
+                let mut attributes = Vec::with_capacity(attribute_count);
+                for index in 0..attribute_count {
+                    let attribute = attribute_start + index * attribute_size;
+                    let name_index = u32_at(body, attribute + 4)?;
+                    let resource_id = u32_at(resource_ids, name_index as usize * 4);
+                    let data_type = *body.get(attribute + 15)?;
+                    let data = u32_at(body, attribute + 16)?;
+                    attributes.push(Attribute {
+                        name: strings.get(name_index).unwrap_or_default(),
+                        resource_id,
+                        value: Value::new(data_type, data, strings),
+                    });
+                }
+                elements.push(Element {
+                    name,
+                    depth,
+                    attributes,
+                });
+                depth += 1;
+            }
+            END_ELEMENT_TYPE => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    Some(elements)
+}
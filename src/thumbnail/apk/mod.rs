@@ -0,0 +1,224 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::path::Path;
+use zip::ZipArchive;
+
+use super::document::read_member;
+use super::raster::{decode_limited, downsample, DEFAULT_MAX_PIXELS};
+use super::{ReadSeek, Thumbnailer};
+use crate::backend::BackendError;
+use crate::image::Image;
+use binary_xml::Value;
+use resource_table::{Density, ResourceTable};
+
+mod binary_xml;
+mod resource_table;
+
+const MIME_TYPE: &str = "application/vnd.android.package-archive";
+/// `android:icon` and `android:drawable`, by attribute ID
+const ICON_ATTRIBUTE: u32 = 0x0101_0002;
+const DRAWABLE_ATTRIBUTE: u32 = 0x0101_0199;
+/// Launcher icons are 48dp, and one dp is one pixel at 160 dpi
+const ICON_DP: u32 = 48;
+const BASE_DPI: u32 = 160;
+/// Adaptive icon layers are 108dp, of which launchers show the middle 72dp
+const LAYER_DP: u32 = 108;
+const VISIBLE_LAYER_DP: u32 = 72;
+/// Resources and layers that point to other resources are followed this many times at most
+const MAX_REFERENCE_DEPTH: u8 = 8;
+
+/// Shows Android packages with their launcher icon, read from the manifest and the
+/// compiled resources. Adaptive icons are composited and masked to a circle
+pub struct ApkThumbnailer;
+
+impl Thumbnailer for ApkThumbnailer {
+    fn supports(&self, mime_type: &str) -> bool {
+        mime_type == MIME_TYPE
+    }
+
+    fn thumbnail(&self, path: &Path, size: u32) -> Result<Image, BackendError> {
+        self.thumbnail_reader(&mut BufReader::new(File::open(path)?), path, size)
+    }
+
+    fn thumbnail_reader(
+        &self,
+        reader: &mut dyn ReadSeek,
+        name: &Path,
+        size: u32,
+    ) -> Result<Image, BackendError> {
+        let mut archive = ZipArchive::new(reader)?;
+        let manifest = read_member(&mut archive, "AndroidManifest.xml")
+            .ok_or_else(|| format!("{} has no manifest", name.display()))?;
+        let resources = read_member(&mut archive, "resources.arsc")
+            .ok_or_else(|| format!("{} has no resource table", name.display()))?;
+        let table = ResourceTable::parse(&resources)
+            .ok_or_else(|| format!("{} has a malformed resource table", name.display()))?;
+        let icon = binary_xml::elements(&manifest)
+            .and_then(|elements| {
+                let application = elements
+                    .into_iter()
+                    .find(|element| element.depth == 1 && element.name == "application")?;
+                application.attribute(ICON_ATTRIBUTE, "icon").cloned()
+            })
+            .ok_or_else(|| format!("{} has no application icon", name.display()))?;
+
+        let mut package = Package { archive, table };
+        let drawable = package
+            .load(&icon, size, ICON_DP, 0)
+            .ok_or_else(|| format!("Cannot load the icon of {}", name.display()))?;
+        let image = match drawable {
+            Drawable::Bitmap(bitmap) => downsample(bitmap, size).to_rgba8(),
+            Drawable::Color(color) => RgbaImage::from_pixel(size, size, argb(color)),
+            Drawable::Adaptive {
+                background,
+                foreground,
+            } => composite_adaptive(background, foreground, size),
+        };
+        let (width, height) = image.dimensions();
+        Ok(Image::from_rgba(image.into_raw(), width, height)
+            .with_origin(name)
+            .with_thumbnail(true))
+    }
+}
+
+enum Drawable {
+    Bitmap(DynamicImage),
+    Color(u32),
+    /// A missing background is left transparent
+    Adaptive {
+        background: Option<Box<Drawable>>,
+        foreground: Box<Drawable>,
+    },
+}
+
+struct Package<'a, R> {
+    archive: ZipArchive<R>,
+    table: ResourceTable<'a>,
+}
+
+impl<R: Read + Seek> Package<'_, R> {
+    /// Loads the drawable `value` names, choosing among its variants the one for `pixels`
+    /// when it is shown `dp` large
+    fn load(&mut self, value: &Value, pixels: u32, dp: u32, depth: u8) -> Option<Drawable> {
+        // Every step counts, as adaptive icons can name themselves as a layer
+        if depth >= MAX_REFERENCE_DEPTH {
+            return None;
+        }
+        match value {
+            Value::Color(color) => Some(Drawable::Color(*color)),
+            Value::String(file) => self.load_file(file, pixels, depth),
+            Value::Reference(id) => {
+                let variants = self.table.values(*id);
+                preferred_order(variants, pixels, dp)
+                    .iter()
+                    .find_map(|variant| self.load(variant, pixels, dp, depth + 1))
+            }
+            _ => None,
+        }
+    }
+
+    /// Decodes a PNG or WebP file, or reads an adaptive icon. Vector drawables can't
+    /// be drawn, so their variants are skipped
+    fn load_file(&mut self, file: &str, pixels: u32, depth: u8) -> Option<Drawable> {
+        let data = read_member(&mut self.archive, file)?;
+        if !file.ends_with(".xml") {
+            return decode_limited(&data, DEFAULT_MAX_PIXELS)
+                .ok()
+                .map(Drawable::Bitmap);
+        }
+        let elements = binary_xml::elements(&data)?;
+        if elements.first()?.name != "adaptive-icon" {
+            return None;
+        }
+        let layer_pixels = layer_size(pixels);
+        let mut layer = |name: &str| {
+            let element = elements
+                .iter()
+                .find(|element| element.depth == 1 && element.name == name)?;
+            let value = element.attribute(DRAWABLE_ATTRIBUTE, "drawable")?.clone();
+            self.load(&value, layer_pixels, LAYER_DP, depth + 1)
+                .map(Box::new)
+        };
+        let background = layer("background");
+        let foreground = layer("foreground")?;
+        Some(Drawable::Adaptive {
+            background,
+            foreground,
+        })
+    }
+}
+
+/// Orders the variants of a resource: bitmaps with enough pixels for `pixels` from the
+/// smallest, then density independent ones like adaptive icons, then the other bitmaps
+/// from the largest
+fn preferred_order(variants: Vec<(Density, Value)>, pixels: u32, dp: u32) -> Vec<Value> {
+    let size_at = |dpi: u16| dp * dpi as u32 / BASE_DPI;
+    let mut large = Vec::new();
+    let mut scalable = Vec::new();
+    let mut small = Vec::new();
+    for (density, value) in variants {
+        let is_file =
+            |extension: &str| matches!(&value, Value::String(file) if file.ends_with(extension));
+        match density {
+            Density::Any => scalable.push((0, value)),
+            _ if is_file(".xml") => scalable.push((0, value)),
+            Density::None => small.push((size_at(BASE_DPI as u16), value)),
+            Density::Dpi(dpi) if size_at(dpi) >= pixels => large.push((size_at(dpi), value)),
+            Density::Dpi(dpi) => small.push((size_at(dpi), value)),
+        }
+    }
+    large.sort_by_key(|(size, _)| *size);
+    small.sort_by_key(|(size, _)| std::cmp::Reverse(*size));
+    large
+        .into_iter()
+        .chain(scalable)
+        .chain(small)
+        .map(|(_, value)| value)
+        .collect()
+}
+
+/// Stacks the layers as a launcher would: each scaled so its middle two thirds fill
+/// the icon, then cut to a circle
+fn composite_adaptive(
+    background: Option<Box<Drawable>>,
+    foreground: Box<Drawable>,
+    size: u32,
+) -> RgbaImage {
+    let layer_size = layer_size(size);
+    let inset = ((layer_size - size) / 2) as i64;
+    let mut icon = RgbaImage::new(size, size);
+    for layer in background.into_iter().chain([foreground]) {
+        let layer = match *layer {
+            Drawable::Bitmap(bitmap) => bitmap
+                .resize_exact(layer_size, layer_size, FilterType::Lanczos3)
+                .to_rgba8(),
+            Drawable::Color(color) => RgbaImage::from_pixel(layer_size, layer_size, argb(color)),
+            // Adaptive icons can't nest
+            Drawable::Adaptive { .. } => continue,
+        };
+        image::imageops::overlay(&mut icon, &layer, -inset, -inset);
+    }
+
+    // Antialiased by covering a pixel partly within a pixel's width of the edge
+    let radius = size as f32 / 2.0;
+    for (x, y, pixel) in icon.enumerate_pixels_mut() {
+        let distance =
+            ((x as f32 + 0.5 - radius).powi(2) + (y as f32 + 0.5 - radius).powi(2)).sqrt();
+        let coverage = (radius - distance + 0.5).clamp(0.0, 1.0);
+        pixel.0[3] = (pixel.0[3] as f32 * coverage).round() as u8;
+    }
+    icon
+}
+
+/// The size of a whole layer whose visible part is `size`
+fn layer_size(size: u32) -> u32 {
+    let layer_size = size as u64 * LAYER_DP as u64 / VISIBLE_LAYER_DP as u64;
+    layer_size.min(u32::MAX as u64) as u32
+}
+
+fn argb(color: u32) -> Rgba<u8> {
+    let [alpha, red, green, blue] = color.to_be_bytes();
+    Rgba([red, green, blue, alpha])
+}
@@ -0,0 +1,134 @@
+//! `resources.arsc`, which maps resource IDs to their value in each configuration
+
+use super::binary_xml::{chunks, u16_at, u32_at, Chunk, StringPool, Value, STRING_POOL_TYPE};
+
+const TABLE_TYPE: u16 = 0x0002;
+const PACKAGE_TYPE: u16 = 0x0200;
+const TYPE_TYPE: u16 = 0x0201;
+
+/// Entries are (index, offset / 4) pairs rather than an offset per index
+const FLAG_SPARSE: u8 = 0x01;
+/// Offsets are 16-bit and divided by four
+const FLAG_OFFSET16: u8 = 0x02;
+/// Styles and arrays, which icons never are
+const ENTRY_FLAG_COMPLEX: u16 = 0x0001;
+/// An eight byte entry with the value type in the upper flags byte (Android 14)
+const ENTRY_FLAG_COMPACT: u16 = 0x0008;
+
+/// Screen density of a configuration in dots per inch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Density {
+    Dpi(u16),
+    /// `anydpi`, used for adaptive icons and other vector drawables
+    Any,
+    /// `nodpi`, shown as is on every screen
+    None,
+}
+
+pub(super) struct ResourceTable<'a> {
+    strings: StringPool<'a>,
+    /// Package ID and the type chunks of that package
+    packages: Vec<(u8, Vec<Chunk<'a>>)>,
+}
+
+impl<'a> ResourceTable<'a> {
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        let table = chunks(data, 0).next()?;
+        if table.kind != TABLE_TYPE {
+            return None;
+        }
+        let mut strings = None;
+        let mut packages = Vec::new();
+        for chunk in chunks(table.data, table.header_size) {
+            match chunk.kind {
+                STRING_POOL_TYPE => strings = StringPool::parse(&chunk),
+                PACKAGE_TYPE => {
+                    let id = u32_at(chunk.data, 8)? as u8;
+                    let types = chunks(chunk.data, chunk.header_size)
+                        .filter(|child| child.kind == TYPE_TYPE)
+                        .collect();
+                    packages.push((id, types));
+                }
+                _ => {}
+            }
+        }
+        Some(Self {
+            strings: strings?,
+            packages,
+        })
+    }
+
+    /// The value of resource `id` in every configuration that defines it
+    pub fn values(&self, id: u32) -> Vec<(Density, Value)> {
+        let (package_id, type_id, index) = ((id >> 24) as u8, (id >> 16) as u8, id as u16);
+        self.packages
+            .iter()
+            .filter(|(id, _)| *id == package_id)
+            .flat_map(|(_, types)| types)
+            .filter(|chunk| chunk.data.get(8) == Some(&type_id))
+            .filter_map(|chunk| self.entry(chunk, index))
+            .collect()
+    }
+
+    fn entry(&self, chunk: &Chunk, index: u16) -> Option<(Density, Value)> {
+        let data = chunk.data;
+        let flags = *data.get(9)?;
+        let entry_count = u32_at(data, 12)? as usize;
+        let entries_start = u32_at(data, 16)? as usize;
+        // The configuration starts after the fixed fields, density is at byte 14 of it
+        let density = match u16_at(data, 20 + 14).unwrap_or(0) {
+            0xfffe => Density::Any,
+            0xffff => Density::None,
+            // Unset means the default, medium density
+            0 => Density::Dpi(160),
+            dpi => Density::Dpi(dpi),
+        };
+
+        let offsets = chunk.header_size;
+        let offset = if flags & FLAG_SPARSE != 0 {
+            // The count comes from the file, so only the pairs before the entries are read
+            let end = entries_start.min(data.len());
+            let entry_count = entry_count.min(end.saturating_sub(offsets) / 4);
+            (0..entry_count).find_map(|position| {
+                let entry = offsets + position * 4;
+                (u16_at(data, entry)? == index).then(|| u16_at(data, entry + 2))?
+            })? as usize
+                * 4
+        } else if (index as usize) < entry_count {
+            if flags & FLAG_OFFSET16 != 0 {
+                match u16_at(data, offsets + index as usize * 2)? {
+                    0xffff => return None,
+                    offset => offset as usize * 4,
+                }
+            } else {
+                match u32_at(data, offsets + index as usize * 4)? {
+                    0xffff_ffff => return None,
+                    offset => offset as usize,
+                }
+            }
+        } else {
+            return None;
+        };
+
+        let entry = entries_start.checked_add(offset)?;
+        let entry_size = u16_at(data, entry)? as usize;
+        let entry_flags = u16_at(data, entry + 2)?;
+        let value = if entry_flags & ENTRY_FLAG_COMPACT != 0 {
+            Value::new(
+                (entry_flags >> 8) as u8,
+                u32_at(data, entry + 4)?,
+                &self.strings,
+            )
+        } else if entry_flags & ENTRY_FLAG_COMPLEX != 0 {
+            Value::Other
+        } else {
+            let value = entry + entry_size;
+            Value::new(
+                *data.get(value + 3)?,
+                u32_at(data, value + 4)?,
+                &self.strings,
+            )
+        };
+        Some((density, value))
+    }
+}
@@ -0,0 +1,109 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use super::{decode_embedded_icon, ReadSeek, Thumbnailer};
+use crate::backend::BackendError;
+use crate::freedesktop::keyfile::KeyFile;
+use crate::image::Image;
+use squashfs::SquashFs;
+
+mod squashfs;
+
+const MIME_TYPE: &str = "application/vnd.appimage";
+/// Icons and desktop files larger than this aren't read
+const MAX_FILE_LENGTH: u64 = 16 * 1024 * 1024;
+/// Where the desktop file's `Icon` is looked for, after the root of the image
+const ICON_DIRS: &[&str] = &[
+    "usr/share/icons/hicolor/256x256/apps",
+    "usr/share/icons/hicolor/scalable/apps",
+    "usr/share/pixmaps",
+];
+
+/// Shows AppImages with the icon in their SquashFS payload, without mounting it:
+/// `.DirIcon`, or else the `Icon` of the desktop file at the root
+pub struct AppImageThumbnailer;
+
+impl Thumbnailer for AppImageThumbnailer {
+    fn supports(&self, mime_type: &str) -> bool {
+        mime_type == MIME_TYPE
+    }
+
+    fn thumbnail(&self, path: &Path, size: u32) -> Result<Image, BackendError> {
+        self.thumbnail_reader(&mut BufReader::new(File::open(path)?), path, size)
+    }
+
+    fn thumbnail_reader(
+        &self,
+        reader: &mut dyn ReadSeek,
+        name: &Path,
+        size: u32,
+    ) -> Result<Image, BackendError> {
+        let start = payload_offset(&mut *reader)?;
+        let mut image = SquashFs::open(reader, start)?;
+        let icon = image
+            .read_file(".DirIcon", MAX_FILE_LENGTH)
+            .ok()
+            .or_else(|| desktop_icon(&mut image))
+            .ok_or_else(|| format!("{} has no icon", name.display()))?;
+
+        Ok(decode_embedded_icon(icon, size, name)?
+            .with_origin(name)
+            .with_thumbnail(true))
+    }
+}
+
+/// Where the SquashFS image starts: right after the ELF runtime, whose section headers
+/// come last, or at the start of the file for a bare image
+fn payload_offset(mut file: impl Read) -> Result<u64, BackendError> {
+    let mut header = [0; 64];
+    file.read_exact(&mut header)?;
+    if header.starts_with(b"hsqs") {
+        return Ok(0);
+    }
+    if !header.starts_with(b"\x7fELF") {
+        return Err("Not an ELF executable".into());
+    }
+    let is_64_bit = header[4] == 2;
+    let is_big_endian = header[5] == 2;
+    let read = |offset: usize, length: usize| {
+        let bytes = &header[offset..offset + length];
+        let fold = |value: u64, byte: &u8| value << 8 | *byte as u64;
+        match is_big_endian {
+            true => bytes.iter().fold(0, fold),
+            false => bytes.iter().rev().fold(0, fold),
+        }
+    };
+    let (section_headers, entry_size, count) = match is_64_bit {
+        true => (read(0x28, 8), read(0x3a, 2), read(0x3c, 2)),
+        false => (read(0x20, 4), read(0x2e, 2), read(0x30, 2)),
+    };
+    entry_size
+        .checked_mul(count)
+        .and_then(|length| section_headers.checked_add(length))
+        .ok_or_else(|| "The ELF section headers end out of range".into())
+}
+
+/// Reads the icon the desktop file at the root of the image names
+fn desktop_icon<R: Read + std::io::Seek>(image: &mut SquashFs<R>) -> Option<Vec<u8>> {
+    let desktop_file = image
+        .list("")
+        .ok()?
+        .into_iter()
+        .find(|name| name.ends_with(".desktop"))?;
+    let text = image.read_file(&desktop_file, MAX_FILE_LENGTH).ok()?;
+    let entry = KeyFile::parse(&String::from_utf8_lossy(&text));
+    let icon = entry.get("Desktop Entry", "Icon")?;
+    if icon.contains('/') {
+        return image.read_file(icon, MAX_FILE_LENGTH).ok();
+    }
+    let names = [
+        format!("{icon}.png"),
+        format!("{icon}.svg"),
+        icon.to_string(),
+    ];
+    std::iter::once("")
+        .chain(ICON_DIRS.iter().copied())
+        .flat_map(|dir| names.iter().map(move |name| format!("{dir}/{name}")))
+        .find_map(|candidate| image.read_file(&candidate, MAX_FILE_LENGTH).ok())
+}
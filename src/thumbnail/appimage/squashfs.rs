@@ -0,0 +1,456 @@
+//! Read-only access to SquashFS 4.0 images, the file system AppImages carry
+
+use flate2::read::ZlibDecoder;
+use std::io::{self, Read, Seek, SeekFrom};
+
+const MAGIC: &[u8; 4] = b"hsqs";
+const SUPERBLOCK_LENGTH: usize = 96;
+const METADATA_BLOCK_LENGTH: usize = 8192;
+/// Set in a metadata block header when the block is stored as is
+const METADATA_UNCOMPRESSED: u16 = 0x8000;
+/// Set in a data block size when the block is stored as is
+const DATA_UNCOMPRESSED: u32 = 1 << 24;
+const NO_FRAGMENT: u32 = 0xffff_ffff;
+/// Fragment table entries per metadata block
+const FRAGMENTS_PER_BLOCK: u32 = 512;
+
+const GZIP: u16 = 1;
+const XZ: u16 = 4;
+const ZSTD: u16 = 6;
+
+const BASIC_DIRECTORY: u16 = 1;
+const BASIC_FILE: u16 = 2;
+const BASIC_SYMLINK: u16 = 3;
+const EXTENDED_DIRECTORY: u16 = 8;
+const EXTENDED_FILE: u16 = 9;
+const EXTENDED_SYMLINK: u16 = 10;
+
+/// Symbolic links followed while resolving one path
+const MAX_SYMLINKS: usize = 8;
+/// Directory listings and block lists larger than this aren't read
+const MAX_METADATA_LENGTH: usize = 16 * 1024 * 1024;
+const MAX_SYMLINK_LENGTH: usize = 4096;
+
+enum Inode {
+    Directory {
+        block: u32,
+        offset: u16,
+        /// Of the listing, plus three for the `.` and `..` entries it doesn't store
+        length: u32,
+    },
+    File {
+        blocks_start: u64,
+        length: u64,
+        fragment: u32,
+        fragment_offset: u32,
+        block_sizes: Vec<u32>,
+    },
+    Symlink(String),
+    Other,
+}
+
+/// A SquashFS image inside `reader`, which may hold other data before it
+pub(super) struct SquashFs<R> {
+    reader: R,
+    /// Where the image starts in `reader`. Positions in the image are relative to it
+    start: u64,
+    compressor: u16,
+    block_size: u32,
+    root: u64,
+    inode_table: u64,
+    directory_table: u64,
+    fragment_table: u64,
+    fragment_count: u32,
+}
+
+impl<R: Read + Seek> SquashFs<R> {
+    pub fn open(mut reader: R, start: u64) -> io::Result<Self> {
+        let mut superblock = [0; SUPERBLOCK_LENGTH];
+        reader.seek(SeekFrom::Start(start))?;
+        reader.read_exact(&mut superblock)?;
+        if &superblock[..4] != MAGIC || u16_at(&superblock, 28) != 4 {
+            return Err(invalid("Not a SquashFS 4.0 image".to_string()));
+        }
+        let compressor = u16_at(&superblock, 20);
+        if ![GZIP, XZ, ZSTD].contains(&compressor) {
+            return Err(invalid(format!(
+                "Unsupported SquashFS compressor {compressor}"
+            )));
+        }
+        let block_size = u32_at(&superblock, 12);
+        if !(4096..=1024 * 1024).contains(&block_size) {
+            return Err(invalid(format!("Invalid SquashFS block size {block_size}")));
+        }
+        Ok(Self {
+            reader,
+            start,
+            compressor,
+            block_size,
+            fragment_count: u32_at(&superblock, 16),
+            root: u64_at(&superblock, 32),
+            inode_table: u64_at(&superblock, 64),
+            directory_table: u64_at(&superblock, 72),
+            fragment_table: u64_at(&superblock, 80),
+        })
+    }
+
+    /// Reads the file at `path`, relative to the root, following symbolic links.
+    /// Files longer than `max_length` are refused
+    pub fn read_file(&mut self, path: &str, max_length: u64) -> io::Result<Vec<u8>> {
+        let Inode::File {
+            blocks_start,
+            length,
+            fragment,
+            fragment_offset,
+            block_sizes,
+        } = self.resolve(path)?
+        else {
+            return Err(invalid(format!("{path} is not a file")));
+        };
+        if length > max_length {
+            return Err(invalid(format!("{path} is too large")));
+        }
+
+        let mut data = Vec::with_capacity(length as usize);
+        let mut position = blocks_start;
+        for size in block_sizes {
+            let stored = size & !DATA_UNCOMPRESSED;
+            if stored == 0 {
+                // A sparse block
+                data.resize(data.len() + self.block_size as usize, 0);
+                continue;
+            }
+            let block = self.read_at(position, stored as usize)?;
+            position = past(position, stored as u64)?;
+            match size & DATA_UNCOMPRESSED != 0 {
+                true => data.extend(block),
+                false => data.extend(self.decompress(&block, self.block_size as usize)?),
+            }
+        }
+        if fragment != NO_FRAGMENT {
+            let block = self.fragment(fragment)?;
+            let tail = (length as usize).saturating_sub(data.len());
+            let start = fragment_offset as usize;
+            data.extend(
+                block
+                    .get(start..start + tail)
+                    .ok_or_else(|| invalid(format!("{path} ends outside its fragment")))?,
+            );
+        }
+        data.truncate(length as usize);
+        Ok(data)
+    }
+
+    /// The names in the directory at `path`
+    pub fn list(&mut self, path: &str) -> io::Result<Vec<String>> {
+        match self.resolve(path)? {
+            Inode::Directory {
+                block,
+                offset,
+                length,
+            } => Ok(self
+                .entries(block, offset, length)?
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect()),
+            _ => Err(invalid(format!("{path} is not a directory"))),
+        }
+    }
+
+    /// Finds the inode at `path`, following symbolic links in any component.
+    /// Absolute link targets are relative to the root of the image
+    fn resolve(&mut self, path: &str) -> io::Result<Inode> {
+        let mut remaining: Vec<String> = path.rsplit('/').map(str::to_string).collect();
+        // References of the directories from the root to the current one
+        let mut directories = vec![self.root];
+        let mut current = self.inode(self.root)?;
+        let mut links = 0;
+        while let Some(component) = remaining.pop() {
+            match component.as_str() {
+                "" | "." => continue,
+                ".." => {
+                    if directories.len() > 1 {
+                        directories.pop();
+                    }
+                    current = self.inode(*directories.last().unwrap())?;
+                    continue;
+                }
+                _ => {}
+            }
+            let Inode::Directory {
+                block,
+                offset,
+                length,
+            } = current
+            else {
+                return Err(invalid(format!("{path} is not in a directory")));
+            };
+            let reference = self
+                .entries(block, offset, length)?
+                .into_iter()
+                .find(|(name, _)| *name == component)
+                .map(|(_, reference)| reference)
+                .ok_or_else(|| invalid(format!("{path} is not in the image")))?;
+            current = match self.inode(reference)? {
+                Inode::Symlink(target) => {
+                    links += 1;
+                    if links > MAX_SYMLINKS {
+                        return Err(invalid(format!("{path} has too many symbolic links")));
+                    }
+                    if target.starts_with('/') {
+                        directories.truncate(1);
+                    }
+                    remaining.extend(target.rsplit('/').map(str::to_string));
+                    self.inode(*directories.last().unwrap())?
+                }
+                inode @ Inode::Directory { .. } => {
+                    directories.push(reference);
+                    inode
+                }
+                inode => inode,
+            };
+        }
+        Ok(current)
+    }
+
+    fn inode(&mut self, reference: u64) -> io::Result<Inode> {
+        let mut metadata = Metadata::new(past(self.inode_table, reference >> 16)?);
+        metadata.read(self, (reference & 0xffff) as usize)?;
+        let kind = u16_at(&metadata.read(self, 16)?, 0);
+        Ok(match kind {
+            BASIC_DIRECTORY => {
+                let fields = metadata.read(self, 16)?;
+                Inode::Directory {
+                    block: u32_at(&fields, 0),
+                    offset: u16_at(&fields, 10),
+                    length: u16_at(&fields, 8) as u32,
+                }
+            }
+            EXTENDED_DIRECTORY => {
+                let fields = metadata.read(self, 24)?;
+                Inode::Directory {
+                    block: u32_at(&fields, 8),
+                    offset: u16_at(&fields, 18),
+                    length: u32_at(&fields, 4),
+                }
+            }
+            BASIC_FILE | EXTENDED_FILE => {
+                let (blocks_start, length, fragment, fragment_offset) = if kind == BASIC_FILE {
+                    let fields = metadata.read(self, 16)?;
+                    let length = u32_at(&fields, 12) as u64;
+                    (
+                        u32_at(&fields, 0) as u64,
+                        length,
+                        u32_at(&fields, 4),
+                        u32_at(&fields, 8),
+                    )
+                } else {
+                    let fields = metadata.read(self, 40)?;
+                    let length = u64_at(&fields, 8);
+                    (
+                        u64_at(&fields, 0),
+                        length,
+                        u32_at(&fields, 28),
+                        u32_at(&fields, 32),
+                    )
+                };
+                // The end of a file is in a fragment, unless it has a block of its own
+                let block_size = self.block_size as u64;
+                let count = match fragment {
+                    NO_FRAGMENT => length.div_ceil(block_size),
+                    _ => length / block_size,
+                };
+                if count as usize > MAX_METADATA_LENGTH / 4 {
+                    return Err(invalid("A file in the image is too large".to_string()));
+                }
+                let sizes = metadata.read(self, count as usize * 4)?;
+                Inode::File {
+                    blocks_start,
+                    length,
+                    fragment,
+                    fragment_offset,
+                    block_sizes: sizes.chunks(4).map(|size| u32_at(size, 0)).collect(),
+                }
+            }
+            BASIC_SYMLINK | EXTENDED_SYMLINK => {
+                let length = u32_at(&metadata.read(self, 8)?, 4) as usize;
+                if length > MAX_SYMLINK_LENGTH {
+                    return Err(invalid(
+                        "A symbolic link in the image is too long".to_string(),
+                    ));
+                }
+                let target = metadata.read(self, length)?;
+                Inode::Symlink(String::from_utf8_lossy(&target).into_owned())
+            }
+            _ => Inode::Other,
+        })
+    }
+
+    /// The names and inode references of a directory listing
+    fn entries(&mut self, block: u32, offset: u16, length: u32) -> io::Result<Vec<(String, u64)>> {
+        let length = length.saturating_sub(3) as usize;
+        if length > MAX_METADATA_LENGTH {
+            return Err(invalid("A directory in the image is too large".to_string()));
+        }
+        let mut metadata = Metadata::new(past(self.directory_table, block as u64)?);
+        metadata.read(self, offset as usize)?;
+        let listing = metadata.read(self, length)?;
+
+        let mut entries = Vec::new();
+        let mut position = 0;
+        // Entries come in runs that share the metadata block of their inodes
+        while position + 12 <= listing.len() {
+            let count = u32_at(&listing, position) as usize + 1;
+            let inode_block = u32_at(&listing, position + 4) as u64;
+            position += 12;
+            for _ in 0..count {
+                let header = listing
+                    .get(position..position + 8)
+                    .ok_or_else(|| invalid("A directory in the image is truncated".to_string()))?;
+                let inode_offset = u16_at(header, 0) as u64;
+                let name_length = u16_at(header, 6) as usize + 1;
+                let name = listing
+                    .get(position + 8..position + 8 + name_length)
+                    .ok_or_else(|| invalid("A directory in the image is truncated".to_string()))?;
+                entries.push((
+                    String::from_utf8_lossy(name).into_owned(),
+                    inode_block << 16 | inode_offset,
+                ));
+                position += 8 + name_length;
+            }
+        }
+        Ok(entries)
+    }
+
+    /// The decompressed fragment block at `index`, holding the ends of small files
+    fn fragment(&mut self, index: u32) -> io::Result<Vec<u8>> {
+        if index >= self.fragment_count {
+            return Err(invalid(format!("Fragment {index} is not in the image")));
+        }
+        let pointer = past(
+            self.fragment_table,
+            (index / FRAGMENTS_PER_BLOCK) as u64 * 8,
+        )?;
+        let block = u64_at(&self.read_at(pointer, 8)?, 0);
+        let mut metadata = Metadata::new(block);
+        metadata.read(self, (index % FRAGMENTS_PER_BLOCK) as usize * 16)?;
+        let entry = metadata.read(self, 16)?;
+        let size = u32_at(&entry, 8);
+        let data = self.read_at(u64_at(&entry, 0), (size & !DATA_UNCOMPRESSED) as usize)?;
+        match size & DATA_UNCOMPRESSED != 0 {
+            true => Ok(data),
+            false => self.decompress(&data, self.block_size as usize),
+        }
+    }
+
+    /// Reads the metadata block at `position`, returning its content and the position of
+    /// the next block
+    fn metadata_block(&mut self, position: u64) -> io::Result<(Vec<u8>, u64)> {
+        let header = u16_at(&self.read_at(position, 2)?, 0);
+        let length = (header & !METADATA_UNCOMPRESSED) as usize;
+        let data = self.read_at(past(position, 2)?, length)?;
+        let next = past(position, 2 + length as u64)?;
+        match header & METADATA_UNCOMPRESSED != 0 {
+            true => Ok((data, next)),
+            false => Ok((self.decompress(&data, METADATA_BLOCK_LENGTH)?, next)),
+        }
+    }
+
+    fn read_at(&mut self, position: u64, length: usize) -> io::Result<Vec<u8>> {
+        if length > self.block_size as usize + METADATA_BLOCK_LENGTH {
+            return Err(invalid("A block in the image is too large".to_string()));
+        }
+        self.reader
+            .seek(SeekFrom::Start(past(self.start, position)?))?;
+        let mut data = vec![0; length];
+        self.reader.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// Decompresses a block, which can't be longer than `limit`
+    fn decompress(&self, data: &[u8], limit: usize) -> io::Result<Vec<u8>> {
+        let mut decompressed = Vec::new();
+        match self.compressor {
+            GZIP => {
+                ZlibDecoder::new(data)
+                    .take(limit as u64 + 1)
+                    .read_to_end(&mut decompressed)?;
+            }
+            XZ => {
+                lzma_rust2::XzReader::new(data, false)
+                    .take(limit as u64 + 1)
+                    .read_to_end(&mut decompressed)?;
+            }
+            _ => {
+                ruzstd::decoding::StreamingDecoder::new(data)
+                    .map_err(|error| invalid(error.to_string()))?
+                    .take(limit as u64 + 1)
+                    .read_to_end(&mut decompressed)?;
+            }
+        }
+        match decompressed.len() > limit {
+            true => Err(invalid(
+                "A block in the image decompresses too far".to_string(),
+            )),
+            false => Ok(decompressed),
+        }
+    }
+}
+
+/// Reads through consecutive metadata blocks, as inodes and listings can span them
+struct Metadata {
+    next_block: u64,
+    data: Vec<u8>,
+    position: usize,
+}
+
+impl Metadata {
+    fn new(block: u64) -> Self {
+        Self {
+            next_block: block,
+            data: Vec::new(),
+            position: 0,
+        }
+    }
+
+    fn read<R: Read + Seek>(
+        &mut self,
+        image: &mut SquashFs<R>,
+        length: usize,
+    ) -> io::Result<Vec<u8>> {
+        while self.data.len() < self.position + length {
+            let (block, next) = image.metadata_block(self.next_block)?;
+            if block.is_empty() {
+                return Err(invalid("Metadata in the image is truncated".to_string()));
+            }
+            self.data.extend(block);
+            self.next_block = next;
+        }
+        let data = self.data[self.position..self.position + length].to_vec();
+        self.position += length;
+        Ok(data)
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The position `length` bytes past `position`, which both come from the image
+fn past(position: u64, length: u64) -> io::Result<u64> {
+    position
+        .checked_add(length)
+        .ok_or_else(|| invalid("A position in the image is out of range".to_string()))
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
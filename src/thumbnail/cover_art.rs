@@ -0,0 +1,445 @@
+use base64::Engine;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use super::raster::{decode_limited, downsample, DEFAULT_MAX_PIXELS};
+use super::{ReadSeek, Thumbnailer};
+use crate::backend::BackendError;
+use crate::image::Image;
+
+/// Picture type of the front cover in ID3 and FLAC
+const FRONT_COVER: u32 = 3;
+/// Tags and pictures larger than this are skipped rather than read into memory
+const MAX_TAG_LENGTH: u64 = 64 * 1024 * 1024;
+/// Ogg pages read while looking for the comment header
+const MAX_OGG_PAGES: usize = 1024;
+
+/// Including the older names some MIME databases still use
+const MIME_TYPES: &[&str] = &[
+    "audio/mpeg",
+    "audio/flac",
+    "audio/x-flac",
+    "audio/ogg",
+    "audio/x-vorbis+ogg",
+    "audio/x-opus+ogg",
+    "audio/opus",
+    "audio/mp4",
+    "audio/x-m4a",
+];
+
+/// An image stored in an audio file's tags
+#[derive(Debug, Clone)]
+pub struct Picture {
+    /// ID3 picture type, 3 for the front cover. 0 (other) when the format doesn't say
+    pub kind: u32,
+    /// The encoded image, usually a JPEG or PNG
+    pub data: Vec<u8>,
+}
+
+/// Previews MP3, FLAC, Ogg Vorbis, Opus and M4A files with their album art
+pub struct CoverArtThumbnailer;
+
+impl Thumbnailer for CoverArtThumbnailer {
+    fn supports(&self, mime_type: &str) -> bool {
+        MIME_TYPES.contains(&mime_type)
+    }
+
+    fn thumbnail(&self, path: &Path, size: u32) -> Result<Image, BackendError> {
+        self.thumbnail_reader(&mut BufReader::new(File::open(path)?), path, size)
+    }
+
+    fn thumbnail_reader(
+        &self,
+        reader: &mut dyn ReadSeek,
+        name: &Path,
+        size: u32,
+    ) -> Result<Image, BackendError> {
+        let pictures = read_pictures(reader)?;
+        let picture = pictures
+            .iter()
+            .find(|picture| picture.kind == FRONT_COVER)
+            .or_else(|| pictures.first())
+            .ok_or_else(|| format!("{} has no cover art", name.display()))?;
+        let rgba = downsample(decode_limited(&picture.data, DEFAULT_MAX_PIXELS)?, size).to_rgba8();
+        let (width, height) = rgba.dimensions();
+        Ok(Image::from_rgba(rgba.into_raw(), width, height)
+            .with_origin(name)
+            .with_thumbnail(true))
+    }
+}
+
+/// Reads the pictures embedded in an audio file, in the order the file stores them.
+/// The container is recognized by its content, not its extension
+pub fn read_pictures(reader: impl Read + Seek) -> std::io::Result<Vec<Picture>> {
+    let mut reader = BufReader::new(reader);
+    let mut magic = [0; 8];
+    let length = reader.read(&mut magic)?;
+    reader.seek(SeekFrom::Start(0))?;
+    match &magic[..length] {
+        [b'I', b'D', b'3', ..] => {
+            let (pictures, end) = read_id3(&mut reader)?;
+            // FLAC files sometimes start with an ID3 tag as well
+            if pictures.is_empty() {
+                reader.seek(SeekFrom::Start(end))?;
+                let mut marker = [0; 4];
+                if reader.read_exact(&mut marker).is_ok() && &marker == b"fLaC" {
+                    return read_flac(&mut reader, end);
+                }
+            }
+            Ok(pictures)
+        }
+        [b'f', b'L', b'a', b'C', ..] => read_flac(&mut reader, 0),
+        [b'O', b'g', b'g', b'S', ..] => read_ogg(&mut reader),
+        [_, _, _, _, b'f', b't', b'y', b'p'] => read_mp4(&mut reader),
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Reads the `APIC` (or ID3v2.2 `PIC`) frames of the ID3v2 tag at the start of the file.
+/// Also returns where the tag ends
+fn read_id3(reader: &mut (impl Read + Seek)) -> std::io::Result<(Vec<Picture>, u64)> {
+    let mut header = [0; 10];
+    reader.read_exact(&mut header)?;
+    let version = header[3];
+    let flags = header[5];
+    let length = synchsafe(&header[6..10]) as u64;
+    let has_footer = version == 4 && flags & 0x10 != 0;
+    let end = 10 + length + if has_footer { 10 } else { 0 };
+    if !(2..=4).contains(&version) || length > MAX_TAG_LENGTH {
+        return Ok((Vec::new(), end));
+    }
+
+    let mut tag = vec![0; length as usize];
+    reader.read_exact(&mut tag)?;
+    // Before 2.4, unsynchronisation applies to the whole tag rather than to each frame
+    if version < 4 && flags & 0x80 != 0 {
+        tag = resynchronise(&tag);
+    }
+
+    let mut position = 0;
+    if version > 2 && flags & 0x40 != 0 && tag.len() >= 4 {
+        position = match version {
+            4 => synchsafe(&tag[0..4]) as usize,
+            // The 2.3 size doesn't include its own four bytes
+            _ => u32::from_be_bytes([tag[0], tag[1], tag[2], tag[3]]) as usize + 4,
+        };
+    }
+
+    let (id_length, header_length) = if version == 2 { (3, 6) } else { (4, 10) };
+    let mut pictures = Vec::new();
+    while position + header_length <= tag.len() {
+        let frame = &tag[position..position + header_length];
+        // Padding fills the rest of the tag with zeros
+        if frame[0] == 0 {
+            break;
+        }
+        let id = &frame[..id_length];
+        let size = match version {
+            2 => u32::from_be_bytes([0, frame[3], frame[4], frame[5]]),
+            3 => u32::from_be_bytes([frame[4], frame[5], frame[6], frame[7]]),
+            _ => synchsafe(&frame[4..8]),
+        } as usize;
+        let format_flags = if version == 2 { 0 } else { frame[9] };
+        let start = position + header_length;
+        let Some(body) = tag.get(start..start + size) else {
+            break;
+        };
+        position = start + size;
+        if id != b"APIC" && id != b"PIC" {
+            continue;
+        }
+
+        let picture = match version {
+            // Compressed and encrypted frames can't be read without more machinery
+            3 if format_flags & 0xc0 != 0 => None,
+            4 if format_flags & 0x0c != 0 => None,
+            4 => {
+                let mut body = body;
+                if format_flags & 0x01 != 0 {
+                    // Data length indicator
+                    body = body.get(4..).unwrap_or_default();
+                }
+                if format_flags & 0x02 != 0 {
+                    parse_apic(&resynchronise(body), false)
+                } else {
+                    parse_apic(body, false)
+                }
+            }
+            _ => parse_apic(body, version == 2),
+        };
+        pictures.extend(picture);
+    }
+    Ok((pictures, end))
+}
+
+/// Parses an `APIC` frame body: encoding, MIME type (a three letter format in `PIC`),
+/// picture type, description and the image
+fn parse_apic(body: &[u8], is_v22: bool) -> Option<Picture> {
+    let (&encoding, rest) = body.split_first()?;
+    let rest = if is_v22 {
+        rest.get(3..)?
+    } else {
+        let end = rest.iter().position(|&byte| byte == 0)?;
+        &rest[end + 1..]
+    };
+    let (&kind, rest) = rest.split_first()?;
+    let data = skip_terminated(rest, encoding)?;
+    Some(Picture {
+        kind: kind as u32,
+        data: data.to_vec(),
+    })
+}
+
+/// Skips a string ending in a terminator of the text `encoding`. UTF-16 strings end in
+/// two zero bytes on an even offset, the other encodings in a single zero byte
+fn skip_terminated(data: &[u8], encoding: u8) -> Option<&[u8]> {
+    if encoding == 1 || encoding == 2 {
+        let end = data.chunks_exact(2).position(|pair| pair == [0, 0])?;
+        data.get(end * 2 + 2..)
+    } else {
+        let end = data.iter().position(|&byte| byte == 0)?;
+        data.get(end + 1..)
+    }
+}
+
+/// Undoes ID3 unsynchronisation, which inserts a zero byte after every 0xFF
+fn resynchronise(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len());
+    let mut previous = 0;
+    for &byte in data {
+        if !(previous == 0xff && byte == 0) {
+            output.push(byte);
+        }
+        previous = byte;
+    }
+    output
+}
+
+/// ID3 sizes use seven bits per byte so they never look like a sync marker
+fn synchsafe(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .fold(0, |value, &byte| (value << 7) | (byte & 0x7f) as u32)
+}
+
+/// Reads the `PICTURE` metadata blocks of a FLAC stream starting at `start`
+fn read_flac(reader: &mut (impl Read + Seek), start: u64) -> std::io::Result<Vec<Picture>> {
+    reader.seek(SeekFrom::Start(start + 4))?;
+    let mut pictures = Vec::new();
+    loop {
+        let mut header = [0; 4];
+        reader.read_exact(&mut header)?;
+        let is_last = header[0] & 0x80 != 0;
+        let length = u32::from_be_bytes([0, header[1], header[2], header[3]]) as u64;
+        if header[0] & 0x7f == 6 && length <= MAX_TAG_LENGTH {
+            let mut block = vec![0; length as usize];
+            reader.read_exact(&mut block)?;
+            pictures.extend(parse_flac_picture(&block));
+        } else {
+            reader.seek(SeekFrom::Current(length as i64))?;
+        }
+        if is_last {
+            return Ok(pictures);
+        }
+    }
+}
+
+/// Parses a FLAC `PICTURE` block, which Vorbis comments also carry in base64
+fn parse_flac_picture(block: &[u8]) -> Option<Picture> {
+    let mut position = 0;
+    let kind = u32::from_be_bytes(next_bytes(block, &mut position)?);
+    let mime_length = u32::from_be_bytes(next_bytes(block, &mut position)?) as usize;
+    position = position.checked_add(mime_length)?;
+    let description_length = u32::from_be_bytes(next_bytes(block, &mut position)?) as usize;
+    // Width, height, color depth and palette size follow, which the image itself knows better
+    position = position.checked_add(description_length)?.checked_add(16)?;
+    let length = u32::from_be_bytes(next_bytes(block, &mut position)?) as usize;
+    let data = block.get(position..position.checked_add(length)?)?;
+    Some(Picture {
+        kind,
+        data: data.to_vec(),
+    })
+}
+
+/// Returns the `N` bytes at `position` and moves past them
+fn next_bytes<const N: usize>(data: &[u8], position: &mut usize) -> Option<[u8; N]> {
+    let bytes = data.get(*position..position.checked_add(N)?)?;
+    *position += N;
+    bytes.try_into().ok()
+}
+
+/// Reads the pictures in the comment header of an Ogg Vorbis or Opus stream: base64
+/// FLAC blocks in `METADATA_BLOCK_PICTURE`, or plain images in the older `COVERART`
+fn read_ogg(reader: &mut (impl Read + Seek)) -> std::io::Result<Vec<Picture>> {
+    let Some(packet) = second_ogg_packet(reader)? else {
+        return Ok(Vec::new());
+    };
+    let Some(comments) = packet
+        .strip_prefix(b"\x03vorbis")
+        .or_else(|| packet.strip_prefix(b"OpusTags"))
+    else {
+        return Ok(Vec::new());
+    };
+
+    let mut position = 0;
+    let next_u32 = |position: &mut usize| {
+        next_bytes(comments, position).map(|bytes| u32::from_le_bytes(bytes) as usize)
+    };
+    let Some(vendor_length) = next_u32(&mut position) else {
+        return Ok(Vec::new());
+    };
+    position = position.saturating_add(vendor_length);
+    let count = next_u32(&mut position).unwrap_or(0);
+
+    let base64 = base64::engine::general_purpose::STANDARD;
+    let mut pictures = Vec::new();
+    for _ in 0..count {
+        let Some(length) = next_u32(&mut position) else {
+            break;
+        };
+        let Some(comment) = comments.get(position..position.saturating_add(length)) else {
+            break;
+        };
+        position += length;
+        let Some(separator) = comment.iter().position(|&byte| byte == b'=') else {
+            continue;
+        };
+        let (key, value) = (&comment[..separator], &comment[separator + 1..]);
+        if key.eq_ignore_ascii_case(b"METADATA_BLOCK_PICTURE") {
+            if let Ok(block) = base64.decode(value) {
+                pictures.extend(parse_flac_picture(&block));
+            }
+        } else if key.eq_ignore_ascii_case(b"COVERART") {
+            if let Ok(data) = base64.decode(value) {
+                pictures.push(Picture { kind: 0, data });
+            }
+        }
+    }
+    Ok(pictures)
+}
+
+/// Reassembles the second packet of the first logical stream, which holds the comments.
+/// Pages of other streams are skipped and the CRC isn't checked
+fn second_ogg_packet(reader: &mut (impl Read + Seek)) -> std::io::Result<Option<Vec<u8>>> {
+    let mut serial = None;
+    let mut packets = 0;
+    let mut packet = Vec::new();
+    for _ in 0..MAX_OGG_PAGES {
+        let mut header = [0; 27];
+        if reader.read_exact(&mut header).is_err() || &header[..4] != b"OggS" {
+            return Ok(None);
+        }
+        let page_serial = u32::from_le_bytes([header[14], header[15], header[16], header[17]]);
+        let mut lacing = vec![0; header[26] as usize];
+        reader.read_exact(&mut lacing)?;
+        if *serial.get_or_insert(page_serial) != page_serial {
+            let body = lacing.iter().map(|&length| length as i64).sum();
+            reader.seek(SeekFrom::Current(body))?;
+            continue;
+        }
+        for length in lacing {
+            let mut segment = vec![0; length as usize];
+            reader.read_exact(&mut segment)?;
+            if packets == 1 {
+                packet.extend_from_slice(&segment);
+                if packet.len() as u64 > MAX_TAG_LENGTH {
+                    return Ok(None);
+                }
+            }
+            // A segment shorter than 255 bytes ends the packet
+            if length < 255 {
+                packets += 1;
+                if packets == 2 {
+                    return Ok(Some(packet));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Reads the `data` atoms of `moov/udta/meta/ilst/covr`, where iTunes keeps cover art.
+/// MP4 has no picture types, but the front cover is conventionally first
+fn read_mp4(reader: &mut (impl Read + Seek)) -> std::io::Result<Vec<Picture>> {
+    let end = reader.seek(SeekFrom::End(0))?;
+    let mut range = (0, end);
+    for name in [b"moov", b"udta", b"meta", b"ilst", b"covr"] {
+        let Some((start, end)) = find_atom(reader, range, name)? else {
+            return Ok(Vec::new());
+        };
+        range = (start, end);
+        if name == b"meta" {
+            // `meta` is a full box with version and flags first, except in some QuickTime
+            // files where its first child follows right away
+            reader.seek(SeekFrom::Start(start + 4))?;
+            let mut kind = [0; 4];
+            reader.read_exact(&mut kind)?;
+            if &kind != b"hdlr" {
+                range.0 += 4;
+            }
+        }
+    }
+
+    let mut pictures = Vec::new();
+    let mut position = range.0;
+    while let Some((start, end, kind)) = next_atom(reader, position, range.1)? {
+        position = end;
+        // A type indicator and locale of four bytes each precede the image
+        if &kind == b"data" && end - start > 8 && end - start - 8 <= MAX_TAG_LENGTH {
+            reader.seek(SeekFrom::Start(start + 8))?;
+            let mut data = vec![0; (end - start - 8) as usize];
+            reader.read_exact(&mut data)?;
+            pictures.push(Picture { kind: 0, data });
+        }
+    }
+    Ok(pictures)
+}
+
+/// Returns the body of the first child atom called `name` between `start` and `end`
+fn find_atom(
+    reader: &mut (impl Read + Seek),
+    (start, end): (u64, u64),
+    name: &[u8; 4],
+) -> std::io::Result<Option<(u64, u64)>> {
+    let mut position = start;
+    while let Some((body_start, body_end, kind)) = next_atom(reader, position, end)? {
+        if &kind == name {
+            return Ok(Some((body_start, body_end)));
+        }
+        position = body_end;
+    }
+    Ok(None)
+}
+
+/// Reads the atom header at `position`, returning its body range and type
+fn next_atom(
+    reader: &mut (impl Read + Seek),
+    position: u64,
+    end: u64,
+) -> std::io::Result<Option<(u64, u64, [u8; 4])>> {
+    if position + 8 > end {
+        return Ok(None);
+    }
+    reader.seek(SeekFrom::Start(position))?;
+    let mut header = [0; 8];
+    reader.read_exact(&mut header)?;
+    let kind = [header[4], header[5], header[6], header[7]];
+    let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    let (body_start, size) = match size {
+        // The atom runs to the end of its parent
+        0 => (position + 8, end - position),
+        // A 64-bit size follows the type
+        1 => {
+            let mut size = [0; 8];
+            reader.read_exact(&mut size)?;
+            (position + 16, u64::from_be_bytes(size))
+        }
+        size => (position + 8, size as u64),
+    };
+    let body_end = position.saturating_add(size);
+    if body_end < body_start || body_end > end {
+        return Ok(None);
+    }
+    Ok(Some((body_start, body_end, kind)))
+}
@@ -0,0 +1,187 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::path::Path;
+use zip::ZipArchive;
+
+use super::raster::{decode_limited, downsample, DEFAULT_MAX_PIXELS};
+use super::{ReadSeek, Thumbnailer};
+use crate::backend::BackendError;
+use crate::image::Image;
+
+/// Relationship type of the package thumbnail in Open Packaging Conventions files
+const OPC_THUMBNAIL: &str =
+    "http://schemas.openxmlformats.org/package/2006/relationships/metadata/thumbnail";
+/// Where Office puts the thumbnail when the relationships don't say
+const OOXML_THUMBNAILS: &[&str] = &["docProps/thumbnail.jpeg", "docProps/thumbnail.png"];
+const ODF_THUMBNAIL: &str = "Thumbnails/thumbnail.png";
+/// Members larger than this aren't read into memory
+const MAX_MEMBER_LENGTH: u64 = 64 * 1024 * 1024;
+
+const OOXML_MIME_TYPES: &[&str] = &[
+    "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+    "model/3mf",
+    "application/vnd.ms-package.3dmanufacturing-3dmodel+xml",
+];
+const ODF_MIME_TYPES: &[&str] = &[
+    "application/vnd.oasis.opendocument.text",
+    "application/vnd.oasis.opendocument.spreadsheet",
+    "application/vnd.oasis.opendocument.presentation",
+    "application/vnd.oasis.opendocument.graphics",
+];
+const EPUB_MIME_TYPE: &str = "application/epub+zip";
+
+/// Previews zip based documents with the image they carry: the package thumbnail of
+/// Office and 3MF files, the thumbnail of OpenDocument files and the cover of EPUB books
+pub struct DocumentThumbnailer;
+
+impl Thumbnailer for DocumentThumbnailer {
+    fn supports(&self, mime_type: &str) -> bool {
+        OOXML_MIME_TYPES.contains(&mime_type)
+            || ODF_MIME_TYPES.contains(&mime_type)
+            || mime_type == EPUB_MIME_TYPE
+    }
+
+    fn thumbnail(&self, path: &Path, size: u32) -> Result<Image, BackendError> {
+        self.thumbnail_reader(&mut BufReader::new(File::open(path)?), path, size)
+    }
+
+    fn thumbnail_reader(
+        &self,
+        reader: &mut dyn ReadSeek,
+        name: &Path,
+        size: u32,
+    ) -> Result<Image, BackendError> {
+        let mut archive = ZipArchive::new(reader)?;
+        // The container format is told by its content, as documents are often misnamed
+        let data = if archive.index_for_name(ODF_THUMBNAIL).is_some() {
+            read_member(&mut archive, ODF_THUMBNAIL)
+        } else if archive.index_for_name("META-INF/container.xml").is_some() {
+            epub_cover(&mut archive)
+        } else {
+            opc_thumbnail(&mut archive)
+        }
+        .ok_or_else(|| format!("{} has no embedded preview", name.display()))?;
+
+        let rgba = downsample(decode_limited(&data, DEFAULT_MAX_PIXELS)?, size).to_rgba8();
+        let (width, height) = rgba.dimensions();
+        Ok(Image::from_rgba(rgba.into_raw(), width, height)
+            .with_origin(name)
+            .with_thumbnail(true))
+    }
+}
+
+/// Finds the thumbnail of an OPC package (Office documents, 3MF models) through the
+/// relationships of the package, then in the places Office uses
+fn opc_thumbnail(archive: &mut ZipArchive<impl Read + Seek>) -> Option<Vec<u8>> {
+    let target = read_member(archive, "_rels/.rels").and_then(|rels| {
+        let rels = String::from_utf8(rels).ok()?;
+        let document = roxmltree::Document::parse(&rels).ok()?;
+        let relationship = document.descendants().find(|node| {
+            node.has_tag_name("Relationship") && node.attribute("Type") == Some(OPC_THUMBNAIL)
+        })?;
+        // Package relationships are relative to the root
+        Some(resolve_href("", relationship.attribute("Target")?))
+    });
+    target
+        .iter()
+        .map(String::as_str)
+        .chain(OOXML_THUMBNAILS.iter().copied())
+        .find_map(|name| read_member(archive, name))
+}
+
+/// Finds the cover of an EPUB book from its package document: the manifest item with the
+/// `cover-image` property in EPUB 3, or the one named by `<meta name="cover">` in EPUB 2
+fn epub_cover(archive: &mut ZipArchive<impl Read + Seek>) -> Option<Vec<u8>> {
+    let container = String::from_utf8(read_member(archive, "META-INF/container.xml")?).ok()?;
+    let container = roxmltree::Document::parse(&container).ok()?;
+    let package_path = container
+        .descendants()
+        .find(|node| node.has_tag_name("rootfile"))?
+        .attribute("full-path")?
+        .to_string();
+
+    let package = String::from_utf8(read_member(archive, &package_path)?).ok()?;
+    let package = roxmltree::Document::parse(&package).ok()?;
+    let items: Vec<_> = package
+        .descendants()
+        .filter(|node| node.has_tag_name("item"))
+        .collect();
+    let cover = items
+        .iter()
+        .find(|item| {
+            item.attribute("properties")
+                .is_some_and(|properties| properties.split_whitespace().any(|p| p == "cover-image"))
+        })
+        .or_else(|| {
+            let id = package
+                .descendants()
+                .find(|node| node.has_tag_name("meta") && node.attribute("name") == Some("cover"))?
+                .attribute("content")?;
+            items.iter().find(|item| item.attribute("id") == Some(id))
+        })?;
+
+    let directory = package_path
+        .rsplit_once('/')
+        .map_or("", |(directory, _)| directory);
+    read_member(archive, &resolve_href(directory, cover.attribute("href")?))
+}
+
+/// Resolves a relative `href` against `directory` inside the archive, decoding
+/// percent escapes and `..` segments. Leading slashes mean the archive root
+fn resolve_href(directory: &str, href: &str) -> String {
+    let href = percent_decode(href);
+    let mut segments: Vec<&str> = match href.starts_with('/') {
+        true => Vec::new(),
+        false => directory.split('/').filter(|s| !s.is_empty()).collect(),
+    };
+    for segment in href.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    segments.join("/")
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = bytes
+            .get(index + 1..index + 3)
+            .filter(|_| bytes[index] == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Reads a member of the archive, `None` if it is missing, unreadable or too large
+pub(crate) fn read_member(
+    archive: &mut ZipArchive<impl Read + Seek>,
+    name: &str,
+) -> Option<Vec<u8>> {
+    let member = archive.by_name(name).ok()?;
+    if member.size() > MAX_MEMBER_LENGTH {
+        return None;
+    }
+    let mut data = Vec::with_capacity(member.size() as usize);
+    // The declared size can lie, so reading stops at the limit regardless
+    member.take(MAX_MEMBER_LENGTH).read_to_end(&mut data).ok()?;
+    Some(data)
+}
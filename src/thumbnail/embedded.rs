@@ -0,0 +1,322 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use super::raster::{apply_orientation, decode_limited, downsample, DEFAULT_MAX_PIXELS};
+use super::{ReadSeek, Thumbnailer};
+use crate::backend::BackendError;
+use crate::image::Image;
+
+const TAG_NEW_SUBFILE_TYPE: u16 = 0x00fe;
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_PHOTOMETRIC: u16 = 0x0106;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_SUB_IFDS: u16 = 0x014a;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+const TAG_EXIF_IFD: u16 = 0x8769;
+
+/// JPEG and lossless JPEG compression
+const COMPRESSION_JPEG: [u32; 2] = [6, 7];
+/// Color filter array and linear raw data, which only look like JPEGs
+const PHOTOMETRIC_RAW: [u32; 2] = [32803, 34892];
+/// Stops malformed files with IFD loops or huge counts from keeping us busy
+const MAX_IFDS: usize = 64;
+const MAX_ENTRIES: u16 = 1024;
+
+/// Raw formats built on TIFF that carry JPEG previews
+const RAW_MIME_TYPES: &[&str] = &[
+    "image/x-adobe-dng",
+    "image/x-canon-cr2",
+    "image/x-nikon-nef",
+];
+
+/// A JPEG stored inside a photo or raw file
+#[derive(Debug, Clone, Copy)]
+pub struct Preview {
+    offset: u64,
+    length: u64,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The embedded previews of a file and how the camera was held
+#[derive(Debug)]
+pub struct Previews<R> {
+    pub previews: Vec<Preview>,
+    /// EXIF orientation of the main image, which the previews share
+    pub orientation: u32,
+    reader: R,
+}
+
+impl<R: Read + Seek> Previews<R> {
+    /// Finds the EXIF thumbnail of a JPEG, or the previews in a TIFF based raw file
+    pub fn read(mut reader: R) -> std::io::Result<Self> {
+        reader.seek(SeekFrom::Start(0))?;
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        let (mut previews, orientation) = match magic {
+            [0xff, 0xd8, ..] => match find_exif(&mut reader)? {
+                Some(base) => walk_tiff(&mut reader, base)?,
+                None => (Vec::new(), 1),
+            },
+            [b'I', b'I', 42, 0] | [b'M', b'M', 0, 42] => walk_tiff(&mut reader, 0)?,
+            _ => (Vec::new(), 1),
+        };
+        previews.sort_by_key(|preview| preview.width.max(preview.height));
+        Ok(Self {
+            previews,
+            orientation,
+            reader,
+        })
+    }
+
+    /// The smallest preview whose longest edge is at least `size`
+    pub fn big_enough(&self, size: u32) -> Option<Preview> {
+        self.previews
+            .iter()
+            .find(|preview| preview.width.max(preview.height) >= size)
+            .copied()
+    }
+
+    pub fn largest(&self) -> Option<Preview> {
+        self.previews.last().copied()
+    }
+
+    /// Decodes `preview`, shrinks it to `size` and turns it upright
+    pub fn decode(&mut self, preview: Preview, size: u32) -> Result<Image, BackendError> {
+        self.reader.seek(SeekFrom::Start(preview.offset))?;
+        let mut data = Vec::new();
+        (&mut self.reader)
+            .take(preview.length)
+            .read_to_end(&mut data)?;
+        let decoded = decode_limited(&data, DEFAULT_MAX_PIXELS)?;
+        let upright = apply_orientation(downsample(decoded, size), self.orientation);
+        let rgba = upright.to_rgba8();
+        let (width, height) = rgba.dimensions();
+        Ok(Image::from_rgba(rgba.into_raw(), width, height).with_thumbnail(true))
+    }
+}
+
+/// Previews DNG, CR2 and NEF raw photos from the JPEGs cameras embed in them.
+/// The raw sensor data itself is never decoded
+pub struct RawThumbnailer;
+
+impl Thumbnailer for RawThumbnailer {
+    fn supports(&self, mime_type: &str) -> bool {
+        RAW_MIME_TYPES.contains(&mime_type)
+    }
+
+    fn thumbnail(&self, path: &Path, size: u32) -> Result<Image, BackendError> {
+        self.thumbnail_reader(&mut BufReader::new(File::open(path)?), path, size)
+    }
+
+    fn thumbnail_reader(
+        &self,
+        reader: &mut dyn ReadSeek,
+        name: &Path,
+        size: u32,
+    ) -> Result<Image, BackendError> {
+        let mut previews = Previews::read(reader)?;
+        // A small preview still beats a generic icon when that's all there is
+        let preview = previews
+            .big_enough(size)
+            .or_else(|| previews.largest())
+            .ok_or_else(|| format!("{} has no embedded preview", name.display()))?;
+        Ok(previews.decode(preview, size)?.with_origin(name))
+    }
+}
+
+/// Returns the offset of the TIFF data in the JPEG's `Exif` APP1 segment
+fn find_exif(reader: &mut (impl Read + Seek)) -> std::io::Result<Option<u64>> {
+    let mut position = 2;
+    loop {
+        reader.seek(SeekFrom::Start(position))?;
+        let mut header = [0; 4];
+        reader.read_exact(&mut header)?;
+        let [0xff, marker, high, low] = header else {
+            return Ok(None);
+        };
+        // Image data starts at SOS, and EXIF always comes before it
+        if marker == 0xda || marker == 0xd9 {
+            return Ok(None);
+        }
+        let length = u16::from_be_bytes([high, low]) as u64;
+        if marker == 0xe1 {
+            let mut signature = [0; 6];
+            reader.read_exact(&mut signature)?;
+            if &signature == b"Exif\0\0" {
+                return Ok(Some(position + 10));
+            }
+        }
+        position += 2 + length;
+    }
+}
+
+/// Reads integers from TIFF data in its byte order
+struct TiffReader<'a, R> {
+    reader: &'a mut R,
+    base: u64,
+    little_endian: bool,
+}
+
+impl<R: Read + Seek> TiffReader<'_, R> {
+    fn bytes<const N: usize>(&mut self, offset: u64) -> std::io::Result<[u8; N]> {
+        self.reader.seek(SeekFrom::Start(self.base + offset))?;
+        let mut bytes = [0; N];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self, offset: u64) -> std::io::Result<u16> {
+        let bytes = self.bytes(offset)?;
+        Ok(match self.little_endian {
+            true => u16::from_le_bytes(bytes),
+            false => u16::from_be_bytes(bytes),
+        })
+    }
+
+    fn u32(&mut self, offset: u64) -> std::io::Result<u32> {
+        let bytes = self.bytes(offset)?;
+        Ok(match self.little_endian {
+            true => u32::from_le_bytes(bytes),
+            false => u32::from_be_bytes(bytes),
+        })
+    }
+
+    /// The first value of an IFD entry, for SHORT and LONG types
+    fn value(&mut self, entry: u64) -> std::io::Result<u32> {
+        match self.u16(entry + 2)? {
+            3 => Ok(self.u16(entry + 8)? as u32),
+            _ => self.u32(entry + 8),
+        }
+    }
+
+    /// All values of a LONG or IFD entry, used for the list of SubIFDs
+    fn values(&mut self, entry: u64) -> std::io::Result<Vec<u32>> {
+        let count = self.u32(entry + 4)?.min(MAX_IFDS as u32);
+        if count <= 1 {
+            return Ok(vec![self.value(entry)?]);
+        }
+        let offset = self.u32(entry + 8)? as u64;
+        (0..count as u64)
+            .map(|index| self.u32(offset + index * 4))
+            .collect()
+    }
+}
+
+/// Collects the JPEGs referenced from every IFD of the TIFF data at `base`, following
+/// the IFD chain, SubIFDs and the EXIF IFD. Also returns the orientation from IFD0
+fn walk_tiff(reader: &mut (impl Read + Seek), base: u64) -> std::io::Result<(Vec<Preview>, u32)> {
+    reader.seek(SeekFrom::Start(base))?;
+    let mut byte_order = [0; 2];
+    reader.read_exact(&mut byte_order)?;
+    let mut tiff = TiffReader {
+        reader,
+        base,
+        little_endian: &byte_order == b"II",
+    };
+
+    let mut previews = Vec::new();
+    let mut orientation = None;
+    let mut pending = vec![tiff.u32(4)?];
+    let mut visited = HashSet::new();
+    while let Some(ifd) = pending.pop() {
+        if ifd == 0 || visited.len() >= MAX_IFDS || !visited.insert(ifd) {
+            continue;
+        }
+        let ifd = ifd as u64;
+        let count = tiff.u16(ifd)?.min(MAX_ENTRIES);
+        let mut fields = Vec::new();
+        for index in 0..count as u64 {
+            let entry = ifd + 2 + index * 12;
+            let tag = tiff.u16(entry)?;
+            match tag {
+                TAG_SUB_IFDS => pending.extend(tiff.values(entry)?),
+                TAG_EXIF_IFD => pending.push(tiff.value(entry)?),
+                _ => fields.push((tag, tiff.value(entry)?)),
+            }
+        }
+        pending.push(tiff.u32(ifd + 2 + count as u64 * 12)?);
+
+        let field = |tag| {
+            fields
+                .iter()
+                .find(|(known, _)| *known == tag)
+                .map(|(_, value)| *value)
+        };
+        if orientation.is_none() {
+            orientation = Some(field(TAG_ORIENTATION).unwrap_or(1));
+        }
+        let jpeg = match (field(TAG_JPEG_OFFSET), field(TAG_JPEG_LENGTH)) {
+            (Some(offset), Some(length)) => Some((offset, length)),
+            _ => {
+                let compression = field(TAG_COMPRESSION).unwrap_or(1);
+                let photometric = field(TAG_PHOTOMETRIC).unwrap_or(0);
+                // Lossless JPEG is also used for raw data, so only trust it in reduced images
+                let is_preview = compression == 6 || field(TAG_NEW_SUBFILE_TYPE) == Some(1);
+                match (field(TAG_STRIP_OFFSETS), field(TAG_STRIP_BYTE_COUNTS)) {
+                    (Some(offset), Some(length))
+                        if COMPRESSION_JPEG.contains(&compression)
+                            && !PHOTOMETRIC_RAW.contains(&photometric)
+                            && is_preview =>
+                    {
+                        Some((offset, length))
+                    }
+                    _ => None,
+                }
+            }
+        };
+        if let Some((offset, length)) = jpeg {
+            let offset = base + offset as u64;
+            if let Ok(Some((width, height))) = jpeg_size(tiff.reader, offset, length as u64) {
+                previews.push(Preview {
+                    offset,
+                    length: length as u64,
+                    width,
+                    height,
+                });
+            }
+        }
+    }
+    Ok((previews, orientation.unwrap_or(1)))
+}
+
+/// Reads the size from a JPEG's frame header without decoding it
+fn jpeg_size(
+    reader: &mut (impl Read + Seek),
+    offset: u64,
+    length: u64,
+) -> std::io::Result<Option<(u32, u32)>> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut soi = [0; 2];
+    reader.read_exact(&mut soi)?;
+    if soi != [0xff, 0xd8] {
+        return Ok(None);
+    }
+    let mut position = offset + 2;
+    while position < offset + length {
+        reader.seek(SeekFrom::Start(position))?;
+        let mut header = [0; 4];
+        reader.read_exact(&mut header)?;
+        let [0xff, marker, high, low] = header else {
+            return Ok(None);
+        };
+        // SOF0 to SOF15, except DHT, JPG and DAC which share the range
+        if (0xc0..=0xcf).contains(&marker) && ![0xc4, 0xc8, 0xcc].contains(&marker) {
+            let mut frame = [0; 5];
+            reader.read_exact(&mut frame)?;
+            let height = u16::from_be_bytes([frame[1], frame[2]]) as u32;
+            let width = u16::from_be_bytes([frame[3], frame[4]]) as u32;
+            return Ok(Some((width, height)));
+        }
+        if marker == 0xda || marker == 0xd9 {
+            return Ok(None);
+        }
+        position += 2 + u16::from_be_bytes([high, low]) as u64;
+    }
+    Ok(None)
+}
@@ -0,0 +1,269 @@
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use super::ico::decode_best;
+use super::{ReadSeek, Thumbnailer};
+use crate::backend::BackendError;
+use crate::image::Image;
+
+const RT_ICON: u32 = 3;
+const RT_GROUP_ICON: u32 = 14;
+const PE32_MAGIC: u16 = 0x10b;
+const PE32_PLUS_MAGIC: u16 = 0x20b;
+const RESOURCE_DIRECTORY: usize = 2;
+const SECTION_HEADER_LENGTH: usize = 40;
+/// Stops malformed files with huge tables from keeping us busy
+const MAX_SECTIONS: u16 = 96;
+const MAX_DIRECTORY_ENTRIES: usize = 4096;
+const MAX_GROUP_IMAGES: usize = 64;
+/// Icon images larger than this aren't read
+const MAX_IMAGE_LENGTH: u32 = 16 * 1024 * 1024;
+
+const MIME_TYPES: &[&str] = &[
+    "application/vnd.microsoft.portable-executable",
+    "application/x-ms-dos-executable",
+    "application/x-msdownload",
+    "application/x-dosexec",
+];
+
+/// Shows Windows programs and libraries with the first icon in their resources,
+/// as Explorer does
+pub struct ExecutableThumbnailer;
+
+impl Thumbnailer for ExecutableThumbnailer {
+    fn supports(&self, mime_type: &str) -> bool {
+        MIME_TYPES.contains(&mime_type)
+    }
+
+    fn thumbnail(&self, path: &Path, size: u32) -> Result<Image, BackendError> {
+        self.thumbnail_reader(&mut BufReader::new(File::open(path)?), path, size)
+    }
+
+    fn thumbnail_reader(
+        &self,
+        reader: &mut dyn ReadSeek,
+        name: &Path,
+        size: u32,
+    ) -> Result<Image, BackendError> {
+        let icon = read_icon(reader, 0)
+            .map_err(|error| format!("{} has no usable icon: {}", name.display(), error))?;
+        Ok(decode_best(&icon, size)?
+            .with_origin(name)
+            .with_thumbnail(true))
+    }
+}
+
+/// Reads an icon group of a PE file as an ICO file. Like the `file,index` references of
+/// Windows, a positive `index` counts groups from 0 and a negative one is a resource ID
+pub(crate) fn read_icon(reader: impl Read + Seek, index: i32) -> Result<Vec<u8>, BackendError> {
+    let mut resources = Resources::open(reader)?;
+    let groups = resources.of_kind(RT_GROUP_ICON)?;
+    let group = match index {
+        0.. => groups.get(index as usize),
+        _ => groups
+            .iter()
+            .find(|(id, _)| *id == Some(index.unsigned_abs())),
+    }
+    .ok_or("No such icon")?;
+    let group = resources.first_data(group.1)?;
+    if group.len() < 6 || group[2..4] != [1, 0] {
+        return Err("Malformed icon group".into());
+    }
+
+    // The group lists the images like an ICO directory, with resource IDs for offsets
+    let count = (u16::from_le_bytes([group[4], group[5]]) as usize).min(MAX_GROUP_IMAGES);
+    let images = resources.of_kind(RT_ICON)?;
+    let mut directory = Vec::new();
+    let mut data = Vec::new();
+    let mut read = Vec::new();
+    // Images can be listed many times, and all of them together are read up to the
+    // limit of one
+    let mut budget = MAX_IMAGE_LENGTH;
+    for entry in group[6..].chunks_exact(14).take(count) {
+        let id = u16::from_le_bytes([entry[12], entry[13]]) as u32;
+        if read.contains(&id) {
+            continue;
+        }
+        let Some((_, offset)) = images.iter().find(|(image, _)| *image == Some(id)) else {
+            continue;
+        };
+        let (offset, length) = resources.locate_data(*offset)?;
+        if length > budget {
+            continue;
+        }
+        budget -= length;
+        read.push(id);
+        let image = resources.read_absolute(offset, length as usize)?;
+        directory.push((entry[..8].to_vec(), length));
+        data.push(image);
+    }
+    if directory.is_empty() {
+        return Err("The icon group has no images".into());
+    }
+
+    let mut icon = vec![0, 0, 1, 0];
+    icon.extend((directory.len() as u16).to_le_bytes());
+    let mut offset = 6 + 16 * directory.len() as u32;
+    for (header, length) in &directory {
+        icon.extend(header);
+        icon.extend(length.to_le_bytes());
+        icon.extend(offset.to_le_bytes());
+        offset += length;
+    }
+    data.iter().for_each(|image| icon.extend(image));
+    Ok(icon)
+}
+
+/// Where a section of a PE file is loaded and where it is stored
+struct Section {
+    address: u32,
+    /// The larger of its size in memory and in the file
+    length: u32,
+    file_offset: u32,
+}
+
+/// The resource tree of a PE file, read as it is walked
+struct Resources<R> {
+    reader: R,
+    sections: Vec<Section>,
+    /// File offset of the root of the tree
+    root: u64,
+}
+
+impl<R: Read + Seek> Resources<R> {
+    fn open(mut reader: R) -> Result<Self, BackendError> {
+        let mut dos = [0; 64];
+        reader.read_exact(&mut dos)?;
+        if !dos.starts_with(b"MZ") {
+            return Err("Not an executable".into());
+        }
+        let pe_offset = u32_at(&dos, 0x3c) as u64;
+        reader.seek(SeekFrom::Start(pe_offset))?;
+        let mut headers = [0; 24];
+        reader.read_exact(&mut headers)?;
+        if !headers.starts_with(b"PE\0\0") {
+            return Err("Not a PE executable".into());
+        }
+        let section_count = u16_at(&headers, 6).min(MAX_SECTIONS) as usize;
+        let optional_length = u16_at(&headers, 20) as usize;
+        let mut optional = vec![0; optional_length];
+        reader.read_exact(&mut optional)?;
+        let directories = match (optional.len() >= 2).then(|| u16_at(&optional, 0)) {
+            Some(PE32_MAGIC) => 96,
+            Some(PE32_PLUS_MAGIC) => 112,
+            _ => return Err("Unknown optional header".into()),
+        };
+        let directory = directories + RESOURCE_DIRECTORY * 8;
+        let resource_address = optional
+            .get(directory..directory + 4)
+            .map(|bytes| u32_at(bytes, 0))
+            .filter(|address| *address != 0)
+            .ok_or("The executable has no resources")?;
+
+        let mut table = vec![0; section_count * SECTION_HEADER_LENGTH];
+        reader.read_exact(&mut table)?;
+        let sections = table
+            .chunks_exact(SECTION_HEADER_LENGTH)
+            .map(|section| Section {
+                address: u32_at(section, 12),
+                length: u32_at(section, 8).max(u32_at(section, 16)),
+                file_offset: u32_at(section, 20),
+            })
+            .collect();
+        let mut resources = Self {
+            reader,
+            sections,
+            root: 0,
+        };
+        resources.root = resources.offset(resource_address)?;
+        Ok(resources)
+    }
+
+    /// The file offset of the virtual address `address`
+    fn offset(&self, address: u32) -> Result<u64, BackendError> {
+        self.sections
+            .iter()
+            .find(|section| {
+                let end = section.address.saturating_add(section.length);
+                (section.address..end).contains(&address)
+            })
+            .map(|section| (address - section.address) as u64 + section.file_offset as u64)
+            .ok_or_else(|| "Resource outside of the sections".into())
+    }
+
+    /// The resources of type `kind`: the ID of each, `None` for named ones, and where its
+    /// entry points in the tree
+    fn of_kind(&mut self, kind: u32) -> Result<Vec<(Option<u32>, u32)>, BackendError> {
+        let (_, offset) = self
+            .directory(0)?
+            .into_iter()
+            .find(|(id, _)| *id == Some(kind))
+            .ok_or("No such resource type")?;
+        self.directory(offset)
+    }
+
+    /// The entries of the directory at `offset` in the tree, high bit set or not
+    fn directory(&mut self, offset: u32) -> Result<Vec<(Option<u32>, u32)>, BackendError> {
+        let offset = offset & 0x7fff_ffff;
+        let header = self.read(offset as u64, 16)?;
+        let count = (u16_at(&header, 12) as usize + u16_at(&header, 14) as usize)
+            .min(MAX_DIRECTORY_ENTRIES);
+        let table = self.read(offset as u64 + 16, count * 8)?;
+        Ok(table
+            .chunks_exact(8)
+            .map(|entry| {
+                let (name, target) = (u32_at(entry, 0), u32_at(entry, 4));
+                let id = (name & 0x8000_0000 == 0).then_some(name);
+                (id, target)
+            })
+            .collect())
+    }
+
+    /// The data of the first language of the resource whose entry points to `target`
+    fn first_data(&mut self, target: u32) -> Result<Vec<u8>, BackendError> {
+        let (offset, length) = self.locate_data(target)?;
+        if length > MAX_IMAGE_LENGTH {
+            return Err("Resource too large".into());
+        }
+        self.read_absolute(offset, length as usize)
+    }
+
+    /// Where the data `first_data` reads is stored, and its length
+    fn locate_data(&mut self, target: u32) -> Result<(u64, u32), BackendError> {
+        let mut target = target;
+        // Subdirectories have the high bit set: the name level, then the language level
+        for _ in 0..2 {
+            if target & 0x8000_0000 == 0 {
+                break;
+            }
+            let entries = self.directory(target)?;
+            target = entries.first().ok_or("Empty resource directory")?.1;
+        }
+        if target & 0x8000_0000 != 0 {
+            return Err("Malformed resource tree".into());
+        }
+        let entry = self.read(target as u64, 8)?;
+        let (address, length) = (u32_at(&entry, 0), u32_at(&entry, 4));
+        Ok((self.offset(address)?, length))
+    }
+
+    fn read(&mut self, offset: u64, length: usize) -> Result<Vec<u8>, BackendError> {
+        self.read_absolute(self.root + offset, length)
+    }
+
+    fn read_absolute(&mut self, offset: u64, length: usize) -> Result<Vec<u8>, BackendError> {
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let mut data = vec![0; length];
+        self.reader.read_exact(&mut data)?;
+        Ok(data)
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
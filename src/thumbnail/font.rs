@@ -0,0 +1,164 @@
+use ab_glyph::{point, Font, FontVec, Glyph, PxScale, Rect, ScaleFont};
+use image::{Rgba, RgbaImage};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use super::woff;
+use super::{ReadSeek, Thumbnailer};
+use crate::backend::BackendError;
+use crate::image::Image;
+
+/// Font files larger than this aren't read
+const MAX_FONT_LENGTH: u64 = 64 * 1024 * 1024;
+/// What file managers show for Latin fonts
+const SAMPLE: [char; 2] = ['A', 'a'];
+const PAGE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const PAGE_BORDER: Rgba<u8> = Rgba([200, 200, 200, 255]);
+const INK: [u8; 3] = [32, 32, 32];
+
+const MIME_TYPES: &[&str] = &[
+    "font/ttf",
+    "font/otf",
+    "font/woff",
+    "font/woff2",
+    "font/collection",
+    "font/sfnt",
+    "application/x-font-ttf",
+    "application/x-font-otf",
+    "application/vnd.ms-opentype",
+];
+
+/// Previews TrueType, OpenType, WOFF and WOFF2 fonts with a sample set in the font
+/// itself, on a page like the ones file managers show
+pub struct FontThumbnailer;
+
+impl Thumbnailer for FontThumbnailer {
+    fn supports(&self, mime_type: &str) -> bool {
+        MIME_TYPES.contains(&mime_type)
+    }
+
+    fn thumbnail(&self, path: &Path, size: u32) -> Result<Image, BackendError> {
+        self.thumbnail_reader(&mut BufReader::new(File::open(path)?), path, size)
+    }
+
+    fn thumbnail_reader(
+        &self,
+        reader: &mut dyn ReadSeek,
+        name: &Path,
+        size: u32,
+    ) -> Result<Image, BackendError> {
+        let mut data = Vec::new();
+        reader.take(MAX_FONT_LENGTH + 1).read_to_end(&mut data)?;
+        if data.len() as u64 > MAX_FONT_LENGTH {
+            return Err(format!("{} is too large for a font", name.display()).into());
+        }
+        let font = FontVec::try_from_vec(woff::to_sfnt(data)?)?;
+        let image = render_sample(&font, size)
+            .ok_or_else(|| format!("{} has no outlines to preview", name.display()))?;
+        Ok(image.with_origin(name).with_thumbnail(true))
+    }
+}
+
+/// Draws the sample on a page of `size` x `size`. `None` if the font has no outlines
+/// for it, as with bitmap-only fonts
+fn render_sample(font: &FontVec, size: u32) -> Option<Image> {
+    let size = size.max(8);
+    let margin = size / 16;
+    let mut page = RgbaImage::new(size, size);
+    for (x, y, pixel) in page.enumerate_pixels_mut() {
+        let inside = |value: u32| (margin..size - margin).contains(&value);
+        if inside(x) && inside(y) {
+            let edge =
+                x == margin || y == margin || x == size - margin - 1 || y == size - margin - 1;
+            *pixel = if edge { PAGE_BORDER } else { PAGE };
+        }
+    }
+
+    let characters = sample_characters(font);
+    // Laid out once at a reference size to measure, then again to fill the text area
+    let text_area = (size - margin * 2) as f32 * 0.7;
+    let reference = PxScale::from(100.0);
+    let bounds = glyph_bounds(font, &layout(font, &characters, reference))?;
+    let factor = (text_area / bounds.width().max(1.0)).min(text_area / bounds.height().max(1.0));
+    let scale = PxScale::from(100.0 * factor);
+    let mut glyphs = layout(font, &characters, scale);
+    let bounds = glyph_bounds(font, &glyphs)?;
+
+    let center = size as f32 / 2.0;
+    let offset = point(
+        center - (bounds.min.x + bounds.max.x) / 2.0,
+        center - (bounds.min.y + bounds.max.y) / 2.0,
+    );
+    for glyph in &mut glyphs {
+        glyph.position += offset;
+    }
+    for glyph in glyphs {
+        let Some(outline) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outline.px_bounds();
+        outline.draw(|x, y, coverage| {
+            let (x, y) = (
+                bounds.min.x as i32 + x as i32,
+                bounds.min.y as i32 + y as i32,
+            );
+            if x < 0 || y < 0 || x >= size as i32 || y >= size as i32 {
+                return;
+            }
+            let pixel = page.get_pixel_mut(x as u32, y as u32);
+            let coverage = coverage.clamp(0.0, 1.0);
+            for (channel, ink) in pixel.0.iter_mut().zip(INK) {
+                *channel = (*channel as f32 * (1.0 - coverage) + ink as f32 * coverage) as u8;
+            }
+            pixel.0[3] = pixel.0[3].max((coverage * 255.0) as u8);
+        });
+    }
+    Some(Image::from_rgba(page.into_raw(), size, size))
+}
+
+/// "Aa", or the first two characters the font has for fonts without Latin letters
+fn sample_characters(font: &FontVec) -> Vec<char> {
+    if SAMPLE.iter().all(|&c| font.glyph_id(c).0 != 0) {
+        return SAMPLE.to_vec();
+    }
+    let mut characters: Vec<char> = font
+        .codepoint_ids()
+        .map(|(_, c)| c)
+        .filter(|c| !c.is_control() && !c.is_whitespace())
+        .collect();
+    characters.sort_unstable();
+    characters.dedup();
+    characters.truncate(SAMPLE.len());
+    characters
+}
+
+/// Places `characters` on a baseline at the font's ascent, with kerning
+fn layout(font: &FontVec, characters: &[char], scale: PxScale) -> Vec<Glyph> {
+    let scaled = font.as_scaled(scale);
+    let mut x = 0.0;
+    let mut previous = None;
+    let mut glyphs = Vec::new();
+    for &character in characters {
+        let id = scaled.glyph_id(character);
+        if let Some(previous) = previous {
+            x += scaled.kern(previous, id);
+        }
+        glyphs.push(id.with_scale_and_position(scale, point(x, scaled.ascent())));
+        x += scaled.h_advance(id);
+        previous = Some(id);
+    }
+    glyphs
+}
+
+/// The area the inked pixels of `glyphs` cover
+fn glyph_bounds(font: &FontVec, glyphs: &[Glyph]) -> Option<Rect> {
+    glyphs
+        .iter()
+        .filter_map(|glyph| font.outline_glyph(glyph.clone()))
+        .map(|outline| outline.px_bounds())
+        .reduce(|a, b| Rect {
+            min: point(a.min.x.min(b.min.x), a.min.y.min(b.min.y)),
+            max: point(a.max.x.max(b.max.x), a.max.y.max(b.max.y)),
+        })
+}
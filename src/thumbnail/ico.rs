@@ -0,0 +1,119 @@
+use std::cmp::Reverse;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use super::raster::{decode_limited, downsample, DEFAULT_MAX_PIXELS};
+use super::{ReadSeek, Thumbnailer};
+use crate::backend::BackendError;
+use crate::image::Image;
+
+/// Icon files larger than this aren't read
+const MAX_ICON_LENGTH: u64 = 16 * 1024 * 1024;
+const HEADER_LENGTH: usize = 6;
+const ENTRY_LENGTH: usize = 16;
+const PNG_MAGIC: &[u8] = b"\x89PNG";
+
+const MIME_TYPES: &[&str] = &["image/vnd.microsoft.icon", "image/x-icon", "image/ico"];
+
+/// Previews Windows icon files with the image among theirs that best fits the size,
+/// rather than the largest one
+pub struct IcoThumbnailer;
+
+impl Thumbnailer for IcoThumbnailer {
+    fn supports(&self, mime_type: &str) -> bool {
+        MIME_TYPES.contains(&mime_type)
+    }
+
+    fn thumbnail(&self, path: &Path, size: u32) -> Result<Image, BackendError> {
+        self.thumbnail_reader(&mut BufReader::new(File::open(path)?), path, size)
+    }
+
+    fn thumbnail_reader(
+        &self,
+        reader: &mut dyn ReadSeek,
+        name: &Path,
+        size: u32,
+    ) -> Result<Image, BackendError> {
+        let mut data = Vec::new();
+        reader.take(MAX_ICON_LENGTH + 1).read_to_end(&mut data)?;
+        if data.len() as u64 > MAX_ICON_LENGTH {
+            return Err(format!("{} is too large for an icon", name.display()).into());
+        }
+        Ok(decode_best(&data, size)?
+            .with_origin(name)
+            .with_thumbnail(true))
+    }
+}
+
+/// One image of an icon file
+struct Entry<'a> {
+    /// 0 stands for 256 in the directory, and is stored as 256 here
+    width: u32,
+    height: u32,
+    bit_count: u16,
+    header: &'a [u8],
+    data: &'a [u8],
+}
+
+/// Decodes the image of the ICO file `data` that best fits `size`: the smallest at least
+/// that large, or else the largest, with the most colors among those of the same size
+pub(crate) fn decode_best(data: &[u8], size: u32) -> Result<Image, BackendError> {
+    let mut entries = entries(data).ok_or("Malformed icon file")?;
+    let longest = |entry: &Entry| entry.width.max(entry.height);
+    entries.sort_by_key(|entry| (longest(entry), Reverse(entry.bit_count)));
+    let largest = entries
+        .last()
+        .map(longest)
+        .ok_or("The icon file has no images")?;
+    let wanted = size.min(largest);
+    let entry = entries
+        .iter()
+        .find(|entry| longest(entry) >= wanted)
+        .ok_or("The icon file has no images")?;
+
+    let decoded = if entry.data.starts_with(PNG_MAGIC) {
+        decode_limited(entry.data, DEFAULT_MAX_PIXELS)?
+    } else {
+        // Bitmap images lack a file header, so they are decoded as an icon file of one
+        let mut single = [0, 0, 1, 0, 1, 0].to_vec();
+        single.extend(&entry.header[..12]);
+        single.extend(((HEADER_LENGTH + ENTRY_LENGTH) as u32).to_le_bytes());
+        single.extend(entry.data);
+        decode_limited(&single, DEFAULT_MAX_PIXELS)?
+    };
+    let rgba = downsample(decoded, size).to_rgba8();
+    let (width, height) = rgba.dimensions();
+    Ok(Image::from_rgba(rgba.into_raw(), width, height))
+}
+
+/// The images listed in the directory of an icon file. `None` if it isn't one
+fn entries(data: &[u8]) -> Option<Vec<Entry<'_>>> {
+    let header = data.get(..HEADER_LENGTH)?;
+    if header[..4] != [0, 0, 1, 0] {
+        return None;
+    }
+    let count = u16::from_le_bytes([header[4], header[5]]) as usize;
+    let mut entries = Vec::with_capacity(count);
+    for index in 0..count {
+        let header = data
+            .get(HEADER_LENGTH + index * ENTRY_LENGTH..)?
+            .get(..ENTRY_LENGTH)?;
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap()) as usize
+        };
+        let (length, offset) = (u32_at(8), u32_at(12));
+        let Some(image) = data.get(offset..).and_then(|rest| rest.get(..length)) else {
+            continue;
+        };
+        let dimension = |byte: u8| if byte == 0 { 256 } else { byte as u32 };
+        entries.push(Entry {
+            width: dimension(header[0]),
+            height: dimension(header[1]),
+            bit_count: u16::from_le_bytes([header[6], header[7]]),
+            header,
+            data: image,
+        });
+    }
+    Some(entries)
+}
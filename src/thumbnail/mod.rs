@@ -0,0 +1,78 @@
+use std::io::{Read, Seek};
+use std::path::Path;
+
+use crate::backend::BackendError;
+use crate::image::Image;
+use raster::{decode_limited, downsample, DEFAULT_MAX_PIXELS};
+
+pub mod apk;
+pub mod appimage;
+pub mod cover_art;
+pub mod document;
+pub mod embedded;
+pub mod executable;
+pub mod font;
+pub mod ico;
+pub mod package;
+pub mod raster;
+pub mod svg;
+mod woff;
+
+/// Content a thumbnailer can read without a file of its own, like an archive member
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// Makes previews of file content, for platforms whose shell doesn't
+pub trait Thumbnailer: Send + Sync {
+    /// Whether files of `mime_type` can be previewed
+    fn supports(&self, mime_type: &str) -> bool;
+
+    /// Returns a preview of `path` whose longest edge is at most `size` pixels.
+    /// The aspect ratio of the content is kept
+    fn thumbnail(&self, path: &Path, size: u32) -> Result<Image, BackendError>;
+
+    /// Like `thumbnail`, for content read from `reader`, which starts at position 0.
+    /// `name` stands for the content in errors and as the origin of the preview.
+    /// Thumbnailers that need a file on disk refuse it
+    fn thumbnail_reader(
+        &self,
+        _reader: &mut dyn ReadSeek,
+        name: &Path,
+        _size: u32,
+    ) -> Result<Image, BackendError> {
+        Err(format!("Cannot preview {} without a file on disk", name.display()).into())
+    }
+}
+
+/// The thumbnailers used unless others are configured
+pub fn default_thumbnailers() -> Vec<Box<dyn Thumbnailer>> {
+    vec![
+        Box::new(raster::RasterThumbnailer::new()),
+        Box::new(embedded::RawThumbnailer),
+        Box::new(cover_art::CoverArtThumbnailer),
+        Box::new(document::DocumentThumbnailer),
+        Box::new(font::FontThumbnailer),
+        Box::new(svg::SvgThumbnailer::new()),
+        Box::new(apk::ApkThumbnailer),
+        Box::new(appimage::AppImageThumbnailer),
+        Box::new(package::PackageThumbnailer),
+        Box::new(ico::IcoThumbnailer),
+        Box::new(executable::ExecutableThumbnailer),
+    ]
+}
+
+/// Decodes an icon stored inside a file, fitting it in `size`: SVG within the limits
+/// of `SvgThumbnailer`, anything else as a raster image. `path` names the file in errors
+pub(crate) fn decode_embedded_icon(
+    data: Vec<u8>,
+    size: u32,
+    path: &Path,
+) -> Result<Image, BackendError> {
+    if data.trim_ascii_start().starts_with(b"<") {
+        return svg::SvgThumbnailer::new().render_document(String::from_utf8(data)?, size, path);
+    }
+    let rgba = downsample(decode_limited(&data, DEFAULT_MAX_PIXELS)?, size).to_rgba8();
+    let (width, height) = rgba.dimensions();
+    Ok(Image::from_rgba(rgba.into_raw(), width, height))
+}
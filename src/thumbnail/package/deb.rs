@@ -0,0 +1,65 @@
+//! Debian packages: an `ar` archive whose `data.tar` member, usually compressed, holds
+//! the installed files
+
+use std::io::{self, Read};
+
+use super::{member_path, Payload};
+use crate::archive::decompressed;
+use crate::backend::BackendError;
+
+const MAGIC: &[u8] = b"!<arch>\n";
+const HEADER_LENGTH: usize = 60;
+
+pub(super) fn is_package(magic: &[u8]) -> bool {
+    magic.starts_with(MAGIC)
+}
+
+/// Streams the files of the package through `payload`
+pub(super) fn read_payload(
+    mut reader: impl Read,
+    payload: &mut Payload,
+) -> Result<(), BackendError> {
+    let mut magic = [0; MAGIC.len()];
+    reader.read_exact(&mut magic)?;
+    loop {
+        let mut header = [0; HEADER_LENGTH];
+        if reader.read_exact(&mut header).is_err() {
+            return Err("The package has no data archive".into());
+        }
+        // GNU ar ends names with a slash
+        let name = String::from_utf8_lossy(&header[..16]);
+        let name = name.trim_end().trim_end_matches('/');
+        let length: u64 = std::str::from_utf8(&header[48..58])?.trim().parse()?;
+        if name.starts_with("data.tar") {
+            return read_tar(decompressed(reader.take(length))?, payload);
+        }
+        // Members are padded to an even length
+        io::copy(
+            &mut (&mut reader).take(length + length % 2),
+            &mut io::sink(),
+        )?;
+    }
+}
+
+fn read_tar(reader: impl Read, payload: &mut Payload) -> Result<(), BackendError> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let path = member_path(&name);
+        match entry.header().entry_type() {
+            tar::EntryType::Symlink => {
+                if let Some(target) = entry.link_name()? {
+                    payload.add_link(path, &target.to_string_lossy());
+                }
+            }
+            tar::EntryType::Regular if payload.wants(path, entry.size()) => {
+                let mut data = Vec::with_capacity(entry.size() as usize);
+                entry.read_to_end(&mut data)?;
+                payload.add_file(path, data);
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
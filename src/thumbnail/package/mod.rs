@@ -0,0 +1,204 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use super::{decode_embedded_icon, ReadSeek, Thumbnailer};
+use crate::backend::BackendError;
+use crate::freedesktop::icon_theme::best_unindexed;
+use crate::freedesktop::keyfile::KeyFile;
+use crate::image::Image;
+
+mod deb;
+mod rpm;
+
+const DEB_MIME_TYPES: &[&str] = &[
+    "application/vnd.debian.binary-package",
+    "application/x-deb",
+    "application/x-debian-package",
+];
+const RPM_MIME_TYPES: &[&str] = &["application/x-rpm", "application/x-redhat-package-manager"];
+
+const APPLICATIONS_DIR: &str = "usr/share/applications/";
+const ICONS_DIR: &str = "usr/share/icons/";
+const PIXMAPS_DIR: &str = "usr/share/pixmaps/";
+const FALLBACK_THEME: &str = "hicolor";
+const ICON_EXTENSIONS: [&str; 2] = ["png", "svg"];
+/// Files larger than this aren't read
+const MAX_FILE_LENGTH: u64 = 16 * 1024 * 1024;
+/// Files read from one payload stop being kept past this total
+const MAX_COLLECTED_LENGTH: u64 = 64 * 1024 * 1024;
+/// Symbolic links followed to reach an icon
+const MAX_SYMLINKS: usize = 8;
+
+/// Shows Debian and RPM packages with the icon of the application they install: the
+/// `Icon` of their desktop file, found among the icons in their payload
+pub struct PackageThumbnailer;
+
+impl Thumbnailer for PackageThumbnailer {
+    fn supports(&self, mime_type: &str) -> bool {
+        DEB_MIME_TYPES.contains(&mime_type) || RPM_MIME_TYPES.contains(&mime_type)
+    }
+
+    fn thumbnail(&self, path: &Path, size: u32) -> Result<Image, BackendError> {
+        self.thumbnail_reader(&mut BufReader::new(File::open(path)?), path, size)
+    }
+
+    fn thumbnail_reader(
+        &self,
+        reader: &mut dyn ReadSeek,
+        name: &Path,
+        size: u32,
+    ) -> Result<Image, BackendError> {
+        let mut file = BufReader::new(reader);
+        let magic = file.fill_buf()?;
+        let (is_deb, is_rpm) = (deb::is_package(magic), rpm::is_package(magic));
+        let mut payload = Payload::default();
+        if is_deb {
+            deb::read_payload(file, &mut payload)?;
+        } else if is_rpm {
+            rpm::read_payload(file, &mut payload)?;
+        } else {
+            return Err(format!("{} is not a Debian or RPM package", name.display()).into());
+        }
+
+        let icon = payload
+            .icon(size)
+            .ok_or_else(|| format!("{} has no application icon", name.display()))?;
+        Ok(decode_embedded_icon(icon, size, name)?
+            .with_origin(name)
+            .with_thumbnail(true))
+    }
+}
+
+/// The desktop files, icons and links of a payload, kept as it streams past
+#[derive(Default)]
+struct Payload {
+    files: HashMap<String, Vec<u8>>,
+    links: HashMap<String, String>,
+    /// The `Icon` of the first desktop file that has one. Once known, other icons
+    /// aren't kept
+    icon: Option<String>,
+    collected: u64,
+}
+
+impl Payload {
+    /// Whether the file at `path`, `length` bytes long, may be needed
+    fn wants(&self, path: &str, length: u64) -> bool {
+        if length > MAX_FILE_LENGTH || self.collected + length > MAX_COLLECTED_LENGTH {
+            return false;
+        }
+        if path.starts_with(APPLICATIONS_DIR) {
+            return self.icon.is_none() && path.ends_with(".desktop");
+        }
+        is_icon_path(path) && self.icon.as_deref().is_none_or(|icon| is_icon(path, icon))
+    }
+
+    fn add_file(&mut self, path: &str, data: Vec<u8>) {
+        if path.starts_with(APPLICATIONS_DIR) {
+            let entry = KeyFile::parse(&String::from_utf8_lossy(&data));
+            self.icon = entry.get("Desktop Entry", "Icon").map(str::to_string);
+            return;
+        }
+        self.collected += data.len() as u64;
+        self.files.insert(path.to_string(), data);
+    }
+
+    fn add_link(&mut self, path: &str, target: &str) {
+        if is_icon_path(path) {
+            let directory = path.rsplit_once('/').map_or("", |(directory, _)| directory);
+            self.links
+                .insert(path.to_string(), resolve_link(directory, target));
+        }
+    }
+
+    /// The icon of the application at `size`: the file the desktop file names, or the
+    /// best size of it in a theme, `hicolor` first, or a pixmap
+    fn icon(&self, size: u32) -> Option<Vec<u8>> {
+        let icon = self.icon.as_deref()?;
+        if icon.contains('/') {
+            return self.read(member_path(icon));
+        }
+
+        let mut themes: BTreeMap<(bool, &str), Vec<&str>> = BTreeMap::new();
+        for path in self.files.keys().chain(self.links.keys()) {
+            let Some((theme, path_in_theme)) = path
+                .strip_prefix(ICONS_DIR)
+                .and_then(|path| path.split_once('/'))
+            else {
+                continue;
+            };
+            if is_icon(path, icon) {
+                let key = (theme != FALLBACK_THEME, theme);
+                themes.entry(key).or_default().push(path_in_theme);
+            }
+        }
+        themes
+            .into_iter()
+            .find_map(|((_, theme), mut paths)| {
+                // Sorted so PNG files come before SVG ones of the same size
+                paths.sort_unstable();
+                let path = best_unindexed(&paths, size, 1)?;
+                self.read(&format!("{ICONS_DIR}{theme}/{path}"))
+            })
+            .or_else(|| {
+                ICON_EXTENSIONS
+                    .iter()
+                    .find_map(|extension| self.read(&format!("{PIXMAPS_DIR}{icon}.{extension}")))
+            })
+    }
+
+    /// Reads a kept file, following symbolic links
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        let mut path = path;
+        for _ in 0..=MAX_SYMLINKS {
+            if let Some(data) = self.files.get(path) {
+                return Some(data.clone());
+            }
+            path = self.links.get(path)?;
+        }
+        None
+    }
+}
+
+fn is_icon_path(path: &str) -> bool {
+    path.starts_with(ICONS_DIR) || path.starts_with(PIXMAPS_DIR)
+}
+
+/// Whether `path` may be a file of the icon the desktop file names `icon`. Icons named
+/// by path are often links to a themed icon, so only their name is compared too
+fn is_icon(path: &str, icon: &str) -> bool {
+    let icon = match icon.rsplit_once('/') {
+        Some((_, file_name)) => file_name
+            .rsplit_once('.')
+            .map_or(file_name, |(name, _)| name),
+        None => icon,
+    };
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    file_name
+        .rsplit_once('.')
+        .is_some_and(|(name, extension)| name == icon && ICON_EXTENSIONS.contains(&extension))
+}
+
+/// The path of a member relative to the root, as archives write it `./usr/…` or `/usr/…`
+fn member_path(name: &str) -> &str {
+    name.trim_start_matches("./").trim_start_matches('/')
+}
+
+/// Resolves the target of a link in `directory`. Absolute targets start at the root
+fn resolve_link(directory: &str, target: &str) -> String {
+    let mut segments: Vec<&str> = match target.starts_with('/') {
+        true => Vec::new(),
+        false => directory.split('/').filter(|s| !s.is_empty()).collect(),
+    };
+    for segment in target.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    segments.join("/")
+}
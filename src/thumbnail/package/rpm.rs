@@ -0,0 +1,112 @@
+//! RPM packages: a lead, a signature and a header, then the installed files as a
+//! compressed cpio archive
+
+use std::io::{self, Read};
+
+use super::{member_path, Payload};
+use crate::archive::decompressed;
+use crate::backend::BackendError;
+
+const LEAD_MAGIC: [u8; 4] = [0xed, 0xab, 0xee, 0xdb];
+const LEAD_LENGTH: u64 = 96;
+const HEADER_MAGIC: [u8; 3] = [0x8e, 0xad, 0xe8];
+/// The `newc` format, with or without checksums
+const CPIO_MAGICS: [&[u8]; 2] = [b"070701", b"070702"];
+const CPIO_HEADER_LENGTH: usize = 110;
+const CPIO_TRAILER: &str = "TRAILER!!!";
+const FILE_TYPE_MASK: u32 = 0o170000;
+const REGULAR_FILE: u32 = 0o100000;
+const SYMLINK: u32 = 0o120000;
+/// Longer names and link targets are refused
+const MAX_NAME_LENGTH: u32 = 4096;
+
+pub(super) fn is_package(magic: &[u8]) -> bool {
+    magic.starts_with(&LEAD_MAGIC)
+}
+
+/// Streams the files of the package through `payload`
+pub(super) fn read_payload(
+    mut reader: impl Read,
+    payload: &mut Payload,
+) -> Result<(), BackendError> {
+    skip(&mut reader, LEAD_LENGTH)?;
+    // The signature is padded to a multiple of eight bytes, the header isn't
+    let signature = skip_header(&mut reader)?;
+    skip(&mut reader, (8 - signature % 8) % 8)?;
+    skip_header(&mut reader)?;
+    read_cpio(decompressed(reader)?, payload)
+}
+
+/// Skips a header structure: an index of tags and the data they point to. Returns its length
+fn skip_header(reader: &mut impl Read) -> Result<u64, BackendError> {
+    let mut intro = [0; 16];
+    reader.read_exact(&mut intro)?;
+    if intro[..3] != HEADER_MAGIC {
+        return Err("Malformed RPM header".into());
+    }
+    let entries = u32::from_be_bytes(intro[8..12].try_into()?) as u64;
+    let data_length = u32::from_be_bytes(intro[12..16].try_into()?) as u64;
+    let length = entries * 16 + data_length;
+    skip(reader, length)?;
+    Ok(intro.len() as u64 + length)
+}
+
+fn read_cpio(mut reader: impl Read, payload: &mut Payload) -> Result<(), BackendError> {
+    loop {
+        let mut header = [0; CPIO_HEADER_LENGTH];
+        reader.read_exact(&mut header)?;
+        if !CPIO_MAGICS.iter().any(|magic| header.starts_with(magic)) {
+            return Err("Unsupported RPM payload".into());
+        }
+        // Eight hexadecimal digits per field, after the magic
+        let field = |index: usize| -> Result<u32, BackendError> {
+            let digits = std::str::from_utf8(&header[6 + index * 8..][..8])?;
+            Ok(u32::from_str_radix(digits, 16)?)
+        };
+        let (mode, length, name_length) = (field(1)?, field(6)?, field(11)?);
+        if name_length > MAX_NAME_LENGTH {
+            return Err("Malformed RPM payload".into());
+        }
+        let mut name = vec![0; name_length as usize];
+        reader.read_exact(&mut name)?;
+        // The name and the data are each padded to four bytes
+        skip(
+            &mut reader,
+            padding(CPIO_HEADER_LENGTH as u64 + name_length as u64),
+        )?;
+        let name = String::from_utf8_lossy(&name);
+        let name = name.trim_end_matches('\0');
+        if name == CPIO_TRAILER {
+            return Ok(());
+        }
+
+        let path = member_path(name);
+        let length = length as u64;
+        match mode & FILE_TYPE_MASK {
+            SYMLINK if length <= MAX_NAME_LENGTH as u64 => {
+                let mut target = vec![0; length as usize];
+                reader.read_exact(&mut target)?;
+                payload.add_link(path, &String::from_utf8_lossy(&target));
+            }
+            REGULAR_FILE if payload.wants(path, length) => {
+                let mut data = vec![0; length as usize];
+                reader.read_exact(&mut data)?;
+                payload.add_file(path, data);
+            }
+            _ => skip(&mut reader, length)?,
+        }
+        skip(&mut reader, padding(length))?;
+    }
+}
+
+fn padding(length: u64) -> u64 {
+    (4 - length % 4) % 4
+}
+
+fn skip(reader: &mut impl Read, length: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut reader.take(length), &mut io::sink())?;
+    match skipped == length {
+        true => Ok(()),
+        false => Err(io::ErrorKind::UnexpectedEof.into()),
+    }
+}
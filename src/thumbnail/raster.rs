@@ -0,0 +1,186 @@
+use image::imageops::FilterType;
+use image::io::{Limits, Reader};
+use image::DynamicImage;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Seek, SeekFrom};
+use std::path::Path;
+
+use super::embedded::Previews;
+use super::{ReadSeek, Thumbnailer};
+use crate::backend::BackendError;
+use crate::image::Image;
+
+/// Largest image decoded by default, in pixels. A 100 megapixel photo still decodes,
+/// a tiny PNG that claims to be 100000 x 100000 does not
+pub(crate) const DEFAULT_MAX_PIXELS: u64 = 100_000_000;
+
+const MIME_TYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/bmp",
+    "image/tiff",
+];
+
+/// Previews photos and other raster images decoded with the `image` crate
+pub struct RasterThumbnailer {
+    max_pixels: u64,
+}
+
+impl RasterThumbnailer {
+    pub fn new() -> Self {
+        Self {
+            max_pixels: DEFAULT_MAX_PIXELS,
+        }
+    }
+
+    /// Refuses images with more than `max_pixels` pixels before decoding them,
+    /// so decompression bombs can't exhaust memory
+    pub fn with_max_pixels(mut self, max_pixels: u64) -> Self {
+        self.max_pixels = max_pixels;
+        self
+    }
+}
+
+impl Default for RasterThumbnailer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Thumbnailer for RasterThumbnailer {
+    fn supports(&self, mime_type: &str) -> bool {
+        MIME_TYPES.contains(&mime_type)
+    }
+
+    fn thumbnail(&self, path: &Path, size: u32) -> Result<Image, BackendError> {
+        self.thumbnail_reader(&mut BufReader::new(File::open(path)?), path, size)
+    }
+
+    fn thumbnail_reader(
+        &self,
+        reader: &mut dyn ReadSeek,
+        name: &Path,
+        size: u32,
+    ) -> Result<Image, BackendError> {
+        // Cameras embed a small JPEG in the EXIF data, which is much faster to decode
+        // than the photo itself. It is only used if it has enough pixels for `size`
+        if let Ok(mut previews) = Previews::read(&mut *reader) {
+            if let Some(preview) = previews.big_enough(size) {
+                match previews.decode(preview, size) {
+                    Ok(image) => return Ok(image.with_origin(name)),
+                    Err(error) => tracing::debug!(
+                        "Bad embedded preview in {}, decoding the image: {}",
+                        name.display(),
+                        error
+                    ),
+                }
+            }
+        }
+
+        // The header is read first, so the size is known before any pixels are allocated
+        let mut reader = BufReader::new(reader);
+        reader.seek(SeekFrom::Start(0))?;
+        let (width, height) = Reader::new(&mut reader)
+            .with_guessed_format()?
+            .into_dimensions()?;
+        if width as u64 * height as u64 > self.max_pixels {
+            return Err(format!(
+                "{} is {}x{} pixels, more than the limit of {}",
+                name.display(),
+                width,
+                height,
+                self.max_pixels
+            )
+            .into());
+        }
+
+        reader.seek(SeekFrom::Start(0))?;
+        let mut decoder = Reader::new(&mut reader).with_guessed_format()?;
+        decoder.limits(limits(width, height, self.max_pixels));
+        let decoded = decoder.decode()?;
+
+        reader.seek(SeekFrom::Start(0))?;
+        let orientation = exif_orientation(&mut reader);
+        let oriented = apply_orientation(downsample(decoded, size), orientation);
+        let rgba = oriented.to_rgba8();
+        let (width, height) = rgba.dimensions();
+        Ok(Image::from_rgba(rgba.into_raw(), width, height)
+            .with_origin(name)
+            .with_thumbnail(true))
+    }
+}
+
+/// Decodes an image held in memory, like cover art or an embedded preview. Images with
+/// more than `max_pixels` pixels are refused before any pixels are allocated
+pub(crate) fn decode_limited(data: &[u8], max_pixels: u64) -> Result<DynamicImage, BackendError> {
+    let (width, height) = Reader::new(Cursor::new(data))
+        .with_guessed_format()?
+        .into_dimensions()?;
+    if width as u64 * height as u64 > max_pixels {
+        return Err(format!(
+            "Embedded image is {}x{} pixels, more than the limit of {}",
+            width, height, max_pixels
+        )
+        .into());
+    }
+    let mut reader = Reader::new(Cursor::new(data)).with_guessed_format()?;
+    reader.limits(limits(width, height, max_pixels));
+    Ok(reader.decode()?)
+}
+
+/// Decoder limits for an image whose header says it is `width` x `height`
+fn limits(width: u32, height: u32, max_pixels: u64) -> Limits {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(width);
+    limits.max_image_height = Some(height);
+    // RGBA with 16 bits per channel is the largest buffer a decoder needs
+    limits.max_alloc = Some(max_pixels * 8);
+    limits
+}
+
+/// Shrinks `image` so its longest edge is at most `size`. A fast box filter does most of
+/// the reduction and Lanczos the last factor of two, which looks as good as Lanczos alone
+pub fn downsample(image: DynamicImage, size: u32) -> DynamicImage {
+    let (width, height) = (image.width(), image.height());
+    let longest = width.max(height);
+    if longest <= size {
+        return image;
+    }
+    let target_width = ((width as u64 * size as u64 / longest as u64) as u32).max(1);
+    let target_height = ((height as u64 * size as u64 / longest as u64) as u32).max(1);
+    let image = if longest > size * 2 {
+        image.thumbnail_exact(target_width * 2, target_height * 2)
+    } else {
+        image
+    };
+    image.resize_exact(target_width, target_height, FilterType::Lanczos3)
+}
+
+/// The EXIF `Orientation` of a photo, 1 (upright) when there is none
+pub fn exif_orientation(reader: &mut (impl BufRead + Seek)) -> u32 {
+    exif::Reader::new()
+        .read_from_container(reader)
+        .ok()
+        .and_then(|exif| {
+            exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+                .value
+                .get_uint(0)
+        })
+        .unwrap_or(1)
+}
+
+/// Turns an image stored with EXIF `orientation` upright
+pub fn apply_orientation(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
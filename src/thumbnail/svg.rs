@@ -0,0 +1,241 @@
+use flate2::read::GzDecoder;
+use parking_lot::Mutex;
+use resvg::usvg;
+use std::fs::File;
+use std::io::Read;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use super::{ReadSeek, Thumbnailer};
+use crate::backend::BackendError;
+use crate::decode::render_tree;
+use crate::image::Image;
+
+/// Documents larger than this, after decompressing SVGZ, aren't rendered
+const MAX_DOCUMENT_LENGTH: u64 = 16 * 1024 * 1024;
+const DEFAULT_MAX_NODES: u32 = 100_000;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+/// How many renders may run on past their timeout before new ones are refused
+const DEFAULT_MAX_ABANDONED: usize = 4;
+const CHECKER_LIGHT: [u8; 4] = [255, 255, 255, 255];
+const CHECKER_DARK: [u8; 4] = [204, 204, 204, 255];
+
+const MIME_TYPES: &[&str] = &["image/svg+xml", "image/svg+xml-compressed"];
+
+/// Renders of the whole process that timed out and haven't finished yet
+static ABANDONED: AtomicUsize = AtomicUsize::new(0);
+
+/// Previews SVG documents by rendering them. Unlike theme icons, documents come from
+/// anywhere, so nothing outside the file is loaded, element counts are capped and
+/// rendering is abandoned after a timeout. The document is centered on a square canvas
+/// of the requested size
+pub struct SvgThumbnailer {
+    max_nodes: u32,
+    timeout: Duration,
+    max_abandoned: usize,
+    checkerboard: bool,
+}
+
+impl SvgThumbnailer {
+    pub fn new() -> Self {
+        Self {
+            max_nodes: DEFAULT_MAX_NODES,
+            timeout: DEFAULT_TIMEOUT,
+            max_abandoned: DEFAULT_MAX_ABANDONED,
+            checkerboard: false,
+        }
+    }
+
+    /// Refuses documents with more than `max_nodes` XML nodes
+    pub fn with_max_nodes(mut self, max_nodes: u32) -> Self {
+        self.max_nodes = max_nodes;
+        self
+    }
+
+    /// Gives up on documents that take longer than `timeout` to render. The rendering
+    /// thread can't be stopped, so it finishes in the background
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Refuses to render while `max_abandoned` renders that timed out are still running,
+    /// so pathological documents can't pile up busy threads
+    pub fn with_max_abandoned(mut self, max_abandoned: usize) -> Self {
+        self.max_abandoned = max_abandoned;
+        self
+    }
+
+    /// Returns the number of renders in this process that timed out and are still running
+    pub fn abandoned(&self) -> usize {
+        ABANDONED.load(Ordering::Acquire)
+    }
+
+    /// Draws transparent areas over a checkerboard, as image editors do, instead of
+    /// leaving them transparent
+    pub fn with_checkerboard(mut self, checkerboard: bool) -> Self {
+        self.checkerboard = checkerboard;
+        self
+    }
+
+    /// Renders the SVG `data` within the limits, so its longest edge is `size`.
+    /// `path` names the document in errors
+    pub(crate) fn render_document(
+        &self,
+        data: String,
+        size: u32,
+        path: &Path,
+    ) -> Result<Image, BackendError> {
+        let abandoned = self.abandoned();
+        if abandoned >= self.max_abandoned {
+            return Err(format!(
+                "Not rendering {}: {} earlier renders are still running",
+                path.display(),
+                abandoned
+            )
+            .into());
+        }
+
+        // Set once the caller stops waiting, under the lock so that a result is either
+        // delivered or counted as abandoned, like in `Loader`
+        let abandoned = Arc::new(Mutex::new(false));
+        let thread_abandoned = Arc::clone(&abandoned);
+        let (max_nodes, checkerboard) = (self.max_nodes, self.checkerboard);
+        let (sender, receiver) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("getfileicon-svg".to_string())
+            .spawn(move || {
+                let render = || render(&data, size, max_nodes, checkerboard);
+                let result = panic::catch_unwind(AssertUnwindSafe(render))
+                    .unwrap_or_else(|_| Err("The renderer panicked".into()));
+                if *thread_abandoned.lock() {
+                    ABANDONED.fetch_sub(1, Ordering::AcqRel);
+                } else {
+                    _ = sender.send(result);
+                }
+            })?;
+
+        match receiver.recv_timeout(self.timeout) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                let mut abandoned = abandoned.lock();
+                // The result may have arrived while we were acquiring the lock
+                if let Ok(result) = receiver.try_recv() {
+                    return result;
+                }
+                *abandoned = true;
+                ABANDONED.fetch_add(1, Ordering::AcqRel);
+                Err(format!(
+                    "Rendering {} took longer than {:?}",
+                    path.display(),
+                    self.timeout
+                )
+                .into())
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err(format!("Rendering {} panicked", path.display()).into())
+            }
+        }
+    }
+}
+
+impl Default for SvgThumbnailer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Thumbnailer for SvgThumbnailer {
+    fn supports(&self, mime_type: &str) -> bool {
+        MIME_TYPES.contains(&mime_type)
+    }
+
+    fn thumbnail(&self, path: &Path, size: u32) -> Result<Image, BackendError> {
+        self.thumbnail_reader(&mut File::open(path)?, path, size)
+    }
+
+    fn thumbnail_reader(
+        &self,
+        reader: &mut dyn ReadSeek,
+        name: &Path,
+        size: u32,
+    ) -> Result<Image, BackendError> {
+        let image = self.render_document(read_document(reader, name)?, size, name)?;
+        Ok(image.with_origin(name).with_thumbnail(true))
+    }
+}
+
+/// Reads an SVG or SVGZ document, refusing ones that are too large
+fn read_document(reader: impl Read, name: &Path) -> Result<String, BackendError> {
+    let mut data = Vec::new();
+    reader.take(MAX_DOCUMENT_LENGTH + 1).read_to_end(&mut data)?;
+    if data.starts_with(&[0x1f, 0x8b]) {
+        let mut decompressed = Vec::new();
+        GzDecoder::new(data.as_slice())
+            .take(MAX_DOCUMENT_LENGTH + 1)
+            .read_to_end(&mut decompressed)?;
+        data = decompressed;
+    }
+    if data.len() as u64 > MAX_DOCUMENT_LENGTH {
+        return Err(format!("{} is too large to render", name.display()).into());
+    }
+    Ok(String::from_utf8(data)?)
+}
+
+/// Renders `text` so its longest edge is `size`, centered on a `size` x `size` canvas
+fn render(
+    text: &str,
+    size: u32,
+    max_nodes: u32,
+    checkerboard: bool,
+) -> Result<Image, BackendError> {
+    let document = usvg::roxmltree::Document::parse_with_options(
+        text,
+        usvg::roxmltree::ParsingOptions {
+            allow_dtd: true,
+            nodes_limit: max_nodes,
+        },
+    )?;
+    let tree = usvg::Tree::from_xmltree(&document, &safe_options())?;
+
+    let document_size = tree.size();
+    let longest = document_size.width().max(document_size.height());
+    let width = ((document_size.width() / longest * size as f32).round() as u32).max(1);
+    let height = ((document_size.height() / longest * size as f32).round() as u32).max(1);
+    let rendered = render_tree(&tree, width, height)?;
+    let size = size.max(1);
+    let mut canvas = if checkerboard {
+        checkerboard_image(size, size, (size / 16).max(2))
+    } else {
+        Image::from_rgba(vec![0; size as usize * size as usize * 4], size, size)
+    };
+    canvas.overlay(&rendered, (size - width) / 2, (size - height) / 2);
+    Ok(canvas)
+}
+
+/// Options that keep `<image>` elements from reading files: only images embedded as
+/// data URLs are shown. Without the `text` feature no fonts are loaded either
+fn safe_options() -> usvg::Options<'static> {
+    usvg::Options {
+        resources_dir: None,
+        image_href_resolver: usvg::ImageHrefResolver {
+            resolve_data: usvg::ImageHrefResolver::default_data_resolver(),
+            resolve_string: Box::new(|_, _| None),
+        },
+        ..usvg::Options::default()
+    }
+}
+
+fn checkerboard_image(width: u32, height: u32, square: u32) -> Image {
+    let pixels = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .flat_map(|(x, y)| match (x / square + y / square) % 2 {
+            0 => CHECKER_LIGHT,
+            _ => CHECKER_DARK,
+        })
+        .collect();
+    Image::from_rgba(pixels, width, height)
+}
@@ -0,0 +1,521 @@
+use flate2::read::ZlibDecoder;
+use std::io::Read;
+
+use crate::backend::BackendError;
+
+/// Fonts that decompress to more than this are refused
+const MAX_SFNT_LENGTH: u64 = 64 * 1024 * 1024;
+/// The most tables whose sfnt directory fields still fit in 16 bits
+const MAX_TABLES: u16 = 0x0fff;
+
+/// Tags WOFF2 encodes as an index into this table instead of spelling them out
+const KNOWN_TAGS: [&[u8; 4]; 63] = [
+    b"cmap", b"head", b"hhea", b"hmtx", b"maxp", b"name", b"OS/2", b"post", b"cvt ", b"fpgm",
+    b"glyf", b"loca", b"prep", b"CFF ", b"VORG", b"EBDT", b"EBLC", b"gasp", b"hdmx", b"kern",
+    b"LTSH", b"PCLT", b"VDMX", b"vhea", b"vmtx", b"BASE", b"GDEF", b"GPOS", b"GSUB", b"EBSC",
+    b"JSTF", b"MATH", b"CBDT", b"CBLC", b"COLR", b"CPAL", b"SVG ", b"sbix", b"acnt", b"avar",
+    b"bdat", b"bloc", b"bsln", b"cvar", b"fdsc", b"feat", b"fmtx", b"fvar", b"gvar", b"hsty",
+    b"just", b"lcar", b"mort", b"morx", b"opbd", b"prop", b"trak", b"Zapf", b"Silf", b"Glat",
+    b"Gloc", b"Feat", b"Sill",
+];
+
+/// Composite glyph flags that decide how many bytes a component takes
+const ARG_1_AND_2_ARE_WORDS: u16 = 0x0001;
+const WE_HAVE_A_SCALE: u16 = 0x0008;
+const MORE_COMPONENTS: u16 = 0x0020;
+const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+const WE_HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+const WE_HAVE_INSTRUCTIONS: u16 = 0x0100;
+
+/// Turns a WOFF or WOFF2 font into the TrueType or OpenType font it wraps, so font
+/// parsers can read it. Other data is returned unchanged
+pub(crate) fn to_sfnt(data: Vec<u8>) -> Result<Vec<u8>, BackendError> {
+    match data.get(..4) {
+        Some(b"wOFF") => decode_woff(&data).ok_or_else(|| "Malformed WOFF font".into()),
+        Some(b"wOF2") => decode_woff2(&data).ok_or_else(|| "Malformed WOFF2 font".into()),
+        _ => Ok(data),
+    }
+}
+
+/// Reads big endian integers and the variable length encodings of WOFF2
+struct Stream<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Stream<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn bytes(&mut self, length: usize) -> Option<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.position..self.position.checked_add(length)?)?;
+        self.position += length;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+
+    fn i16(&mut self) -> Option<i16> {
+        Some(self.u16()? as i16)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    /// `255UInt16`: one byte for small values, with codes for the larger ones
+    fn u16_255(&mut self) -> Option<u16> {
+        match self.u8()? {
+            253 => self.u16(),
+            254 => Some(self.u8()? as u16 + 506),
+            255 => Some(self.u8()? as u16 + 253),
+            code => Some(code as u16),
+        }
+    }
+
+    /// `UIntBase128`: seven bits per byte, most significant first, at most five bytes
+    fn base128(&mut self) -> Option<u32> {
+        let mut value: u32 = 0;
+        for index in 0..5 {
+            let byte = self.u8()?;
+            // Leading zeros and overflow make the encoding invalid
+            if (index == 0 && byte == 0x80) || value & 0xfe00_0000 != 0 {
+                return None;
+            }
+            value = (value << 7) | (byte & 0x7f) as u32;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+}
+
+struct Table {
+    tag: [u8; 4],
+    data: Vec<u8>,
+}
+
+/// WOFF 1.0 stores each table, zlib compressed when that made it smaller
+fn decode_woff(data: &[u8]) -> Option<Vec<u8>> {
+    let mut header = Stream::new(data);
+    header.bytes(4)?;
+    let flavor = header.u32()?;
+    header.u32()?;
+    let count = header.u16()?;
+    if count > MAX_TABLES {
+        return None;
+    }
+    header.position = 44;
+
+    let mut tables = Vec::new();
+    let mut total = 0;
+    for _ in 0..count {
+        let tag = header.bytes(4)?.try_into().ok()?;
+        let offset = header.u32()? as usize;
+        let compressed = header.u32()? as usize;
+        let original = header.u32()? as usize;
+        header.u32()?;
+        total += original as u64;
+        if total > MAX_SFNT_LENGTH {
+            return None;
+        }
+        let stored = data.get(offset..offset.checked_add(compressed)?)?;
+        let data = if compressed < original {
+            let mut table = Vec::with_capacity(original);
+            ZlibDecoder::new(stored)
+                .take(original as u64)
+                .read_to_end(&mut table)
+                .ok()?;
+            table
+        } else {
+            stored.to_vec()
+        };
+        if data.len() != original {
+            return None;
+        }
+        tables.push(Table { tag, data });
+    }
+    Some(build_sfnt(flavor, tables))
+}
+
+/// WOFF2 compresses all tables as one Brotli stream, after transforming `glyf`, `loca`
+/// and optionally `hmtx` into forms that compress better. Collections aren't supported
+fn decode_woff2(data: &[u8]) -> Option<Vec<u8>> {
+    struct Entry {
+        tag: [u8; 4],
+        length: usize,
+        transformed: bool,
+    }
+
+    let mut header = Stream::new(data);
+    header.bytes(4)?;
+    let flavor = header.u32()?;
+    if flavor == u32::from_be_bytes(*b"ttcf") {
+        return None;
+    }
+    header.u32()?;
+    let count = header.u16()?;
+    if count > MAX_TABLES {
+        return None;
+    }
+    header.u16()?;
+    header.u32()?;
+    let compressed_length = header.u32()? as usize;
+    header.position = 48;
+
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let flags = header.u8()?;
+        let tag = match flags & 0x3f {
+            63 => header.bytes(4)?.try_into().ok()?,
+            index => *KNOWN_TAGS[index as usize],
+        };
+        let original = header.base128()?;
+        // Transform version 0 means transformed for `glyf` and `loca`, untouched otherwise
+        let version = flags >> 6;
+        let transformed = match &tag {
+            b"glyf" | b"loca" => version == 0,
+            _ => version != 0,
+        };
+        let length = if transformed {
+            header.base128()?
+        } else {
+            original
+        };
+        entries.push(Entry {
+            tag,
+            length: length as usize,
+            transformed,
+        });
+    }
+
+    let total: u64 = entries.iter().map(|entry| entry.length as u64).sum();
+    if total > MAX_SFNT_LENGTH {
+        return None;
+    }
+    let compressed = header.bytes(compressed_length)?;
+    let mut decompressed = Vec::with_capacity(total as usize);
+    brotli_decompressor::Decompressor::new(compressed, 4096)
+        .take(total)
+        .read_to_end(&mut decompressed)
+        .ok()?;
+    if decompressed.len() as u64 != total {
+        return None;
+    }
+
+    let mut tables = Vec::new();
+    let mut glyphs = None;
+    let mut hmtx = None;
+    let mut stream = Stream::new(&decompressed);
+    for entry in &entries {
+        let data = stream.bytes(entry.length)?;
+        match (&entry.tag, entry.transformed) {
+            (_, false) => tables.push(Table {
+                tag: entry.tag,
+                data: data.to_vec(),
+            }),
+            (b"glyf", true) => glyphs = Some(reconstruct_glyf(data)?),
+            // Rebuilt along with `glyf`
+            (b"loca", true) => {}
+            (b"hmtx", true) => hmtx = Some(data),
+            _ => return None,
+        }
+    }
+    if let Some(glyphs) = glyphs {
+        if let Some(hmtx) = hmtx {
+            let hhea = tables.iter().find(|table| &table.tag == b"hhea")?;
+            let metrics = u16::from_be_bytes(hhea.data.get(34..36)?.try_into().ok()?);
+            tables.push(Table {
+                tag: *b"hmtx",
+                data: reconstruct_hmtx(hmtx, &glyphs.x_mins, metrics as usize)?,
+            });
+        }
+        tables.push(Table {
+            tag: *b"glyf",
+            data: glyphs.glyf,
+        });
+        tables.push(Table {
+            tag: *b"loca",
+            data: glyphs.loca,
+        });
+    } else if hmtx.is_some() {
+        // The `hmtx` transform takes side bearings from `glyf`
+        return None;
+    }
+    Some(build_sfnt(flavor, tables))
+}
+
+struct Glyphs {
+    glyf: Vec<u8>,
+    loca: Vec<u8>,
+    /// Left edge of each glyph, which transformed `hmtx` tables leave out
+    x_mins: Vec<i16>,
+}
+
+/// Rebuilds `glyf` and `loca` from the seven streams of the transformed `glyf` table
+fn reconstruct_glyf(data: &[u8]) -> Option<Glyphs> {
+    let mut header = Stream::new(data);
+    header.u16()?;
+    header.u16()?;
+    let glyph_count = header.u16()? as usize;
+    let index_format = header.u16()?;
+    let mut lengths = [0; 7];
+    for length in &mut lengths {
+        *length = header.u32()? as usize;
+    }
+    let mut contours = Stream::new(header.bytes(lengths[0])?);
+    let mut points = Stream::new(header.bytes(lengths[1])?);
+    let mut flags = Stream::new(header.bytes(lengths[2])?);
+    let mut coordinates = Stream::new(header.bytes(lengths[3])?);
+    let mut composites = Stream::new(header.bytes(lengths[4])?);
+    let mut boxes = Stream::new(header.bytes(lengths[5])?);
+    let mut instructions = Stream::new(header.bytes(lengths[6])?);
+    let box_bitmap = boxes.bytes(4 * glyph_count.div_ceil(32))?;
+
+    let mut glyf = Vec::new();
+    let mut offsets = Vec::with_capacity(glyph_count + 1);
+    let mut x_mins = vec![0; glyph_count];
+    for (index, x_min) in x_mins.iter_mut().enumerate() {
+        offsets.push(glyf.len());
+        let contour_count = contours.i16()?;
+        let has_box = box_bitmap[index / 8] & (0x80 >> (index % 8)) != 0;
+        let mut explicit_box = || -> Option<[i16; 4]> {
+            Some([boxes.i16()?, boxes.i16()?, boxes.i16()?, boxes.i16()?])
+        };
+
+        if contour_count == 0 {
+            if has_box {
+                return None;
+            }
+        } else if contour_count == -1 {
+            // Components are stored as in `glyf`, only their size has to be worked out
+            let start = composites.position;
+            let mut has_instructions = false;
+            loop {
+                let flags = composites.u16()?;
+                composites.u16()?;
+                let arguments = if flags & ARG_1_AND_2_ARE_WORDS != 0 {
+                    4
+                } else {
+                    2
+                };
+                let transform = if flags & WE_HAVE_A_SCALE != 0 {
+                    2
+                } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+                    4
+                } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+                    8
+                } else {
+                    0
+                };
+                composites.bytes(arguments + transform)?;
+                has_instructions |= flags & WE_HAVE_INSTRUCTIONS != 0;
+                if flags & MORE_COMPONENTS == 0 {
+                    break;
+                }
+            }
+            // Composite glyphs always store their bounding box
+            let bounds = if has_box {
+                explicit_box()?
+            } else {
+                return None;
+            };
+            *x_min = bounds[0];
+            glyf.extend(contour_count.to_be_bytes());
+            glyf.extend(bounds.iter().flat_map(|value| value.to_be_bytes()));
+            glyf.extend(&composites.data[start..composites.position]);
+            if has_instructions {
+                let length = coordinates.u16_255()?;
+                glyf.extend(length.to_be_bytes());
+                glyf.extend(instructions.bytes(length as usize)?);
+            }
+        } else if contour_count > 0 {
+            let mut end_points = Vec::with_capacity(contour_count as usize);
+            let mut point_count: u32 = 0;
+            for _ in 0..contour_count {
+                point_count += points.u16_255()? as u32;
+                end_points.push(u16::try_from(point_count.checked_sub(1)?).ok()?);
+            }
+            let mut on_curve = Vec::with_capacity(point_count as usize);
+            let mut deltas = Vec::with_capacity(point_count as usize);
+            let (mut x, mut y) = (0, 0);
+            let mut bounds = [i32::MAX, i32::MAX, i32::MIN, i32::MIN];
+            for _ in 0..point_count {
+                let flag = flags.u8()?;
+                let (dx, dy) = triplet(flag & 0x7f, &mut coordinates)?;
+                on_curve.push(flag & 0x80 == 0);
+                deltas.push((i16::try_from(dx).ok()?, i16::try_from(dy).ok()?));
+                x += dx;
+                y += dy;
+                bounds = [
+                    bounds[0].min(x),
+                    bounds[1].min(y),
+                    bounds[2].max(x),
+                    bounds[3].max(y),
+                ];
+            }
+            let instruction_length = coordinates.u16_255()?;
+            let code = instructions.bytes(instruction_length as usize)?;
+            let bounds = match has_box {
+                true => explicit_box()?,
+                false => bounds.map(|value| value.clamp(i16::MIN as i32, i16::MAX as i32) as i16),
+            };
+            *x_min = bounds[0];
+
+            glyf.extend(contour_count.to_be_bytes());
+            glyf.extend(bounds.iter().flat_map(|value| value.to_be_bytes()));
+            glyf.extend(end_points.iter().flat_map(|value| value.to_be_bytes()));
+            glyf.extend(instruction_length.to_be_bytes());
+            glyf.extend(code);
+            // Every coordinate is written as a 16-bit delta, which needs no flags but on-curve
+            glyf.extend(on_curve.iter().map(|&on| on as u8));
+            glyf.extend(deltas.iter().flat_map(|(dx, _)| dx.to_be_bytes()));
+            glyf.extend(deltas.iter().flat_map(|(_, dy)| dy.to_be_bytes()));
+        } else {
+            return None;
+        }
+        glyf.resize(glyf.len().next_multiple_of(4), 0);
+    }
+    offsets.push(glyf.len());
+
+    let loca = match index_format {
+        0 => offsets
+            .iter()
+            .map(|&offset| u16::try_from(offset / 2).ok().map(u16::to_be_bytes))
+            .collect::<Option<Vec<_>>>()?
+            .concat(),
+        _ => offsets
+            .iter()
+            .map(|&offset| u32::try_from(offset).ok().map(u32::to_be_bytes))
+            .collect::<Option<Vec<_>>>()?
+            .concat(),
+    };
+    Some(Glyphs { glyf, loca, x_mins })
+}
+
+/// Decodes a point of a simple glyph, stored as a flag and one to four bytes.
+/// Returns the offset from the previous point
+fn triplet(flag: u8, stream: &mut Stream) -> Option<(i32, i32)> {
+    // The lowest bit of the flag (shifted for y) tells whether the value is positive
+    let signed = |flag: u8, value: i32| if flag & 1 != 0 { value } else { -value };
+    let flag_bits = flag as i32;
+    Some(match flag {
+        0..=9 => (
+            0,
+            signed(flag, ((flag_bits & 14) << 7) + stream.u8()? as i32),
+        ),
+        10..=19 => (
+            signed(flag, (((flag_bits - 10) & 14) << 7) + stream.u8()? as i32),
+            0,
+        ),
+        20..=83 => {
+            let base = flag_bits - 20;
+            let byte = stream.u8()? as i32;
+            (
+                signed(flag, 1 + (base & 0x30) + (byte >> 4)),
+                signed(flag >> 1, 1 + ((base & 0x0c) << 2) + (byte & 0x0f)),
+            )
+        }
+        84..=119 => {
+            let base = flag_bits - 84;
+            let (x, y) = (stream.u8()? as i32, stream.u8()? as i32);
+            (
+                signed(flag, 1 + ((base / 12) << 8) + x),
+                signed(flag >> 1, 1 + (((base % 12) >> 2) << 8) + y),
+            )
+        }
+        120..=123 => {
+            let bytes = stream.bytes(3)?;
+            let (first, second, third) = (bytes[0] as i32, bytes[1] as i32, bytes[2] as i32);
+            (
+                signed(flag, (first << 4) + (second >> 4)),
+                signed(flag >> 1, ((second & 0x0f) << 8) + third),
+            )
+        }
+        _ => {
+            let bytes = stream.bytes(4)?;
+            (
+                signed(flag, i32::from(u16::from_be_bytes([bytes[0], bytes[1]]))),
+                signed(
+                    flag >> 1,
+                    i32::from(u16::from_be_bytes([bytes[2], bytes[3]])),
+                ),
+            )
+        }
+    })
+}
+
+/// Rebuilds `hmtx`, whose left side bearings may have been dropped because they
+/// equal the glyphs' `xMin`
+fn reconstruct_hmtx(data: &[u8], x_mins: &[i16], metric_count: usize) -> Option<Vec<u8>> {
+    if metric_count == 0 || metric_count > x_mins.len() {
+        return None;
+    }
+    let mut stream = Stream::new(data);
+    let flags = stream.u8()?;
+    let advances = (0..metric_count)
+        .map(|_| stream.u16())
+        .collect::<Option<Vec<_>>>()?;
+    let mut bearings = |range: std::ops::Range<usize>, dropped: bool| match dropped {
+        true => Some(x_mins[range].to_vec()),
+        false => range.map(|_| stream.i16()).collect::<Option<Vec<_>>>(),
+    };
+    let proportional = bearings(0..metric_count, flags & 1 != 0)?;
+    let monospaced = bearings(metric_count..x_mins.len(), flags & 2 != 0)?;
+
+    let mut hmtx = Vec::with_capacity(metric_count * 4 + monospaced.len() * 2);
+    for (advance, bearing) in advances.iter().zip(&proportional) {
+        hmtx.extend(advance.to_be_bytes());
+        hmtx.extend(bearing.to_be_bytes());
+    }
+    hmtx.extend(monospaced.iter().flat_map(|bearing| bearing.to_be_bytes()));
+    Some(hmtx)
+}
+
+/// Lays out `tables` as an sfnt font file, sorted by tag as the format requires. There
+/// must be at most `MAX_TABLES` of them
+fn build_sfnt(flavor: u32, mut tables: Vec<Table>) -> Vec<u8> {
+    tables.sort_by_key(|table| table.tag);
+    let count = tables.len() as u16;
+    let selector = count.max(1).ilog2() as u16;
+    let search_range = (1u16 << selector) * 16;
+
+    let mut font = flavor.to_be_bytes().to_vec();
+    font.extend(count.to_be_bytes());
+    font.extend(search_range.to_be_bytes());
+    font.extend(selector.to_be_bytes());
+    font.extend((count * 16).saturating_sub(search_range).to_be_bytes());
+
+    let mut offset = 12 + tables.len() * 16;
+    for table in &tables {
+        let checksum = table
+            .data
+            .chunks(4)
+            .map(|chunk| {
+                let mut word = [0; 4];
+                word[..chunk.len()].copy_from_slice(chunk);
+                u32::from_be_bytes(word)
+            })
+            .fold(0u32, u32::wrapping_add);
+        font.extend(table.tag);
+        font.extend(checksum.to_be_bytes());
+        font.extend((offset as u32).to_be_bytes());
+        font.extend((table.data.len() as u32).to_be_bytes());
+        offset += table.data.len().next_multiple_of(4);
+    }
+    for table in &tables {
+        font.extend(&table.data);
+        font.resize(font.len().next_multiple_of(4), 0);
+    }
+    font
+}